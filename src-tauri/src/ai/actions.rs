@@ -0,0 +1,504 @@
+// Action parsing - extracts structured actions from AI responses
+//
+// Two formats are recognised:
+//   * inline markers:      [action:type:target:value]
+//   * fenced JSON blocks:  ```json {"actions":[{"type":"execute","system":"ris",...}]} ```
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use tracing::warn;
+
+use super::citations::REF_PREFIX;
+use super::AiAction;
+
+const INLINE_PREFIX: &str = "[action:";
+const FENCE: &str = "```";
+
+/// Supported action types
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionType {
+    Click,
+    Input,
+    Navigate,
+    Search,
+    Select,
+    Scroll,
+    Submit,
+    Download,
+    Execute,
+    Extract,
+}
+
+impl ActionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionType::Click => "click",
+            ActionType::Input => "input",
+            ActionType::Navigate => "navigate",
+            ActionType::Search => "search",
+            ActionType::Select => "select",
+            ActionType::Scroll => "scroll",
+            ActionType::Submit => "submit",
+            ActionType::Download => "download",
+            ActionType::Execute => "execute",
+            ActionType::Extract => "extract",
+        }
+    }
+
+    /// Whether the action needs a value (e.g. text to type)
+    fn requires_value(&self) -> bool {
+        matches!(self, ActionType::Input | ActionType::Search)
+    }
+}
+
+impl fmt::Display for ActionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ActionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "click" => Ok(ActionType::Click),
+            "input" | "type" | "fill" => Ok(ActionType::Input),
+            "navigate" | "goto" => Ok(ActionType::Navigate),
+            "search" => Ok(ActionType::Search),
+            "select" => Ok(ActionType::Select),
+            "scroll" => Ok(ActionType::Scroll),
+            "submit" => Ok(ActionType::Submit),
+            "download" => Ok(ActionType::Download),
+            "execute" => Ok(ActionType::Execute),
+            "extract" => Ok(ActionType::Extract),
+            other => Err(format!("Unknown action type: {}", other)),
+        }
+    }
+}
+
+/// Diagnostic for an action entry that was rejected
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionDiagnostic {
+    pub snippet: String,
+    pub message: String,
+}
+
+/// Result of parsing an AI response
+#[derive(Debug, Clone, Default)]
+pub struct ParsedResponse {
+    /// Response text with all action markup removed
+    pub content: String,
+    pub actions: Vec<AiAction>,
+    pub diagnostics: Vec<ActionDiagnostic>,
+}
+
+/// Parse inline markers and fenced JSON action blocks, in order of appearance
+pub fn parse(raw: &str) -> ParsedResponse {
+    let mut parsed = ParsedResponse::default();
+    let mut stripped = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    loop {
+        let inline_pos = rest.find(INLINE_PREFIX);
+        let fence_pos = rest.find(FENCE);
+
+        let next = match (inline_pos, fence_pos) {
+            (Some(i), Some(f)) => Some(i.min(f)),
+            (i, f) => i.or(f),
+        };
+        let Some(pos) = next else {
+            stripped.push_str(rest);
+            break;
+        };
+
+        stripped.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+
+        if Some(pos) == inline_pos {
+            match marker_end(tail) {
+                Some(end) => {
+                    let marker = &tail[..=end];
+                    match parse_inline(&marker[INLINE_PREFIX.len()..end]) {
+                        Ok(action) => parsed.actions.push(action),
+                        Err(message) => push_diagnostic(&mut parsed, marker, message),
+                    }
+                    rest = join_cut(&mut stripped, &tail[end + 1..]);
+                }
+                None => {
+                    // Unterminated marker, keep the text as-is
+                    push_diagnostic(&mut parsed, tail, "Unterminated action marker".to_string());
+                    stripped.push_str(tail);
+                    break;
+                }
+            }
+        } else {
+            let Some((block, consumed)) = split_fence(tail) else {
+                // Unclosed fence, keep it as text and look for markers after it
+                stripped.push_str(FENCE);
+                rest = &tail[FENCE.len()..];
+                continue;
+            };
+            if parse_block(&block, &mut parsed) {
+                rest = join_cut(&mut stripped, &tail[consumed..]);
+            } else {
+                // Not an action block, leave it in the content
+                stripped.push_str(&tail[..consumed]);
+                rest = &tail[consumed..];
+            }
+        }
+    }
+
+    parsed.content = stripped;
+    parsed
+}
+
+/// Incremental filter that hides action markup and citation markers from streamed text
+///
+/// Text that could start a marker or fence is held back until the markup is
+/// complete; action markup and citations are dropped, anything else is
+/// released unchanged.
+#[derive(Debug, Default)]
+pub struct MarkupFilter {
+    pending: String,
+}
+
+impl MarkupFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a raw delta, returning the text that is safe to display
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+        let mut visible = String::new();
+
+        loop {
+            let start = [
+                self.pending.find(INLINE_PREFIX),
+                self.pending.find(REF_PREFIX),
+                self.pending.find(FENCE),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+
+            let Some(pos) = start else {
+                let keep = partial_markup_len(&self.pending);
+                let cut = self.pending.len() - keep;
+                visible.push_str(&self.pending[..cut]);
+                self.pending.drain(..cut);
+                return visible;
+            };
+
+            visible.push_str(&self.pending[..pos]);
+            self.pending.drain(..pos);
+
+            let end = if self.pending.starts_with(INLINE_PREFIX) || self.pending.starts_with(REF_PREFIX) {
+                marker_end(&self.pending).map(|i| i + 1)
+            } else {
+                split_fence(&self.pending).map(|(_, consumed)| consumed)
+            };
+            let Some(end) = end else {
+                return visible;
+            };
+
+            let markup: String = self.pending.drain(..end).collect();
+            // Citations are resolved from the complete answer
+            if !markup.starts_with(REF_PREFIX) {
+                visible.push_str(&parse(&markup).content);
+            }
+        }
+    }
+
+    /// Release whatever is still held back once the stream has ended
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        parse(&rest).content
+    }
+}
+
+/// Length of a trailing fragment that may be the start of a marker or fence
+fn partial_markup_len(text: &str) -> usize {
+    [INLINE_PREFIX, REF_PREFIX, FENCE]
+        .iter()
+        .flat_map(|m| (1..m.len()).rev().filter(move |&k| text.ends_with(&m[..k])))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Fenced block: language tag, body and total length including fences
+struct FencedBlock<'a> {
+    lang: &'a str,
+    body: &'a str,
+}
+
+fn split_fence(text: &str) -> Option<(FencedBlock<'_>, usize)> {
+    let after_open = &text[FENCE.len()..];
+    let header_end = after_open.find('\n')?;
+    let lang = after_open[..header_end].trim();
+    let body_start = FENCE.len() + header_end + 1;
+    let close = text[body_start..].find(FENCE)?;
+    let body = &text[body_start..body_start + close];
+    Some((FencedBlock { lang, body }, body_start + close + FENCE.len()))
+}
+
+/// Returns true if the block was consumed as an action block
+fn parse_block(block: &FencedBlock<'_>, parsed: &mut ParsedResponse) -> bool {
+    let tagged = matches!(block.lang.to_lowercase().as_str(), "action" | "actions");
+    if !tagged && !block.lang.eq_ignore_ascii_case("json") && !block.lang.is_empty() {
+        return false;
+    }
+
+    let value: Value = match serde_json::from_str(block.body.trim()) {
+        Ok(v) => v,
+        Err(e) => {
+            if tagged {
+                push_diagnostic(parsed, block.body, format!("Invalid JSON in action block: {}", e));
+            }
+            return tagged;
+        }
+    };
+
+    let entries = match &value {
+        Value::Object(obj) => match obj.get("actions") {
+            Some(Value::Array(items)) => items.clone(),
+            Some(_) => {
+                push_diagnostic(parsed, block.body, "\"actions\" must be an array".to_string());
+                return true;
+            }
+            None if tagged => vec![value.clone()],
+            None => return false,
+        },
+        Value::Array(items) if tagged => items.clone(),
+        _ => {
+            if tagged {
+                push_diagnostic(parsed, block.body, "Action block must be an object or array".to_string());
+            }
+            return tagged;
+        }
+    };
+
+    for entry in entries {
+        match parse_json_action(&entry) {
+            Ok(action) => parsed.actions.push(action),
+            Err(message) => push_diagnostic(parsed, &entry.to_string(), message),
+        }
+    }
+    true
+}
+
+/// Parse the body of an inline marker: `type:target[:value]`
+fn parse_inline(body: &str) -> Result<AiAction, String> {
+    let mut parts = body.splitn(3, ':');
+    let action_type: ActionType = parts.next().unwrap_or_default().parse()?;
+    let mut target = parts.next().unwrap_or_default().trim().to_string();
+    let mut value = parts.next().map(|v| v.trim().to_string());
+
+    // `[action:navigate:https://host/path]` splits the URL scheme off the target
+    if matches!(target.as_str(), "http" | "https") {
+        if let Some(v) = value.take() {
+            if let Some((rest, val)) = v.strip_prefix("//").map(split_url_value) {
+                target = format!("{}://{}", target, rest);
+                value = val;
+            } else {
+                value = Some(v);
+            }
+        }
+    }
+
+    if target.is_empty() {
+        return Err(format!("Action '{}' is missing a target", action_type));
+    }
+    let value = value.filter(|v| !v.is_empty());
+    if action_type.requires_value() && value.is_none() {
+        return Err(format!("Action '{}' is missing a value", action_type));
+    }
+
+    Ok(AiAction {
+        action_type,
+        target,
+        value,
+        system: None,
+        params: Map::new(),
+        risk: None,
+    })
+}
+
+/// Split `host:port/path:value` after a URL scheme, keeping ports attached to the URL
+fn split_url_value(s: &str) -> (String, Option<String>) {
+    let path_start = s.find('/').unwrap_or(s.len());
+    match s[path_start..].find(':') {
+        Some(i) => {
+            let cut = path_start + i;
+            (s[..cut].to_string(), Some(s[cut + 1..].trim().to_string()))
+        }
+        None => (s.to_string(), None),
+    }
+}
+
+/// Parse a single entry of a JSON action block
+pub(crate) fn parse_json_action(entry: &Value) -> Result<AiAction, String> {
+    let obj = entry.as_object().ok_or("Action entry must be an object")?;
+
+    let action_type: ActionType = obj
+        .get("type")
+        .and_then(Value::as_str)
+        .ok_or("Action entry is missing \"type\"")?
+        .parse()?;
+
+    let system = string_field(obj, "system");
+    let value = string_field(obj, "value");
+
+    let mut params = match obj.get("params") {
+        Some(Value::Object(p)) => p.clone(),
+        Some(Value::Null) | None => Map::new(),
+        Some(_) => return Err("\"params\" must be an object".to_string()),
+    };
+
+    let target = match action_type {
+        ActionType::Execute => {
+            if system.is_none() {
+                return Err("Execute action is missing \"system\"".to_string());
+            }
+            string_field(obj, "capability").or_else(|| string_field(obj, "action"))
+        }
+        ActionType::Extract => {
+            if let Some(from) = string_field(obj, "from") {
+                params.insert("from".to_string(), Value::String(from));
+            }
+            string_field(obj, "field").or_else(|| string_field(obj, "target"))
+        }
+        _ => string_field(obj, "target").or_else(|| string_field(obj, "selector")),
+    };
+
+    let target = target.ok_or_else(|| format!("Action '{}' is missing a target", action_type))?;
+    if action_type.requires_value() && value.is_none() {
+        return Err(format!("Action '{}' is missing a value", action_type));
+    }
+
+    Ok(AiAction {
+        action_type,
+        target,
+        value,
+        system,
+        params,
+        risk: None,
+    })
+}
+
+fn string_field(obj: &Map<String, Value>, key: &str) -> Option<String> {
+    match obj.get(key) {
+        Some(Value::String(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn push_diagnostic(parsed: &mut ParsedResponse, snippet: &str, message: String) {
+    let snippet: String = snippet.chars().take(200).collect();
+    warn!("Rejected AI action: {} ({})", message, snippet);
+    parsed.diagnostics.push(ActionDiagnostic { snippet, message });
+}
+
+/// Index of the `]` closing the marker at the start of `text`, skipping nested brackets
+fn marker_end(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Join the text around removed markup, returning the rest to parse
+///
+/// Whitespace on both sides of the cut is merged: line breaks are kept up to
+/// one blank line, otherwise a single space survives. Text away from the cut
+/// is left as it is.
+fn join_cut<'a>(stripped: &mut String, rest: &'a str) -> &'a str {
+    let kept = stripped.trim_end().len();
+    let before = &stripped[kept..];
+    let after = &rest[..rest.len() - rest.trim_start().len()];
+    let newlines = (before.matches('\n').count() + after.matches('\n').count()).min(2);
+    let spaced = !before.is_empty() || !after.is_empty();
+    stripped.truncate(kept);
+
+    let rest = rest.trim_start();
+    if !stripped.is_empty() && !rest.is_empty() {
+        if newlines > 0 {
+            stripped.push_str(&"\n".repeat(newlines));
+        } else if spaced {
+            stripped.push(' ');
+        }
+    }
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fenced_json_actions() {
+        let raw = "正在查询。\n\n```json\n{\"actions\":[{\"type\":\"execute\",\"system\":\"ris\",\"capability\":\"query.patient\",\"params\":{\"id\":\"P1\"}}]}\n```\n\n请稍候。";
+        let parsed = parse(raw);
+        assert_eq!(parsed.content, "正在查询。\n\n请稍候。");
+        assert_eq!(parsed.actions.len(), 1);
+        let action = &parsed.actions[0];
+        assert_eq!(action.action_type, ActionType::Execute);
+        assert_eq!(action.system.as_deref(), Some("ris"));
+        assert_eq!(action.target, "query.patient");
+        assert_eq!(action.params["id"], "P1");
+    }
+
+    #[test]
+    fn parses_inline_markers() {
+        let parsed = parse("点击[action:click:#submit]按钮，然后 [action:navigate:https://ris.local:8080/list] 打开列表");
+        assert_eq!(parsed.content, "点击按钮，然后 打开列表");
+        assert_eq!(parsed.actions.len(), 2);
+        assert_eq!(parsed.actions[0].target, "#submit");
+        assert_eq!(parsed.actions[1].action_type, ActionType::Navigate);
+        assert_eq!(parsed.actions[1].target, "https://ris.local:8080/list");
+    }
+
+    #[test]
+    fn inline_value_may_contain_brackets() {
+        let parsed = parse("[action:input:#name:张三[复查]] 已填写");
+        assert_eq!(parsed.content, "已填写");
+        assert_eq!(parsed.actions[0].value.as_deref(), Some("张三[复查]"));
+    }
+
+    #[test]
+    fn unclosed_fence_does_not_hide_later_markers() {
+        let parsed = parse("示例：```json\n{\"a\":1}\n[action:click:#ok]");
+        assert_eq!(parsed.actions.len(), 1);
+        assert_eq!(parsed.actions[0].target, "#ok");
+        assert!(parsed.content.starts_with("示例：```json\n{\"a\":1}"));
+    }
+
+    #[test]
+    fn text_without_markup_is_unchanged() {
+        let raw = "代码如下：\n\n```rust\nfn main() {   \n\n\n    run();\n}\n```\n\n\n结束  ";
+        let parsed = parse(raw);
+        assert!(parsed.actions.is_empty());
+        assert_eq!(parsed.content, raw);
+    }
+
+    #[test]
+    fn invalid_entries_become_diagnostics() {
+        let parsed = parse("[action:input:#q]```action\n{\"type\":\"fly\"}\n```");
+        assert!(parsed.actions.is_empty());
+        assert_eq!(parsed.diagnostics.len(), 2);
+    }
+}
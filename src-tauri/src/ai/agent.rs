@@ -0,0 +1,645 @@
+// Agent loop - executes AI actions and feeds the results back to the model
+//
+// Follows the action execution protocol of the architecture doc:
+// receive actions -> execute in order -> collect results -> return to AI.
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use super::capabilities;
+use super::context::PageSnapshot;
+use super::prompts;
+use super::session::{
+    append_messages, conversation_client, history_window, load_conversation, page_context, plan_turn,
+    session_messages, turn_context, TurnPlan,
+};
+use super::{AiAction, AiClient, AiMessage, AiRequest, AiResponse, AiState, TOOL_ROLE};
+use crate::auth::AuthState;
+use crate::browser::BrowserState;
+use crate::config::AppConfig;
+use crate::core::security::RiskLevel;
+use crate::permissions;
+use crate::storage::Database;
+
+/// Event asking the frontend bridge to execute an action
+///
+/// Actions carry their risk assessment; the bridge shows a preview or asks
+/// for confirmation according to `risk.execution_mode` before executing.
+pub const ACTION_EVENT: &str = "ai-action";
+
+/// Agent loop limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentConfig {
+    /// Maximum number of model round trips per user message
+    pub max_steps: u32,
+    /// Wall-clock budget for the whole run, including action execution
+    pub time_budget_secs: u64,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: 5,
+            time_budget_secs: 120,
+        }
+    }
+}
+
+/// Outcome of a single executed action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionOutcome {
+    pub action: AiAction,
+    pub success: bool,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// One model round trip and the actions it produced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentStep {
+    pub step: u32,
+    pub content: String,
+    pub outcomes: Vec<ActionOutcome>,
+    pub elapsed_ms: u64,
+}
+
+/// Why the loop stopped
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The model answered without requesting actions
+    Answered,
+    StepLimit,
+    TimeBudget,
+}
+
+/// Result of an agent run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentRun {
+    pub response: AiResponse,
+    pub steps: Vec<AgentStep>,
+    pub stop_reason: StopReason,
+}
+
+/// Executes actions proposed by the model
+#[async_trait]
+pub trait ActionExecutor: Send + Sync {
+    async fn execute(&self, action: &AiAction) -> Result<Value, String>;
+}
+
+/// Drives the chat -> execute -> report cycle
+pub struct Agent<'a> {
+    client: &'a AiClient,
+    executor: &'a dyn ActionExecutor,
+    config: AgentConfig,
+    user_id: Option<String>,
+    /// Audit log for the steps, opened once per run
+    audit: Option<Mutex<Database>>,
+}
+
+impl<'a> Agent<'a> {
+    pub fn new(
+        client: &'a AiClient,
+        executor: &'a dyn ActionExecutor,
+        config: AgentConfig,
+        user_id: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            executor,
+            config,
+            user_id,
+            audit: None,
+        }
+    }
+
+    /// Record every step in the audit log of `db`
+    pub fn with_audit_log(mut self, db: Database) -> Self {
+        self.audit = Some(Mutex::new(db));
+        self
+    }
+
+    /// Run until the model answers in plain text or a limit is reached
+    pub async fn run(&self, request: AiRequest) -> Result<AgentRun, String> {
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(self.config.time_budget_secs);
+        let mut messages = request.messages;
+        let mut steps = Vec::new();
+        let mut last_response: Option<AiResponse> = None;
+
+        for step in 1..=self.config.max_steps.max(1) {
+            let step_started = Instant::now();
+            let chat_request = AiRequest {
+                messages: messages.clone(),
+                context: request.context.clone(),
+                tools: request.tools.clone(),
+            };
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let response = match tokio::time::timeout(remaining, self.client.chat(chat_request)).await {
+                Ok(response) => response?,
+                Err(_) => return Ok(self.finish(last_response, steps, StopReason::TimeBudget)),
+            };
+
+            if response.actions.is_empty() {
+                self.audit_step(step, &response.content, &[], step_started);
+                return Ok(AgentRun {
+                    response,
+                    steps,
+                    stop_reason: StopReason::Answered,
+                });
+            }
+
+            let mut outcomes = Vec::with_capacity(response.actions.len());
+            for action in &response.actions {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let result = match tokio::time::timeout(remaining, self.executor.execute(action)).await {
+                    Ok(result) => result,
+                    Err(_) => Err("执行超时".to_string()),
+                };
+                outcomes.push(match result {
+                    Ok(value) => ActionOutcome {
+                        action: action.clone(),
+                        success: true,
+                        result: Some(value),
+                        error: None,
+                    },
+                    Err(e) => {
+                        warn!("Action {} on {} failed: {}", action.action_type, action.target, e);
+                        ActionOutcome {
+                            action: action.clone(),
+                            success: false,
+                            result: None,
+                            error: Some(e),
+                        }
+                    }
+                });
+            }
+
+            self.audit_step(step, &response.content, &outcomes, step_started);
+
+            messages.push(AiMessage {
+                role: "assistant".to_string(),
+                content: response.content.clone(),
+            });
+            messages.push(tool_result_message(&outcomes));
+            steps.push(AgentStep {
+                step,
+                content: response.content.clone(),
+                outcomes,
+                elapsed_ms: step_started.elapsed().as_millis() as u64,
+            });
+            last_response = Some(response);
+
+            if Instant::now() >= deadline {
+                return Ok(self.finish(last_response, steps, StopReason::TimeBudget));
+            }
+        }
+
+        Ok(self.finish(last_response, steps, StopReason::StepLimit))
+    }
+
+    fn finish(
+        &self,
+        last_response: Option<AiResponse>,
+        steps: Vec<AgentStep>,
+        stop_reason: StopReason,
+    ) -> AgentRun {
+        info!("Agent stopped after {} steps: {:?}", steps.len(), stop_reason);
+        let notice = match stop_reason {
+            StopReason::TimeBudget => "已达到处理时间上限，操作未全部完成。",
+            _ => "已达到最大执行步数，操作未全部完成。",
+        };
+        let mut response = last_response.unwrap_or_else(|| AiResponse::text(String::new()));
+        // Actions of the last step were already executed
+        response.actions.clear();
+        response.content = if response.content.is_empty() {
+            notice.to_string()
+        } else {
+            format!("{}\n\n{}", response.content, notice)
+        };
+        AgentRun {
+            response,
+            steps,
+            stop_reason,
+        }
+    }
+
+    fn audit_step(&self, step: u32, content: &str, outcomes: &[ActionOutcome], started: Instant) {
+        let Some(db) = &self.audit else {
+            return;
+        };
+        let risk = outcomes
+            .iter()
+            .filter_map(|o| o.action.risk.as_ref().map(|r| r.level))
+            .max()
+            .unwrap_or(RiskLevel::Low);
+
+        let details = json!({
+            "step": step,
+            "ai_response": content,
+            "executed_actions": outcomes.iter().map(|o| &o.action).collect::<Vec<_>>(),
+            "action_results": outcomes.iter().map(|o| json!({
+                "success": o.success,
+                "result": o.result,
+                "error": o.error,
+            })).collect::<Vec<_>>(),
+            "duration_ms": started.elapsed().as_millis() as u64,
+        });
+
+        let db = db.lock().unwrap_or_else(|e| e.into_inner());
+        let risk = format!("{:?}", risk);
+        if let Err(e) = db.log_audit(self.user_id.as_deref(), "ai_agent_step", &details.to_string(), &risk) {
+            warn!("Failed to write agent audit log: {}", e);
+        }
+    }
+}
+
+/// Message reporting action results back to the model
+fn tool_result_message(outcomes: &[ActionOutcome]) -> AiMessage {
+    let results: Vec<Value> = outcomes
+        .iter()
+        .map(|o| {
+            json!({
+                "type": o.action.action_type,
+                "target": o.action.target,
+                "success": o.success,
+                "result": o.result,
+                "error": o.error,
+            })
+        })
+        .collect();
+
+    AiMessage {
+        role: TOOL_ROLE.to_string(),
+        content: json!({ "results": results }).to_string(),
+    }
+}
+
+/// Result reported by the frontend bridge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionResult {
+    pub success: bool,
+    pub result: Option<Value>,
+    pub error: Option<String>,
+}
+
+/// Payload of the `ai-action` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ActionRequest<'a> {
+    execution_id: String,
+    action: &'a AiAction,
+}
+
+/// Pending frontend executions keyed by execution id
+#[derive(Default)]
+pub struct PendingActions {
+    senders: Mutex<HashMap<String, oneshot::Sender<ActionResult>>>,
+}
+
+impl PendingActions {
+    /// Deliver a result reported by the frontend
+    pub fn complete(&self, execution_id: &str, result: ActionResult) -> Result<(), String> {
+        let sender = self.senders
+            .lock()
+            .map_err(|e| e.to_string())?
+            .remove(execution_id)
+            .ok_or_else(|| format!("Unknown execution: {}", execution_id))?;
+        sender.send(result).map_err(|_| "Agent is no longer waiting for this action".to_string())
+    }
+
+    /// Start waiting for the result of an execution
+    ///
+    /// The execution stays registered until the returned guard is dropped,
+    /// including when the agent stops waiting because its time budget ran out.
+    fn wait(&self) -> Result<(Waiting<'_>, oneshot::Receiver<ActionResult>), String> {
+        let execution_id = uuid::Uuid::new_v4().to_string();
+        let (tx, rx) = oneshot::channel();
+        self.senders
+            .lock()
+            .map_err(|e| e.to_string())?
+            .insert(execution_id.clone(), tx);
+        Ok((Waiting { pending: self, execution_id }, rx))
+    }
+}
+
+/// Registration of an execution in `PendingActions`, removed on drop
+struct Waiting<'a> {
+    pending: &'a PendingActions,
+    execution_id: String,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut senders) = self.pending.senders.lock() {
+            senders.remove(&self.execution_id);
+        }
+    }
+}
+
+/// Executes actions in the page through the frontend bridge
+pub struct FrontendExecutor {
+    app: AppHandle,
+    pending: Arc<PendingActions>,
+}
+
+impl FrontendExecutor {
+    pub fn new(app: AppHandle, pending: Arc<PendingActions>) -> Self {
+        Self { app, pending }
+    }
+}
+
+#[async_trait]
+impl ActionExecutor for FrontendExecutor {
+    async fn execute(&self, action: &AiAction) -> Result<Value, String> {
+        permissions::authorize_action(&self.app.state::<AuthState>(), "ai_run_agent", action)?;
+        let (waiting, rx) = self.pending.wait()?;
+
+        let request = ActionRequest {
+            execution_id: waiting.execution_id.clone(),
+            action,
+        };
+        self.app.emit(ACTION_EVENT, request).map_err(|e| e.to_string())?;
+
+        // When the agent times out, this future is dropped and `waiting`
+        // unregisters the execution, so a late report is rejected
+        let result = rx.await.map_err(|_| "Action execution was abandoned".to_string())?;
+        if result.success {
+            Ok(result.result.unwrap_or(Value::Null))
+        } else {
+            Err(result.error.unwrap_or_else(|| "Action failed".to_string()))
+        }
+    }
+}
+
+/// Tauri commands for the agent loop
+
+#[tauri::command]
+pub async fn ai_run_agent(
+    app: AppHandle,
+    state: tauri::State<'_, AiState>,
+    auth: tauri::State<'_, AuthState>,
+    browser: tauri::State<'_, BrowserState>,
+    session_id: String,
+    message: String,
+    page: Option<PageSnapshot>,
+) -> Result<AgentRun, String> {
+    let user = auth.require_user()?;
+    let _turn = state.lock_turn(&session_id).await;
+    let mut session = load_conversation(&auth, &session_id)?;
+    let mut messages = session_messages(&session)?;
+    let user_message = AiMessage {
+        role: "user".to_string(),
+        content: message,
+    };
+    messages.push(user_message.clone());
+
+    let config = AppConfig::load();
+    let page = page_context(&browser, &config.business_systems, &user, page)?;
+    let confirmed = match plan_turn(&mut session, &user_message.content, &config.business_systems, page.as_ref())? {
+        TurnPlan::Reply(reply) => {
+            let answer = AiMessage {
+                role: "assistant".to_string(),
+                content: reply.clone(),
+            };
+            append_messages(&session_id, &[user_message, answer])?;
+            return Ok(AgentRun {
+                response: AiResponse::text(reply),
+                steps: Vec::new(),
+                stop_reason: StopReason::Answered,
+            });
+        }
+        TurnPlan::Model { confirmed } => confirmed,
+    };
+    let client = conversation_client(&state, &config.ai, &session, page.as_ref());
+    let mut messages = history_window(&client, &config.ai, &mut session, &messages).await?;
+    messages.insert(0, prompts::system_prompt(&config.business_systems, page.as_ref()).message);
+    let executor = FrontendExecutor::new(app, state.pending_actions.clone());
+    let mut agent = Agent::new(&client, &executor, config.ai.agent, Some(user.id));
+    match Database::open_default() {
+        Ok(db) => agent = agent.with_audit_log(db),
+        Err(e) => warn!("Agent steps will not be audited: {}", e),
+    }
+
+    let context = turn_context(page.as_ref(), confirmed);
+    let run = agent
+        .run(AiRequest {
+            messages,
+            context,
+            tools: capabilities::tools(),
+        })
+        .await?;
+
+    let answer = AiMessage {
+        role: "assistant".to_string(),
+        content: run.response.content.clone(),
+    };
+    append_messages(&session_id, &[user_message, answer])?;
+    Ok(run)
+}
+
+#[tauri::command]
+pub fn ai_report_action_result(
+    state: tauri::State<AiState>,
+    execution_id: String,
+    result: ActionResult,
+) -> Result<(), String> {
+    state.pending_actions.complete(&execution_id, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::{AiProvider, DeltaSink};
+    use tokio::sync::Notify;
+
+    /// Model answering from a script and keeping the requests it got
+    struct Model {
+        answers: Mutex<Vec<&'static str>>,
+        requests: Arc<Mutex<Vec<AiRequest>>>,
+    }
+
+    #[async_trait]
+    impl AiProvider for Model {
+        fn name(&self) -> &'static str {
+            "Model"
+        }
+
+        async fn complete(&self, request: &AiRequest) -> Result<String, String> {
+            self.requests.lock().unwrap().push(request.clone());
+            let mut answers = self.answers.lock().unwrap();
+            // The last answer repeats
+            Ok(if answers.len() > 1 { answers.remove(0) } else { answers[0] }.to_string())
+        }
+
+        async fn complete_stream(
+            &self,
+            request: &AiRequest,
+            _cancel: &Notify,
+            _on_delta: DeltaSink<'_>,
+        ) -> Result<String, String> {
+            self.complete(request).await
+        }
+    }
+
+    fn model(answers: &[&'static str]) -> (AiClient, Arc<Mutex<Vec<AiRequest>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let provider = Model {
+            answers: Mutex::new(answers.to_vec()),
+            requests: requests.clone(),
+        };
+        (AiClient::with_provider(Box::new(provider)), requests)
+    }
+
+    /// Executor failing on targets containing "fail" and never finishing on "hang"
+    #[derive(Default)]
+    struct Executor {
+        targets: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl ActionExecutor for Executor {
+        async fn execute(&self, action: &AiAction) -> Result<Value, String> {
+            self.targets.lock().unwrap().push(action.target.clone());
+            if action.target.contains("hang") {
+                std::future::pending::<()>().await;
+            }
+            if action.target.contains("fail") {
+                return Err("元素不存在".to_string());
+            }
+            Ok(json!({"clicked": action.target}))
+        }
+    }
+
+    fn limits(max_steps: u32, time_budget_secs: u64) -> AgentConfig {
+        AgentConfig {
+            max_steps,
+            time_budget_secs,
+        }
+    }
+
+    fn ask(message: &str) -> AiRequest {
+        AiRequest {
+            messages: vec![AiMessage {
+                role: "user".to_string(),
+                content: message.to_string(),
+            }],
+            context: None,
+            tools: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn action_results_are_fed_back_to_the_model() {
+        let (client, requests) = model(&["查询中[action:click:#search][action:click:#fail]", "找到 3 条记录"]);
+        let executor = Executor::default();
+        let agent = Agent::new(&client, &executor, limits(5, 60), None);
+
+        let run = agent.run(ask("查一下今天的检查")).await.unwrap();
+
+        assert_eq!(run.stop_reason, StopReason::Answered);
+        assert_eq!(run.response.content, "找到 3 条记录");
+        assert_eq!(run.steps.len(), 1);
+        assert!(run.steps[0].outcomes[0].success);
+        assert_eq!(run.steps[0].outcomes[1].error.as_deref(), Some("元素不存在"));
+
+        let requests = requests.lock().unwrap();
+        let followup = &requests[1].messages;
+        assert_eq!(followup.len(), 3);
+        assert_eq!(followup[1].role, "assistant");
+        assert_eq!(followup[2].role, TOOL_ROLE);
+        let results: Value = serde_json::from_str(&followup[2].content).unwrap();
+        assert_eq!(results["results"][0]["result"], json!({"clicked": "#search"}));
+        assert_eq!(results["results"][1]["success"], false);
+        assert_eq!(results["results"][1]["error"], "元素不存在");
+    }
+
+    #[tokio::test]
+    async fn run_stops_at_the_step_limit() {
+        let (client, requests) = model(&["[action:click:#next]"]);
+        let executor = Executor::default();
+        let agent = Agent::new(&client, &executor, limits(2, 60), None);
+
+        let run = agent.run(ask("一直点下一页")).await.unwrap();
+
+        assert_eq!(run.stop_reason, StopReason::StepLimit);
+        assert_eq!(run.steps.len(), 2);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(executor.targets.lock().unwrap().len(), 2);
+        // Executed actions are not handed out again
+        assert!(run.response.actions.is_empty());
+        assert!(run.response.content.contains("最大执行步数"));
+    }
+
+    #[tokio::test]
+    async fn run_stops_when_the_time_budget_runs_out() {
+        let (client, requests) = model(&["[action:click:#hang]", "不应到达"]);
+        let executor = Executor::default();
+        let agent = Agent::new(&client, &executor, limits(5, 1), None);
+
+        let started = Instant::now();
+        let run = agent.run(ask("打开报告")).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(run.stop_reason, StopReason::TimeBudget);
+        assert_eq!(run.steps[0].outcomes[0].error.as_deref(), Some("执行超时"));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert!(run.response.content.contains("处理时间上限"));
+    }
+
+    #[tokio::test]
+    async fn every_step_is_audited() {
+        let db = Database::new(":memory:").unwrap();
+        db.init_schema().unwrap();
+        let (client, _) = model(&["[action:click:#search]", "完成"]);
+        let executor = Executor::default();
+        let agent = Agent::new(&client, &executor, limits(5, 60), Some("u1".to_string())).with_audit_log(db);
+
+        agent.run(ask("查询")).await.unwrap();
+
+        let db = agent.audit.unwrap().into_inner().unwrap();
+        let query = "SELECT COUNT(*) FROM audit_logs WHERE action = 'ai_agent_step' AND user_id = 'u1'";
+        let logged: i64 = db.conn.query_row(query, [], |row| row.get(0)).unwrap();
+        assert_eq!(logged, 2);
+    }
+
+    fn reported(success: bool) -> ActionResult {
+        ActionResult {
+            success,
+            result: Some(json!({"ok": success})),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reported_result_reaches_the_waiting_agent() {
+        let pending = PendingActions::default();
+        let (waiting, rx) = pending.wait().unwrap();
+
+        pending.complete(&waiting.execution_id, reported(true)).unwrap();
+        assert!(rx.await.unwrap().success);
+        assert!(pending.complete(&waiting.execution_id, reported(true)).is_err());
+    }
+
+    #[tokio::test]
+    async fn timed_out_execution_is_unregistered() {
+        let pending = PendingActions::default();
+        let execution_id = {
+            let (waiting, rx) = pending.wait().unwrap();
+            let waited = tokio::time::timeout(Duration::from_millis(10), rx).await;
+            assert!(waited.is_err());
+            waiting.execution_id.clone()
+        };
+
+        assert!(pending.senders.lock().unwrap().is_empty());
+        let late = pending.complete(&execution_id, reported(true));
+        assert_eq!(late, Err(format!("Unknown execution: {}", execution_id)));
+    }
+}
@@ -0,0 +1,235 @@
+// Answer cache - reuses answers to repeated informational questions
+//
+// Only questions classified as plain chat are cached, never requests that
+// lead to actions. Questions or answers that contain patient data, and
+// questions that refer to the current page, are never stored: cached
+// answers are shared by all users with the same role in a business system.
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::intent::{Intent, IntentKind};
+use super::redact::Redactor;
+use super::AiResponse;
+use crate::auth::AuthState;
+use crate::permissions::Permission;
+use crate::storage::{CachedAnswer, Database};
+
+/// Words that make a question depend on the page or the conversation
+const DEICTIC_WORDS: &[&str] = &[
+    "这个", "这位", "这里", "这页", "这张", "当前", "本页", "上面", "刚才", "那个", "那位",
+];
+
+/// Answer cache settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub max_entries: u32,
+    /// Longer answers are not cached
+    pub max_answer_chars: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 24 * 3600,
+            max_entries: 500,
+            max_answer_chars: 2000,
+        }
+    }
+}
+
+/// Lowercase, without whitespace and punctuation, so "EIS怎么打印报告？" and
+/// "eis 怎么打印报告" share an entry
+pub fn normalize_question(question: &str) -> String {
+    question
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_ascii_punctuation() && !is_cjk_punctuation(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303F}' | '\u{FF01}'..='\u{FF0F}' | '\u{FF1A}'..='\u{FF20}')
+}
+
+/// Cache entry a turn may read and fill
+#[derive(Debug, Clone)]
+pub struct CacheSlot {
+    pub key: String,
+    pub system: Option<String>,
+    pub prompt_version: String,
+}
+
+/// Local cache of answers in the `ai_answer_cache` table
+pub struct AnswerCache {
+    config: CacheConfig,
+}
+
+impl AnswerCache {
+    pub fn new(config: &CacheConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// Cache entry for a question, `None` when its answer must not be cached
+    ///
+    /// Entries are keyed by role, as the system prompt and what the model may
+    /// suggest differ between roles.
+    pub fn slot(
+        &self,
+        question: &str,
+        intent: &Intent,
+        role: &str,
+        system: Option<&str>,
+        prompt_version: &str,
+        redactor: &Redactor,
+    ) -> Option<CacheSlot> {
+        if !self.config.enabled || intent.kind != IntentKind::Chat {
+            return None;
+        }
+        if DEICTIC_WORDS.iter().any(|w| question.contains(w)) || redactor.redact(question) != question {
+            return None;
+        }
+        let normalized = normalize_question(question);
+        if normalized.is_empty() {
+            return None;
+        }
+        Some(CacheSlot {
+            key: format!("{}|{}|{}|{}", role, system.unwrap_or("-"), prompt_version, normalized),
+            system: system.map(str::to_string),
+            prompt_version: prompt_version.to_string(),
+        })
+    }
+
+    /// Cached answer for a slot; cache errors count as a miss
+    pub fn get(&self, slot: &CacheSlot) -> Option<AiResponse> {
+        let since = cutoff(self.config.ttl_secs);
+        let answer = Database::open_default()
+            .and_then(|db| db.cached_answer(&slot.key, &since))
+            .unwrap_or_else(|e| {
+                warn!("Answer cache lookup failed: {}", e);
+                None
+            })?;
+        info!("Answer cache hit: {}", slot.key);
+        Some(AiResponse::text(answer))
+    }
+
+    /// Store an answer unless it carries actions, patient data or is too long
+    pub fn put(&self, slot: &CacheSlot, user_id: &str, response: &AiResponse, redactor: &Redactor) {
+        if !response.actions.is_empty() || !response.diagnostics.is_empty() {
+            return;
+        }
+        let answer = response.content.trim();
+        if answer.is_empty()
+            || answer.chars().count() > self.config.max_answer_chars
+            || redactor.redact(answer) != answer
+        {
+            return;
+        }
+
+        let entry = CachedAnswer {
+            key: slot.key.clone(),
+            user_id: user_id.to_string(),
+            system: slot.system.clone(),
+            prompt_version: slot.prompt_version.clone(),
+            answer: answer.to_string(),
+        };
+        let result = Database::open_default().and_then(|db| {
+            db.store_answer(&entry)?;
+            db.trim_answer_cache(&cutoff(self.config.ttl_secs), self.config.max_entries)
+        });
+        if let Err(e) = result {
+            warn!("Failed to cache answer: {}", e);
+        }
+    }
+}
+
+fn cutoff(ttl_secs: u64) -> String {
+    let ttl = chrono::Duration::from_std(Duration::from_secs(ttl_secs)).unwrap_or_default();
+    (chrono::Utc::now() - ttl).to_rfc3339()
+}
+
+/// Tauri commands for the answer cache
+
+#[tauri::command]
+pub fn ai_clear_answer_cache(auth: tauri::State<AuthState>, user_id: Option<String>) -> Result<usize, String> {
+    // Users may drop their own answers; anything wider is an admin task
+    let user = auth.require_user()?;
+    if user_id.as_deref() != Some(user.id.as_str()) {
+        auth.authorize("ai_clear_answer_cache", None, Permission::Admin)?;
+    }
+    let removed = Database::open_default()?.clear_answer_cache(user_id.as_deref())?;
+    info!("Cleared {} cached answers", removed);
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::intent::IntentSource;
+    use crate::ai::redact::RedactionConfig;
+
+    fn intent(kind: IntentKind) -> Intent {
+        Intent {
+            kind,
+            slots: Default::default(),
+            confidence: 0.3,
+            source: IntentSource::Rules,
+        }
+    }
+
+    fn key(question: &str, role: &str, redactor: &Redactor) -> Option<String> {
+        AnswerCache::new(&CacheConfig::default())
+            .slot(question, &intent(IntentKind::Chat), role, Some("eis"), "v1", redactor)
+            .map(|slot| slot.key)
+    }
+
+    #[test]
+    fn questions_differing_in_case_and_punctuation_share_an_entry() {
+        let redactor = Redactor::new(&RedactionConfig::default());
+        let first = key("EIS怎么打印报告？", "doctor", &redactor).unwrap();
+        assert_eq!(first, "doctor|eis|v1|eis怎么打印报告");
+        assert_eq!(key("eis 怎么打印报告", "doctor", &redactor), Some(first));
+    }
+
+    #[test]
+    fn entries_are_not_shared_across_roles() {
+        let redactor = Redactor::new(&RedactionConfig::default());
+        let doctor = key("怎么审核报告", "doctor", &redactor);
+        let nurse = key("怎么审核报告", "nurse", &redactor);
+        assert!(doctor.is_some() && nurse.is_some());
+        assert_ne!(doctor, nurse);
+    }
+
+    #[test]
+    fn patient_data_and_page_references_are_not_cached() {
+        let redactor = Redactor::new(&RedactionConfig::default());
+        assert_eq!(key("13812345678是哪个科室的电话", "doctor", &redactor), None);
+        assert_eq!(key("病历号：MR20240001怎么查", "doctor", &redactor), None);
+        assert_eq!(key("这个报告怎么打印", "doctor", &redactor), None);
+        assert_eq!(key("刚才那个检查要多久", "doctor", &redactor), None);
+        assert_eq!(key("？？", "doctor", &redactor), None);
+
+        // A name the conversation already knows is PHI even without a label
+        redactor.add_known("patient_name", "张三");
+        assert_eq!(key("张三的报告好了吗", "doctor", &redactor), None);
+    }
+
+    #[test]
+    fn only_plain_chat_is_cached() {
+        let redactor = Redactor::new(&RedactionConfig::default());
+        let cache = AnswerCache::new(&CacheConfig::default());
+        let search = intent(IntentKind::SearchPatient);
+        assert!(cache.slot("查一下CT", &search, "doctor", None, "v1", &redactor).is_none());
+
+        let disabled = AnswerCache::new(&CacheConfig {
+            enabled: false,
+            ..CacheConfig::default()
+        });
+        assert!(disabled.slot("怎么审核报告", &intent(IntentKind::Chat), "doctor", None, "v1", &redactor).is_none());
+    }
+}
@@ -0,0 +1,640 @@
+// Capabilities - business system functions the model may call
+//
+// Capabilities follow the standard of the architecture doc: ids are named
+// "{system}.{category}.{action}" and arguments are described by a JSON
+// schema. They are published by the gateway or listed in capabilities.json
+// in the config directory; a gateway entry wins over a local one with the
+// same id. The registry turns them into function-calling tools and checks
+// the arguments of `execute` actions before anything is executed.
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use tracing::{error, info, warn};
+
+use super::actions::{ActionDiagnostic, ActionType, ParsedResponse};
+use super::AiAction;
+use crate::auth::AuthState;
+use crate::config::AppConfig;
+use crate::permissions::Permission;
+
+/// Stands in for "." in tool names, which providers do not accept
+const TOOL_NAME_SEPARATOR: &str = "__";
+
+/// A function published by a business system
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capability {
+    /// "{system}.{category}.{action}", e.g. "ris.query.patient"
+    pub id: String,
+    /// Business system, taken from the id when unset
+    #[serde(default)]
+    pub system: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// JSON schema of the arguments, no arguments when unset
+    #[serde(default)]
+    pub input_schema: Value,
+    #[serde(default)]
+    pub output_schema: Value,
+    /// Permission the caller needs, e.g. "ris:read"
+    #[serde(default)]
+    pub auth_required: Option<String>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default = "default_true", alias = "is_enabled")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Capability {
+    /// Check the id and schema, filling in the system from the id
+    fn normalized(mut self) -> Result<Self, String> {
+        static ID: OnceLock<Regex> = OnceLock::new();
+        let id = ID.get_or_init(|| {
+            Regex::new(r"^[a-z][a-z0-9_]*(\.[a-z][a-z0-9_]*){2,}$").expect("valid capability id pattern")
+        });
+        if !id.is_match(&self.id) || self.id.contains(TOOL_NAME_SEPARATOR) {
+            return Err(format!("能力 id 不符合 {{system}}.{{category}}.{{action}} 命名规范: {}", self.id));
+        }
+
+        let prefix = self.id.split('.').next().unwrap_or_default();
+        if self.system.is_empty() {
+            self.system = prefix.to_string();
+        } else if self.system != prefix {
+            return Err(format!("能力 {} 不属于系统 {}", self.id, self.system));
+        }
+
+        match &self.input_schema {
+            Value::Null => {}
+            Value::Object(schema) => {
+                if schema.get("type").is_some_and(|t| t != "object") {
+                    return Err(format!("能力 {} 的 input_schema 必须是 object 类型", self.id));
+                }
+            }
+            _ => return Err(format!("能力 {} 的 input_schema 必须是 JSON 对象", self.id)),
+        }
+        Ok(self)
+    }
+
+    /// Argument schema, an empty object schema when none was published
+    pub fn parameters(&self) -> Value {
+        match &self.input_schema {
+            Value::Object(schema) if !schema.is_empty() => {
+                let mut schema = schema.clone();
+                schema.entry("type").or_insert_with(|| json!("object"));
+                Value::Object(schema)
+            }
+            _ => json!({ "type": "object", "properties": {} }),
+        }
+    }
+
+    /// Function name used in tool definitions
+    pub fn tool_name(&self) -> String {
+        self.id.replace('.', TOOL_NAME_SEPARATOR)
+    }
+
+    pub fn to_tool(&self) -> ToolDefinition {
+        let description = if self.description.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}：{}", self.name, self.description)
+        };
+        ToolDefinition {
+            name: self.tool_name(),
+            description,
+            parameters: self.parameters(),
+        }
+    }
+
+    /// Check arguments against the input schema
+    pub fn validate_arguments(&self, args: &Map<String, Value>) -> Result<(), String> {
+        let mut errors = Vec::new();
+        check_schema(&self.parameters(), &Value::Object(args.clone()), "params", &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+/// Capability id for a name produced by `Capability::tool_name`
+pub fn capability_id(tool_name: &str) -> String {
+    tool_name.replace(TOOL_NAME_SEPARATOR, ".")
+}
+
+/// Function-calling tool offered to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments
+    pub parameters: Value,
+}
+
+/// Capabilities of one business system, as sent to the gateway's register endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityManifest {
+    pub system: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    pub capabilities: Vec<Capability>,
+}
+
+/// Contents of capabilities.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub manifests: Vec<CapabilityManifest>,
+}
+
+/// Known capabilities plus what is needed to notice manifest changes
+#[derive(Debug)]
+pub struct CapabilityRegistry {
+    /// From the local manifest file
+    local: Vec<Capability>,
+    /// Last list fetched from the gateway
+    gateway: Vec<Capability>,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl CapabilityRegistry {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            local: Vec::new(),
+            gateway: Vec::new(),
+            path,
+            modified: None,
+        }
+    }
+
+    /// Reload the manifest file if it changed since the last load
+    pub fn refresh(&mut self) {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        if modified.is_none() {
+            self.local.clear();
+            return;
+        }
+        if let Err(e) = self.reload() {
+            error!("{}", e);
+        }
+    }
+
+    /// Load and validate the manifest file, keeping the current entries on failure
+    pub fn reload(&mut self) -> Result<(), String> {
+        let capabilities = load_manifests(&self.path)?;
+        info!("Loaded {} capabilities from {}", capabilities.len(), self.path.display());
+        self.local = capabilities;
+        Ok(())
+    }
+
+    /// Replace the capabilities published by the gateway, skipping invalid entries
+    pub fn set_gateway(&mut self, capabilities: &[Capability]) {
+        self.gateway = capabilities
+            .iter()
+            .cloned()
+            .filter_map(|c| match c.normalized() {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    warn!("Ignoring gateway capability: {}", e);
+                    None
+                }
+            })
+            .collect();
+        info!("Registered {} gateway capabilities", self.gateway.len());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.local.is_empty() && self.gateway.is_empty()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Capability> {
+        self.gateway.iter().chain(&self.local).find(|c| c.id == id)
+    }
+
+    /// All capabilities ordered by id, gateway entries replacing local ones
+    pub fn capabilities(&self) -> Vec<Capability> {
+        let mut merged = BTreeMap::new();
+        for capability in self.local.iter().chain(&self.gateway) {
+            merged.insert(capability.id.clone(), capability.clone());
+        }
+        merged.into_values().collect()
+    }
+
+    /// Tool definitions of all enabled capabilities
+    pub fn tools(&self) -> Vec<ToolDefinition> {
+        self.capabilities().iter().filter(|c| c.enabled).map(Capability::to_tool).collect()
+    }
+
+    /// Capability an `execute` action calls, with its arguments checked
+    ///
+    /// The target may be the full id or the id without the system prefix.
+    pub fn resolve(&self, action: &AiAction) -> Result<&Capability, String> {
+        let capability = self
+            .get(&action.target)
+            .or_else(|| {
+                let system = action.system.as_deref()?;
+                self.get(&format!("{}.{}", system, action.target))
+            })
+            .ok_or_else(|| format!("Unknown capability: {}", action.target))?;
+        if !capability.enabled {
+            return Err(format!("Capability {} is disabled", capability.id));
+        }
+        if action.system.as_deref().is_some_and(|s| s != capability.system) {
+            return Err(format!(
+                "Capability {} does not belong to system {}",
+                capability.id,
+                action.system.as_deref().unwrap_or_default()
+            ));
+        }
+        capability
+            .validate_arguments(&action.params)
+            .map_err(|e| format!("Invalid arguments for {}: {}", capability.id, e))?;
+        Ok(capability)
+    }
+}
+
+impl CapabilityRegistry {
+    /// Move `execute` actions this registry cannot resolve into the diagnostics
+    pub fn check_actions(&self, parsed: &mut ParsedResponse) {
+        for mut action in std::mem::take(&mut parsed.actions) {
+            if action.action_type != ActionType::Execute {
+                parsed.actions.push(action);
+                continue;
+            }
+            match self.resolve(&action) {
+                Ok(capability) => {
+                    action.target = capability.id.clone();
+                    action.system = Some(capability.system.clone());
+                    parsed.actions.push(action);
+                }
+                Err(message) => {
+                    let snippet: String =
+                        serde_json::to_string(&action).unwrap_or_default().chars().take(200).collect();
+                    warn!("Rejected AI action: {} ({})", message, snippet);
+                    parsed.diagnostics.push(ActionDiagnostic { snippet, message });
+                }
+            }
+        }
+    }
+}
+
+fn load_manifests(path: &Path) -> Result<Vec<Capability>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("无法读取能力清单 {}: {}", path.display(), e))?;
+    let file: ManifestFile = serde_json::from_str(&content)
+        .map_err(|e| format!("能力清单格式错误 {}: {}", path.display(), e))?;
+
+    let mut ids = HashSet::new();
+    let mut capabilities = Vec::new();
+    for manifest in file.manifests {
+        for mut capability in manifest.capabilities {
+            if capability.system.is_empty() {
+                capability.system = manifest.system.clone();
+            }
+            let capability = capability
+                .normalized()
+                .map_err(|e| format!("能力清单无效 {}: {}", path.display(), e))?;
+            if !ids.insert(capability.id.clone()) {
+                return Err(format!("能力清单无效 {}: 能力 id 重复: {}", path.display(), capability.id));
+            }
+            capabilities.push(capability);
+        }
+    }
+    Ok(capabilities)
+}
+
+/// Check `value` against the supported subset of JSON schema:
+/// type, enum, required, properties, additionalProperties, items,
+/// minLength/maxLength, minimum/maximum and pattern
+fn check_schema(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        return;
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            errors.push(format!("{}: expected {}, got {}", path, types.join(" or "), type_name(value)));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: {} is not one of {}", path, value, Value::Array(allowed.clone())));
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for name in required.iter().filter_map(Value::as_str) {
+                    if map.get(name).is_none_or(Value::is_null) {
+                        errors.push(format!("{}.{}: required", path, name));
+                    }
+                }
+            }
+            for (name, item) in map {
+                let item_path = format!("{}.{}", path, name);
+                match properties.and_then(|p| p.get(name)) {
+                    Some(item_schema) => check_schema(item_schema, item, &item_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => errors.push(format!("{}: unexpected property", item_path)),
+                        Some(extra @ Value::Object(_)) => check_schema(extra, item, &item_path, errors),
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check_schema(item_schema, item, &format!("{}.{}", path, i), errors);
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if schema.get("minLength").and_then(Value::as_u64).is_some_and(|min| len < min) {
+                errors.push(format!("{}: shorter than minLength", path));
+            }
+            if schema.get("maxLength").and_then(Value::as_u64).is_some_and(|max| len > max) {
+                errors.push(format!("{}: longer than maxLength", path));
+            }
+            if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+                match Regex::new(pattern) {
+                    Ok(re) if !re.is_match(s) => errors.push(format!("{}: does not match {}", path, pattern)),
+                    Ok(_) => {}
+                    Err(_) => warn!("Ignoring invalid schema pattern at {}: {}", path, pattern),
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if schema.get("minimum").and_then(Value::as_f64).is_some_and(|min| n < min) {
+                errors.push(format!("{}: less than minimum", path));
+            }
+            if schema.get("maximum").and_then(Value::as_f64).is_some_and(|max| n > max) {
+                errors.push(format!("{}: greater than maximum", path));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "integer" => value.as_i64().is_some() || value.as_u64().is_some(),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn manifest_path() -> PathBuf {
+    AppConfig::config_dir().join("capabilities.json")
+}
+
+fn registry() -> &'static Mutex<CapabilityRegistry> {
+    static REGISTRY: OnceLock<Mutex<CapabilityRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(CapabilityRegistry::new(manifest_path())))
+}
+
+/// Tool definitions to offer the model
+pub fn tools() -> Vec<ToolDefinition> {
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.refresh();
+    registry.tools()
+}
+
+/// `auth_required` of a capability, `None` when it is unknown or declares nothing
+pub fn auth_required(id: &str) -> Option<String> {
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.refresh();
+    registry.get(id).and_then(|c| c.auth_required.clone()).filter(|p| !p.trim().is_empty())
+}
+
+/// Remember the capabilities last fetched from the gateway
+pub fn register_gateway(capabilities: &[Capability]) {
+    registry().lock().unwrap_or_else(|e| e.into_inner()).set_gateway(capabilities);
+}
+
+/// Reject `execute` actions that call unknown capabilities or carry invalid arguments
+///
+/// Accepted actions get the full capability id as target. While no
+/// capabilities are known, every `execute` action is rejected.
+pub fn check_actions(parsed: &mut ParsedResponse) {
+    if !parsed.actions.iter().any(|a| a.action_type == ActionType::Execute) {
+        return;
+    }
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.refresh();
+    registry.check_actions(parsed);
+}
+
+/// Tauri commands for capabilities
+
+#[tauri::command]
+pub fn ai_get_capabilities() -> Vec<Capability> {
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.refresh();
+    registry.capabilities()
+}
+
+#[tauri::command]
+pub fn ai_reload_capabilities(auth: tauri::State<AuthState>) -> Result<Vec<Capability>, String> {
+    auth.authorize("ai_reload_capabilities", None, Permission::Admin)?;
+    let mut registry = registry().lock().map_err(|e| e.to_string())?;
+    registry.reload()?;
+    Ok(registry.capabilities())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(schema: Value, value: Value) -> Vec<String> {
+        let mut errors = Vec::new();
+        check_schema(&schema, &value, "params", &mut errors);
+        errors
+    }
+
+    fn query_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["patientId"],
+            "additionalProperties": false,
+            "properties": {
+                "patientId": { "type": "string", "minLength": 4 },
+                "modality": { "type": "string", "enum": ["CT", "MRI"] },
+                "limit": { "type": "integer", "minimum": 1, "maximum": 50 },
+                "range": {
+                    "type": "object",
+                    "required": ["from"],
+                    "properties": {
+                        "from": { "type": "string", "pattern": "^\\d{4}-\\d{2}-\\d{2}$" },
+                        "to": { "type": ["string", "null"] }
+                    }
+                },
+                "tags": { "type": "array", "items": { "type": "string" } }
+            }
+        })
+    }
+
+    fn registry_with(capabilities: Value) -> CapabilityRegistry {
+        let mut registry = CapabilityRegistry::new(PathBuf::from("capabilities-test-missing.json"));
+        let capabilities: Vec<Capability> = serde_json::from_value(capabilities).unwrap();
+        registry.set_gateway(&capabilities);
+        registry
+    }
+
+    fn execute(target: &str, system: Option<&str>, params: Value) -> AiAction {
+        AiAction {
+            action_type: ActionType::Execute,
+            target: target.to_string(),
+            value: None,
+            system: system.map(str::to_string),
+            params: params.as_object().cloned().unwrap_or_default(),
+            risk: None,
+        }
+    }
+
+    fn checked(registry: &CapabilityRegistry, actions: Vec<AiAction>) -> ParsedResponse {
+        let mut parsed = ParsedResponse {
+            content: String::new(),
+            actions,
+            diagnostics: Vec::new(),
+        };
+        registry.check_actions(&mut parsed);
+        parsed
+    }
+
+    #[test]
+    fn valid_arguments_pass() {
+        let value = json!({
+            "patientId": "P0001",
+            "modality": "CT",
+            "limit": 10,
+            "range": { "from": "2024-05-01", "to": null },
+            "tags": ["急诊"]
+        });
+        assert_eq!(errors(query_schema(), value), Vec::<String>::new());
+    }
+
+    #[test]
+    fn required_fields_and_unknown_properties_are_reported() {
+        let reported = errors(query_schema(), json!({ "extra": 1 }));
+        assert_eq!(reported, vec!["params.patientId: required", "params.extra: unexpected property"]);
+    }
+
+    #[test]
+    fn types_enums_and_bounds_are_checked() {
+        let reported = errors(
+            query_schema(),
+            json!({ "patientId": "P1", "modality": "PET", "limit": 2.5, "tags": ["a", 3] }),
+        );
+        assert_eq!(
+            reported,
+            vec![
+                "params.limit: expected integer, got number",
+                "params.modality: \"PET\" is not one of [\"CT\",\"MRI\"]",
+                "params.patientId: shorter than minLength",
+                "params.tags.1: expected string, got number",
+            ]
+        );
+        assert_eq!(
+            errors(query_schema(), json!({ "patientId": "P0001", "limit": 99 })),
+            vec!["params.limit: greater than maximum"]
+        );
+    }
+
+    #[test]
+    fn nested_objects_are_checked() {
+        let reported = errors(query_schema(), json!({ "patientId": "P0001", "range": { "from": "5月1日", "to": 3 } }));
+        assert_eq!(
+            reported,
+            vec![
+                "params.range.from: does not match ^\\d{4}-\\d{2}-\\d{2}$",
+                "params.range.to: expected string or null, got number",
+            ]
+        );
+        let reported = errors(query_schema(), json!({ "patientId": "P0001", "range": {} }));
+        assert_eq!(reported, vec!["params.range.from: required"]);
+    }
+
+    #[test]
+    fn known_capabilities_resolve_to_their_full_id() {
+        let registry = registry_with(json!([
+            { "id": "ris.query.exams", "name": "查询检查", "input_schema": query_schema() },
+            { "id": "ris.report.approve", "name": "审核报告", "enabled": false }
+        ]));
+        let parsed = checked(&registry, vec![execute("query.exams", Some("ris"), json!({ "patientId": "P0001" }))]);
+        assert!(parsed.diagnostics.is_empty());
+        assert_eq!(parsed.actions[0].target, "ris.query.exams");
+        assert_eq!(parsed.actions[0].system.as_deref(), Some("ris"));
+
+        let parsed = checked(
+            &registry,
+            vec![
+                execute("ris.query.exams", None, json!({})),
+                execute("ris.report.approve", None, json!({})),
+                execute("ris.query.exams", Some("pis"), json!({ "patientId": "P0001" })),
+            ],
+        );
+        assert!(parsed.actions.is_empty());
+        let messages: Vec<&str> = parsed.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Invalid arguments for ris.query.exams: params.patientId: required",
+                "Capability ris.report.approve is disabled",
+                "Capability ris.query.exams does not belong to system pis",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_capabilities_are_rejected_even_without_a_registry() {
+        let page_action = AiAction {
+            action_type: ActionType::Click,
+            ..execute("#submit", None, json!({}))
+        };
+        let empty = CapabilityRegistry::new(PathBuf::from("capabilities-test-missing.json"));
+        let parsed = checked(&empty, vec![execute("ris.report.delete", None, json!({})), page_action.clone()]);
+        assert_eq!(parsed.actions.len(), 1);
+        assert_eq!(parsed.actions[0].action_type, ActionType::Click);
+        assert_eq!(parsed.diagnostics[0].message, "Unknown capability: ris.report.delete");
+
+        let registry = registry_with(json!([{ "id": "ris.query.exams", "name": "查询检查" }]));
+        let parsed = checked(&registry, vec![execute("pis.query.slides", None, json!({}))]);
+        assert_eq!(parsed.diagnostics[0].message, "Unknown capability: pis.query.slides");
+    }
+}
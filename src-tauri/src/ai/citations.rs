@@ -0,0 +1,268 @@
+// Citations - ties statements in an answer to the data they came from
+//
+// The model marks the source of a value with an inline marker:
+//   * page context field:   [ref:page:context.currentPatient.lastCtDate]
+//   * action result field:  [ref:result:1:exams.0.examDate]
+// Markers are removed from the answer and resolved against the request that
+// was actually sent, so a citation is only valid if the model saw the value.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::context::PROMPT_PREFIX;
+use super::{AiRequest, TOOL_ROLE};
+
+pub(crate) const REF_PREFIX: &str = "[ref:";
+
+/// Instruction appended to the page context so the model knows the marker syntax
+pub const CITATION_PROMPT: &str = "引用页面数据或操作结果中的具体数值时，请在该句末尾标注来源，\
+     例如 [ref:page:context.currentPatient.name] 或 [ref:result:1:exams.0.examDate]（第1个操作结果中的字段）。";
+
+/// Where a cited value comes from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CitationSource {
+    /// The page context sent with the request
+    Page,
+    /// The result of an action executed earlier in the run
+    ActionResult,
+}
+
+/// A source reference attached to a statement in the answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Citation {
+    pub source: CitationSource,
+    /// 1-based index of the action result, for `ActionResult` citations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_index: Option<usize>,
+    /// Dotted path into the source, array items by index
+    pub path: String,
+    /// Character offset in the answer where the marker stood
+    pub offset: usize,
+    /// Cited value as sent to the model, `None` if the path does not resolve
+    #[serde(default)]
+    pub value: Option<Value>,
+    pub valid: bool,
+}
+
+/// How well an answer is backed by citations
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Grounding {
+    /// Answer produced locally, e.g. a follow-up question, or nothing to cite was sent
+    #[default]
+    NotChecked,
+    /// Every citation resolved
+    Grounded,
+    /// Some citations do not match the context that was sent
+    Partial,
+    /// No valid citation although data to cite was sent
+    Ungrounded,
+}
+
+/// Data the model was given: the page context and action results
+#[derive(Debug, Clone, Default)]
+pub struct CitationSources {
+    pub page: Option<Value>,
+    /// `result` of every action reported back, in order
+    pub results: Vec<Value>,
+}
+
+impl CitationSources {
+    /// Collect the page context and action results contained in a request
+    pub fn from_request(request: &AiRequest) -> Self {
+        let page = request.context.as_deref().and_then(|context| {
+            context
+                .lines()
+                .find_map(|line| line.strip_prefix(PROMPT_PREFIX))
+                .and_then(|json| serde_json::from_str(json.trim()).ok())
+        });
+
+        let results = request
+            .messages
+            .iter()
+            .filter(|m| m.role == TOOL_ROLE)
+            .filter_map(|m| serde_json::from_str::<Value>(&m.content).ok())
+            .filter_map(|v| v.get("results").and_then(Value::as_array).cloned())
+            .flatten()
+            .map(|r| r.get("result").cloned().unwrap_or(Value::Null))
+            .collect();
+
+        Self { page, results }
+    }
+
+    /// Whether the model was given any value it could cite
+    pub fn has_values(&self) -> bool {
+        self.page.iter().chain(&self.results).any(has_values)
+    }
+
+    fn resolve(&self, source: CitationSource, index: Option<usize>, path: &str) -> Option<Value> {
+        match source {
+            CitationSource::Page => {
+                let page = self.page.as_ref()?;
+                // "currentPatient.name" is accepted as short for "context.currentPatient.name"
+                lookup(page, path).or_else(|| page.get("context").and_then(|c| lookup(c, path)))
+            }
+            CitationSource::ActionResult => {
+                let result = self.results.get(index?.checked_sub(1)?)?;
+                lookup(result, path)
+            }
+        }
+    }
+}
+
+fn has_values(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Object(map) => map.values().any(has_values),
+        Value::Array(items) => items.iter().any(has_values),
+        _ => true,
+    }
+}
+
+/// Value at a dotted path; an empty path cites nothing rather than the whole source
+fn lookup(value: &Value, path: &str) -> Option<Value> {
+    let segments: Vec<&str> = path.split('.').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return None;
+    }
+    let mut current = value;
+    for segment in segments {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    (!current.is_null()).then(|| current.clone())
+}
+
+/// Remove citation markers from `content` and validate them against `sources`
+pub fn extract(content: &str, sources: &CitationSources) -> (String, Vec<Citation>) {
+    let mut text = String::with_capacity(content.len());
+    let mut citations = Vec::new();
+    let mut rest = content;
+
+    while let Some(pos) = rest.find(REF_PREFIX) {
+        text.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+        let Some(end) = tail.find(']') else {
+            text.push_str(tail);
+            rest = "";
+            break;
+        };
+        let marker = &tail[REF_PREFIX.len()..end];
+        rest = &tail[end + 1..];
+        // Markers usually sit before punctuation; don't leave a gap behind
+        if rest.starts_with(['。', '，', '；', '！', '？', '.', ',']) {
+            text.truncate(text.trim_end().len());
+        }
+
+        let Some((source, index, path)) = parse_marker(marker) else {
+            continue;
+        };
+        let value = sources.resolve(source, index, &path);
+        citations.push(Citation {
+            source,
+            result_index: index,
+            path,
+            offset: text.trim_end().chars().count(),
+            valid: value.is_some(),
+            value,
+        });
+    }
+    text.push_str(rest);
+    (text, citations)
+}
+
+/// "page:<path>" or "result:<n>:<path>"
+fn parse_marker(marker: &str) -> Option<(CitationSource, Option<usize>, String)> {
+    let (source, rest) = marker.trim().split_once(':')?;
+    match source.trim() {
+        "page" => Some((CitationSource::Page, None, rest.trim().to_string())),
+        "result" => {
+            let (index, path) = rest.split_once(':').unwrap_or((rest, ""));
+            let index = index.trim().parse().ok()?;
+            Some((CitationSource::ActionResult, Some(index), path.trim().to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Overall grounding of an answer produced by the model
+///
+/// An answer without citations is only ungrounded if there was something to cite.
+pub fn grounding(citations: &[Citation], sources: &CitationSources) -> Grounding {
+    let valid = citations.iter().filter(|c| c.valid).count();
+    if citations.is_empty() && !sources.has_values() {
+        Grounding::NotChecked
+    } else if valid == 0 {
+        Grounding::Ungrounded
+    } else if valid < citations.len() {
+        Grounding::Partial
+    } else {
+        Grounding::Grounded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sources() -> CitationSources {
+        CitationSources {
+            page: Some(json!({"context": {"currentPatient": {"name": "[PATIENT_NAME_1]", "lastCtDate": "2024-05-01"}}})),
+            results: vec![json!({"exams": [{"examDate": "2024-05-10"}]})],
+        }
+    }
+
+    #[test]
+    fn markers_are_removed_and_resolved() {
+        let (text, citations) = extract(
+            "上次CT在2024-05-01 [ref:page:currentPatient.lastCtDate]，最近一次在2024-05-10[ref:result:1:exams.0.examDate]。",
+            &sources(),
+        );
+        assert_eq!(text, "上次CT在2024-05-01，最近一次在2024-05-10。");
+        assert_eq!(citations.len(), 2);
+        assert_eq!(citations[0].value, Some(json!("2024-05-01")));
+        assert_eq!(citations[1].source, CitationSource::ActionResult);
+        assert_eq!(citations[1].value, Some(json!("2024-05-10")));
+        assert_eq!(grounding(&citations, &sources()), Grounding::Grounded);
+    }
+
+    #[test]
+    fn unresolved_paths_are_invalid() {
+        let (_, citations) = extract(
+            "姓名[ref:page:context.currentPatient.name]，年龄[ref:page:context.currentPatient.age]，结果[ref:result:2:exams]",
+            &sources(),
+        );
+        assert_eq!(citations.iter().map(|c| c.valid).collect::<Vec<_>>(), vec![true, false, false]);
+        assert_eq!(grounding(&citations, &sources()), Grounding::Partial);
+
+        let (_, citations) = extract("年龄[ref:page:context.currentPatient.age]", &sources());
+        assert_eq!(grounding(&citations, &sources()), Grounding::Ungrounded);
+    }
+
+    #[test]
+    fn empty_path_does_not_cite_the_whole_source() {
+        let (text, citations) = extract("见页面[ref:page:]和结果[ref:result:1]及[ref:page:.]", &sources());
+        assert_eq!(text, "见页面和结果及");
+        assert_eq!(citations.len(), 3);
+        assert!(citations.iter().all(|c| !c.valid && c.value.is_none()));
+        assert_eq!(grounding(&citations, &sources()), Grounding::Ungrounded);
+    }
+
+    #[test]
+    fn answer_without_markers_is_ungrounded_only_if_data_was_sent() {
+        let (_, citations) = extract("报告一般在检查后24小时内出具。", &sources());
+        assert_eq!(grounding(&citations, &sources()), Grounding::Ungrounded);
+
+        assert_eq!(grounding(&citations, &CitationSources::default()), Grounding::NotChecked);
+        let empty_page = CitationSources {
+            page: Some(json!({"context": {}})),
+            results: vec![Value::Null],
+        };
+        assert_eq!(grounding(&citations, &empty_page), Grounding::NotChecked);
+    }
+}
@@ -0,0 +1,327 @@
+// Page context - what the user is looking at, sent with every AI request
+//
+// Follows the page context standard of the architecture doc (section 4.4).
+// Serialization is compact and deterministic: struct fields keep their order
+// and JSON object keys are sorted, whatever order the page bridge sent.
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+use super::gateway::GatewayContext;
+use crate::browser::{BrowserState, BrowserTab};
+use crate::config::BusinessSystem;
+
+/// Longest string kept in page data
+const MAX_STRING_CHARS: usize = 200;
+/// Most items kept per array and fields per object in page data
+const MAX_ITEMS: usize = 30;
+/// Nesting below this depth is dropped
+const MAX_DEPTH: usize = 4;
+/// Upper bound for the serialized context
+const MAX_CONTEXT_BYTES: usize = 4096;
+/// Label in front of the serialized context in prompts
+pub(crate) const PROMPT_PREFIX: &str = "当前页面上下文: ";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PageInfo {
+    /// Business system id, e.g. "ris"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub path: String,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextUser {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<String>,
+}
+
+/// Business data of the page, as reported by the page bridge
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PageState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_patient: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_visit: Option<Value>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub form_data: Map<String, Value>,
+}
+
+/// What the page bridge reports about the active page
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageSnapshot {
+    #[serde(default)]
+    pub page_id: Option<String>,
+    #[serde(default)]
+    pub context: PageState,
+    #[serde(default)]
+    pub available_actions: Vec<String>,
+}
+
+/// Page context attached to AI requests
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PageContext {
+    pub page: PageInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<ContextUser>,
+    #[serde(default)]
+    pub context: PageState,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub available_actions: Vec<String>,
+}
+
+impl PageContext {
+    /// Build the context for a tab, matching it to the business system it belongs to
+    pub fn from_tab(
+        tab: &BrowserTab,
+        systems: &[BusinessSystem],
+        user: Option<ContextUser>,
+        snapshot: Option<PageSnapshot>,
+    ) -> Self {
+        let snapshot = snapshot.unwrap_or_default();
+        let path = url::Url::parse(&tab.url)
+            .map(|u| u.path().to_string())
+            .unwrap_or_else(|_| tab.url.clone());
+
+        let mut context = Self {
+            page: PageInfo {
+                system: system_for_url(systems, &tab.url).map(|s| s.id.clone()),
+                id: snapshot.page_id,
+                path,
+                title: tab.title.clone(),
+            },
+            user,
+            context: snapshot.context,
+            available_actions: snapshot.available_actions,
+        };
+        context.enforce_limits();
+        context
+    }
+
+    /// Context of the active tab, if any
+    pub fn from_active_tab(
+        browser: &BrowserState,
+        systems: &[BusinessSystem],
+        user: Option<ContextUser>,
+        snapshot: Option<PageSnapshot>,
+    ) -> Result<Option<Self>, String> {
+        let manager = browser.tab_manager.lock().map_err(|e| e.to_string())?;
+        let tab = manager
+            .active_tab_id
+            .as_deref()
+            .and_then(|id| manager.tabs.iter().find(|t| t.id == id));
+        Ok(tab.map(|tab| Self::from_tab(tab, systems, user, snapshot)))
+    }
+
+    /// Name of the current patient, if the page reports one
+    pub fn patient_name(&self) -> Option<&str> {
+        self.context
+            .current_patient
+            .as_ref()
+            .and_then(|p| p.get("name"))
+            .and_then(Value::as_str)
+    }
+
+    /// Compact single-line form used in prompts
+    pub fn to_prompt(&self) -> String {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        sort_keys(&mut value);
+        format!("{}{}", PROMPT_PREFIX, value)
+    }
+
+    /// Truncate page data so the serialized context stays within `MAX_CONTEXT_BYTES`
+    fn enforce_limits(&mut self) {
+        if let Some(patient) = self.context.current_patient.as_mut() {
+            clamp(patient, 0);
+        }
+        if let Some(visit) = self.context.current_visit.as_mut() {
+            clamp(visit, 0);
+        }
+        clamp_map(&mut self.context.form_data, 0);
+        self.available_actions.truncate(MAX_ITEMS);
+        for action in &mut self.available_actions {
+            truncate_chars(action);
+        }
+
+        // Drop the bulkiest parts first; the patient identity is kept longest
+        let mut dropped = Vec::new();
+        while self.serialized_len() > MAX_CONTEXT_BYTES {
+            if !self.context.form_data.is_empty() {
+                self.context.form_data.clear();
+                dropped.push("formData");
+            } else if self.context.current_visit.is_some() {
+                self.context.current_visit = None;
+                dropped.push("currentVisit");
+            } else if !self.available_actions.is_empty() {
+                self.available_actions.clear();
+                dropped.push("availableActions");
+            } else if self.context.current_patient.is_some() {
+                self.context.current_patient = None;
+                dropped.push("currentPatient");
+            } else {
+                break;
+            }
+        }
+        if !dropped.is_empty() {
+            warn!("Page context too large, dropped {}", dropped.join(", "));
+        }
+    }
+
+    fn serialized_len(&self) -> usize {
+        serde_json::to_string(self).map(|s| s.len()).unwrap_or(0)
+    }
+}
+
+impl From<&PageContext> for GatewayContext {
+    fn from(context: &PageContext) -> Self {
+        let page_data = match serde_json::to_value(&context.context) {
+            Ok(Value::Object(map)) => map,
+            _ => Map::new(),
+        };
+        GatewayContext {
+            current_system: context.page.system.clone(),
+            current_page: context.page.id.clone().or_else(|| Some(context.page.path.clone())),
+            page_data,
+        }
+    }
+}
+
+/// Business system with the same origin as `url` and the longest matching path
+///
+/// Paths are compared by segment, so "http://ris/app" matches "http://ris/app/list"
+/// but neither "http://ris/apple" nor "http://ris.hostile/app".
+fn system_for_url<'a>(systems: &'a [BusinessSystem], url: &str) -> Option<&'a BusinessSystem> {
+    let page = url::Url::parse(url).ok()?;
+    let page_segments = path_segments(&page);
+    systems
+        .iter()
+        .filter(|s| s.enabled)
+        .filter_map(|s| {
+            let base = url::Url::parse(&s.url).ok()?;
+            let segments = path_segments(&base);
+            (base.origin() == page.origin() && page_segments.starts_with(&segments)).then_some((s, segments.len()))
+        })
+        .max_by_key(|(_, depth)| *depth)
+        .map(|(s, _)| s)
+}
+
+/// Non-empty path segments of a URL
+fn path_segments(url: &url::Url) -> Vec<&str> {
+    url.path_segments()
+        .map(|segments| segments.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+fn clamp(value: &mut Value, depth: usize) {
+    match value {
+        Value::String(s) => truncate_chars(s),
+        Value::Array(items) => {
+            if depth >= MAX_DEPTH {
+                items.clear();
+            }
+            items.truncate(MAX_ITEMS);
+            for item in items {
+                clamp(item, depth + 1);
+            }
+        }
+        Value::Object(map) => clamp_map(map, depth),
+        _ => {}
+    }
+}
+
+fn clamp_map(map: &mut Map<String, Value>, depth: usize) {
+    if depth >= MAX_DEPTH {
+        map.clear();
+        return;
+    }
+    // Keep the first keys in sorted order so the subset is deterministic
+    let mut keys: Vec<String> = map.keys().cloned().collect();
+    keys.sort();
+    for key in keys.into_iter().skip(MAX_ITEMS) {
+        map.remove(&key);
+    }
+    for value in map.values_mut() {
+        clamp(value, depth + 1);
+    }
+}
+
+/// Re-insert object entries in key order (maps may preserve insertion order)
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = std::mem::take(map).into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            for (key, mut value) in entries {
+                sort_keys(&mut value);
+                map.insert(key, value);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
+
+fn truncate_chars(s: &mut String) {
+    if let Some((idx, _)) = s.char_indices().nth(MAX_STRING_CHARS) {
+        s.truncate(idx);
+        s.push('…');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(id: &str, url: &str) -> BusinessSystem {
+        BusinessSystem {
+            id: id.to_string(),
+            name: id.to_uppercase(),
+            url: url.to_string(),
+            icon: None,
+            enabled: true,
+        }
+    }
+
+    fn matched(systems: &[BusinessSystem], url: &str) -> Option<String> {
+        system_for_url(systems, url).map(|s| s.id.clone())
+    }
+
+    #[test]
+    fn matches_origin_and_path_segments() {
+        let systems = vec![
+            system("portal", "http://his.local"),
+            system("ris", "http://his.local/ris/"),
+            system("ris_report", "http://his.local/ris/report"),
+        ];
+
+        assert_eq!(matched(&systems, "http://his.local/ris/list?id=1").as_deref(), Some("ris"));
+        assert_eq!(matched(&systems, "http://his.local/ris/report/42").as_deref(), Some("ris_report"));
+        assert_eq!(matched(&systems, "http://his.local/risk").as_deref(), Some("portal"));
+        assert_eq!(matched(&systems, "http://HIS.local:80/ris").as_deref(), Some("ris"));
+    }
+
+    #[test]
+    fn other_origins_do_not_match() {
+        let systems = vec![system("ris", "http://ris"), system("pis", "http://localhost:8080/pis")];
+
+        assert_eq!(matched(&systems, "http://ris.hostile/list"), None);
+        assert_eq!(matched(&systems, "http://ris@evil.example/"), None);
+        assert_eq!(matched(&systems, "https://ris/list"), None);
+        assert_eq!(matched(&systems, "http://localhost:9090/pis"), None);
+        assert_eq!(matched(&systems, "not a url"), None);
+
+        let disabled = vec![BusinessSystem { enabled: false, ..system("ris", "http://ris") }];
+        assert_eq!(matched(&disabled, "http://ris/list"), None);
+    }
+}
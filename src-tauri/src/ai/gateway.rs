@@ -0,0 +1,691 @@
+// AI gateway client - /api/gateway endpoints from the API contract
+//
+// The gateway maps a business-system token to a short-lived token for the
+// AI backend; the mapped token is cached here until it expires. The system
+// token itself is kept in the secret store so it survives a restart.
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use super::actions::{self, ActionDiagnostic};
+use super::capabilities::{self, Capability};
+use super::citations::{self, CitationSources};
+use super::provider::http_client;
+use super::redact::Redactor;
+use super::usage::UsageMeter;
+use super::{AiMessage, AiRequest, AiResponse, AiState};
+use crate::auth::AuthState;
+use crate::config::{AiConfig, AppConfig};
+use crate::permissions::{self, Permission};
+use crate::secrets::{SecretStore, SYSTEM_TOKEN};
+
+/// Mapped tokens are refreshed this long before they expire
+const EXPIRY_MARGIN_SECS: u64 = 30;
+
+/// Gateway settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayConfig {
+    pub endpoint: String,
+    /// Capabilities requested when mapping the system token, e.g. "ris:read"
+    #[serde(default)]
+    pub requested_capabilities: Vec<String>,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:5000".to_string(),
+            requested_capabilities: Vec::new(),
+        }
+    }
+}
+
+/// Errors reported by the gateway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GatewayError {
+    /// 401 - token missing, invalid or expired
+    Unauthorized(String),
+    /// 403 - capability not granted to this user
+    Forbidden(String),
+    /// 404 - unknown endpoint or capability
+    NotFound(String),
+    /// 429 - rate limited
+    RateLimited { retry_after_secs: Option<u64> },
+    /// 5xx - gateway or upstream failure
+    Server(String),
+    /// Any other rejected request
+    BadRequest(String),
+    /// No system token has been provided yet
+    NotConnected,
+    /// The user's AI quota does not allow another request
+    QuotaExceeded(String),
+    Network(String),
+    InvalidResponse(String),
+}
+
+impl GatewayError {
+    fn from_status(status: StatusCode, retry_after_secs: Option<u64>, message: String) -> Self {
+        match status.as_u16() {
+            401 => GatewayError::Unauthorized(message),
+            403 => GatewayError::Forbidden(message),
+            404 => GatewayError::NotFound(message),
+            429 => GatewayError::RateLimited { retry_after_secs },
+            500..=599 => GatewayError::Server(message),
+            _ => GatewayError::BadRequest(format!("{}: {}", status, message)),
+        }
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GatewayError::Unauthorized(m) => write!(f, "网关认证失败: {}", m),
+            GatewayError::Forbidden(m) => write!(f, "无权访问该能力: {}", m),
+            GatewayError::NotFound(m) => write!(f, "网关资源不存在: {}", m),
+            GatewayError::RateLimited { retry_after_secs: Some(secs) } => {
+                write!(f, "请求过于频繁，请 {} 秒后重试", secs)
+            }
+            GatewayError::RateLimited { retry_after_secs: None } => write!(f, "请求过于频繁，请稍后重试"),
+            GatewayError::Server(m) => write!(f, "网关服务错误: {}", m),
+            GatewayError::BadRequest(m) => write!(f, "网关请求无效: {}", m),
+            GatewayError::NotConnected => write!(f, "尚未登录业务系统，无法访问网关"),
+            GatewayError::QuotaExceeded(m) => f.write_str(m),
+            GatewayError::Network(m) => write!(f, "无法连接网关: {}", m),
+            GatewayError::InvalidResponse(m) => write!(f, "网关响应格式错误: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+impl From<GatewayError> for String {
+    fn from(e: GatewayError) -> Self {
+        e.to_string()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AuthMapRequest<'a> {
+    system_token: &'a str,
+    requested_capabilities: &'a [String],
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AuthMapResponse {
+    mapped_token: String,
+    expires_in: u64,
+    #[serde(default)]
+    allowed_capabilities: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CapabilitiesResponse {
+    capabilities: Vec<Capability>,
+}
+
+/// Page context sent with gateway AI requests
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GatewayContext {
+    pub current_system: Option<String>,
+    pub current_page: Option<String>,
+    #[serde(default)]
+    pub page_data: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct GatewayAiRequest<'a> {
+    user_message: &'a str,
+    context: &'a GatewayContext,
+    options: GatewayAiOptions,
+}
+
+#[derive(Debug, Serialize)]
+struct GatewayAiOptions {
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct GatewayAiResponse {
+    ai_response: String,
+    #[serde(default)]
+    actions: Vec<Value>,
+    session_id: Option<String>,
+}
+
+/// Answer of `/api/gateway/ai/request`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayReply {
+    pub response: AiResponse,
+    pub session_id: Option<String>,
+}
+
+struct CachedToken {
+    token: String,
+    expires_at: Instant,
+    allowed_capabilities: Vec<String>,
+}
+
+#[derive(Default)]
+struct TokenState {
+    system_token: Option<String>,
+    mapped: Option<CachedToken>,
+}
+
+/// Client for the AI gateway
+pub struct GatewayClient {
+    http: Client,
+    endpoint: String,
+    requested_capabilities: Vec<String>,
+    tokens: Mutex<TokenState>,
+}
+
+impl GatewayClient {
+    pub fn new(http: Client, config: &GatewayConfig) -> Self {
+        let system_token = SecretStore::open_default().get(SYSTEM_TOKEN).unwrap_or_else(|e| {
+            warn!("Failed to restore gateway system token: {}", e);
+            None
+        });
+        Self {
+            http,
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+            requested_capabilities: config.requested_capabilities.clone(),
+            tokens: Mutex::new(TokenState {
+                system_token,
+                mapped: None,
+            }),
+        }
+    }
+
+    pub fn from_config(config: &AiConfig) -> Self {
+        Self::new(http_client(&config.resilience), &config.gateway)
+    }
+
+    /// Exchange a business-system token, returning the capabilities granted
+    pub async fn connect(&self, system_token: &str) -> Result<Vec<String>, GatewayError> {
+        let mapped = self.map_token(system_token).await?;
+        let allowed = mapped.allowed_capabilities.clone();
+        if let Err(e) = SecretStore::open_default().set(SYSTEM_TOKEN, system_token) {
+            warn!("Gateway system token not persisted: {}", e);
+        }
+        let mut tokens = self.tokens.lock().map_err(|e| GatewayError::Network(e.to_string()))?;
+        tokens.system_token = Some(system_token.to_string());
+        tokens.mapped = Some(mapped);
+        Ok(allowed)
+    }
+
+    /// Drop the mapped token of the current user, e.g. on logout
+    ///
+    /// The system token is shared by all users and stays, so the next user
+    /// gets a token mapped for them.
+    pub fn disconnect(&self) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.mapped = None;
+        }
+    }
+
+    /// Forget all tokens, including the persisted system token
+    pub fn forget(&self) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            *tokens = TokenState::default();
+        }
+        if let Err(e) = SecretStore::open_default().delete(SYSTEM_TOKEN) {
+            warn!("Failed to delete gateway system token: {}", e);
+        }
+    }
+
+    /// Capabilities granted to the current mapped token
+    pub fn allowed_capabilities(&self) -> Vec<String> {
+        self.tokens
+            .lock()
+            .ok()
+            .and_then(|t| t.mapped.as_ref().map(|m| m.allowed_capabilities.clone()))
+            .unwrap_or_default()
+    }
+
+    /// List all capabilities available to the current user
+    pub async fn capabilities(&self) -> Result<Vec<Capability>, GatewayError> {
+        let url = format!("{}/api/gateway/capabilities", self.endpoint);
+        let response: CapabilitiesResponse = self.authorized(|| self.http.get(&url)).await?;
+        capabilities::register_gateway(&response.capabilities);
+        Ok(response.capabilities)
+    }
+
+    /// Send a message through the gateway's AI entry point
+    ///
+    /// The message and page data are redacted before they leave the
+    /// workstation and the answer is restored, as for `AiClient`.
+    pub async fn ai_request(
+        &self,
+        message: &str,
+        context: &GatewayContext,
+        redactor: Option<&Redactor>,
+        meter: Option<&UsageMeter>,
+    ) -> Result<GatewayReply, GatewayError> {
+        let (user_message, sent_context) = match redactor {
+            Some(redactor) => (
+                redactor.redact(message),
+                GatewayContext {
+                    page_data: context
+                        .page_data
+                        .iter()
+                        .map(|(key, value)| (key.clone(), map_strings(value, &|s| redactor.redact(s))))
+                        .collect(),
+                    ..context.clone()
+                },
+            ),
+            None => (message.to_string(), context.clone()),
+        };
+
+        let estimate = AiRequest {
+            messages: vec![AiMessage {
+                role: "user".to_string(),
+                content: user_message.clone(),
+            }],
+            context: Some(Value::Object(sent_context.page_data.clone()).to_string()),
+            tools: Vec::new(),
+        };
+        let prompt_tokens = match meter {
+            Some(meter) => meter.check(&estimate).map_err(GatewayError::QuotaExceeded)?,
+            None => 0,
+        };
+
+        let url = format!("{}/api/gateway/ai/request", self.endpoint);
+        let body = GatewayAiRequest {
+            user_message: &user_message,
+            context: &sent_context,
+            options: GatewayAiOptions { stream: false },
+        };
+        let started = Instant::now();
+        let result: Result<GatewayAiResponse, GatewayError> =
+            self.authorized(|| self.http.post(&url).json(&body)).await;
+        if let Some(meter) = meter {
+            let outcome = result.as_ref().map(|r| r.ai_response.clone()).map_err(|e| e.to_string());
+            meter.record(prompt_tokens, &outcome, started);
+        }
+        let mut response = result?;
+        if let Some(redactor) = redactor {
+            response.ai_response = redactor.restore(&response.ai_response);
+            for entry in &mut response.actions {
+                *entry = map_strings(entry, &|s| redactor.restore(s));
+            }
+        }
+
+        // Actions may come both as markup in the text and as a structured list
+        let mut parsed = actions::parse(&response.ai_response);
+        for entry in &response.actions {
+            match actions::parse_json_action(entry) {
+                Ok(action) => parsed.actions.push(action),
+                Err(message) => {
+                    warn!("Rejected gateway action: {}", message);
+                    parsed.diagnostics.push(ActionDiagnostic {
+                        snippet: entry.to_string(),
+                        message,
+                    });
+                }
+            }
+        }
+
+        capabilities::check_actions(&mut parsed);
+        super::assess_actions(&mut parsed.actions);
+        let sources = CitationSources {
+            page: Some(json!({ "context": context.page_data })),
+            results: Vec::new(),
+        };
+        let (content, citations) = citations::extract(&parsed.content, &sources);
+
+        Ok(GatewayReply {
+            response: AiResponse {
+                content,
+                actions: parsed.actions,
+                diagnostics: parsed.diagnostics,
+                grounding: citations::grounding(&citations, &sources),
+                citations,
+            },
+            session_id: response.session_id,
+        })
+    }
+
+    async fn map_token(&self, system_token: &str) -> Result<CachedToken, GatewayError> {
+        let url = format!("{}/api/gateway/auth/map", self.endpoint);
+        let body = AuthMapRequest {
+            system_token,
+            requested_capabilities: &self.requested_capabilities,
+        };
+        let response = send(self.http.post(url).json(&body)).await?;
+        let mapped: AuthMapResponse = decode(response).await?;
+        info!("Mapped gateway token, expires in {}s", mapped.expires_in);
+
+        let lifetime = mapped.expires_in.saturating_sub(EXPIRY_MARGIN_SECS);
+        Ok(CachedToken {
+            token: mapped.mapped_token,
+            expires_at: Instant::now() + Duration::from_secs(lifetime),
+            allowed_capabilities: mapped.allowed_capabilities,
+        })
+    }
+
+    /// Valid mapped token, re-mapping the system token when the cache expired
+    async fn token(&self, force_refresh: bool) -> Result<String, GatewayError> {
+        let system_token = {
+            let tokens = self.tokens.lock().map_err(|e| GatewayError::Network(e.to_string()))?;
+            if let Some(mapped) = tokens.mapped.as_ref() {
+                if !force_refresh && Instant::now() < mapped.expires_at {
+                    return Ok(mapped.token.clone());
+                }
+            }
+            tokens.system_token.clone().ok_or(GatewayError::NotConnected)?
+        };
+
+        let mapped = self.map_token(&system_token).await?;
+        let token = mapped.token.clone();
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.mapped = Some(mapped);
+        }
+        Ok(token)
+    }
+
+    /// Send with the mapped token, re-mapping once if the gateway rejects it
+    async fn authorized<T, F>(&self, build: F) -> Result<T, GatewayError>
+    where
+        T: DeserializeOwned,
+        F: Fn() -> RequestBuilder,
+    {
+        let token = self.token(false).await?;
+        match send(build().bearer_auth(token)).await {
+            Err(GatewayError::Unauthorized(_)) => {
+                info!("Gateway rejected cached token, re-mapping");
+                let token = self.token(true).await?;
+                decode(send(build().bearer_auth(token)).await?).await
+            }
+            result => decode(result?).await,
+        }
+    }
+}
+
+/// Copy of `value` with `f` applied to every string in it
+fn map_strings(value: &Value, f: &impl Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(s) => Value::String(f(s)),
+        Value::Array(items) => Value::Array(items.iter().map(|item| map_strings(item, f)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, field)| (key.clone(), map_strings(field, f)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+async fn send(builder: RequestBuilder) -> Result<Response, GatewayError> {
+    let response = builder.send().await.map_err(|e| {
+        warn!("Gateway request failed: {}", e);
+        GatewayError::Network(e.to_string())
+    })?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+    let text = response.text().await.unwrap_or_default();
+    // The gateway reports `{"message": ...}` or `{"error": ...}`; fall back to the raw body
+    let message = serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|v| {
+            v.get("message")
+                .or_else(|| v.get("error"))
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        })
+        .unwrap_or(text);
+    warn!("Gateway error: {} - {}", status, message);
+    Err(GatewayError::from_status(status, retry_after, message))
+}
+
+async fn decode<T: DeserializeOwned>(response: Response) -> Result<T, GatewayError> {
+    response
+        .json()
+        .await
+        .map_err(|e| GatewayError::InvalidResponse(e.to_string()))
+}
+
+/// Tauri commands for the AI gateway
+
+#[tauri::command]
+pub async fn gateway_connect(
+    gateway: tauri::State<'_, GatewayClient>,
+    auth: tauri::State<'_, AuthState>,
+    system_token: String,
+) -> Result<Vec<String>, String> {
+    // The system token is persisted and used for every user of this workstation
+    auth.authorize("gateway_connect", None, Permission::Admin)?;
+    Ok(gateway.connect(&system_token).await?)
+}
+
+#[tauri::command]
+pub fn gateway_disconnect(
+    gateway: tauri::State<GatewayClient>,
+    auth: tauri::State<AuthState>,
+) -> Result<(), String> {
+    auth.authorize("gateway_disconnect", None, Permission::Admin)?;
+    gateway.forget();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn gateway_get_capabilities(
+    gateway: tauri::State<'_, GatewayClient>,
+    auth: tauri::State<'_, AuthState>,
+) -> Result<Vec<Capability>, String> {
+    auth.require_user()?;
+    Ok(gateway.capabilities().await?)
+}
+
+#[tauri::command]
+pub async fn gateway_ai_request(
+    gateway: tauri::State<'_, GatewayClient>,
+    auth: tauri::State<'_, AuthState>,
+    state: tauri::State<'_, AiState>,
+    message: String,
+    context: Option<GatewayContext>,
+) -> Result<GatewayReply, String> {
+    let context = context.unwrap_or_default();
+    let user = match &context.current_system {
+        Some(system) => auth.authorize("gateway_ai_request", Some(system), Permission::Read)?,
+        None => auth.require_user()?,
+    };
+    let config = AppConfig::load().ai;
+    // One vault per user, so a patient keeps its placeholder across gateway requests
+    let redactor = state.session_redactor(&format!("gateway:{}", user.id), &config.redaction);
+    let meter = UsageMeter::new(&user.id, context.current_system.clone(), &config.quota);
+    let mut reply = gateway
+        .ai_request(&message, &context, redactor.as_deref(), Some(&meter))
+        .await?;
+    permissions::filter_actions(&auth, "gateway_ai_request", &mut reply.response);
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::mock_server::{MockServer, Reply};
+    use crate::ai::redact::RedactionConfig;
+
+    fn client(url: &str, system_token: Option<&str>, mapped: Option<&str>) -> GatewayClient {
+        GatewayClient {
+            http: Client::new(),
+            endpoint: url.to_string(),
+            requested_capabilities: Vec::new(),
+            tokens: Mutex::new(TokenState {
+                system_token: system_token.map(str::to_string),
+                mapped: mapped.map(|token| CachedToken {
+                    token: token.to_string(),
+                    expires_at: Instant::now() + Duration::from_secs(60),
+                    allowed_capabilities: Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    fn connected(url: &str) -> GatewayClient {
+        client(url, None, Some("mapped"))
+    }
+
+    fn mapped_reply(token: &str, expires_in: u64) -> Reply {
+        Reply::Json(200, json!({"mapped_token": token, "expires_in": expires_in, "allowed_capabilities": []}))
+    }
+
+    #[tokio::test]
+    async fn request_is_redacted_and_answer_restored() {
+        let server = MockServer::start(vec![Reply::Json(
+            200,
+            json!({
+                "ai_response": "[PATIENT_NAME_1]的报告已找到",
+                "actions": [{"type": "fill", "target": "#patient", "value": "[PATIENT_NAME_1]"}],
+                "session_id": "s1"
+            }),
+        )])
+        .await;
+        let gateway = connected(&server.url);
+        let redactor = Redactor::new(&RedactionConfig::default());
+        let context = GatewayContext {
+            current_system: Some("ris".to_string()),
+            current_page: None,
+            page_data: json!({"patient": {"name": "张三", "phone": "13812345678"}, "rows": 3})
+                .as_object()
+                .cloned()
+                .unwrap(),
+        };
+
+        let reply = gateway
+            .ai_request("患者张三的报告", &context, Some(&redactor), None)
+            .await
+            .unwrap();
+
+        let sent = &server.requests()[0];
+        assert_eq!(sent.path, "/api/gateway/ai/request");
+        assert_eq!(sent.header("authorization"), Some("Bearer mapped"));
+        assert_eq!(sent.body["user_message"], "患者[PATIENT_NAME_1]的报告");
+        assert_eq!(
+            sent.body["context"]["page_data"],
+            json!({"patient": {"name": "[PATIENT_NAME_1]", "phone": "[PHONE_1]"}, "rows": 3})
+        );
+        assert!(!sent.body.to_string().contains("张三"));
+
+        assert_eq!(reply.response.content, "张三的报告已找到");
+        assert_eq!(reply.response.actions[0].value.as_deref(), Some("张三"));
+        assert_eq!(reply.session_id.as_deref(), Some("s1"));
+    }
+
+    #[tokio::test]
+    async fn disconnect_keeps_the_system_token_for_the_next_user() {
+        let server = MockServer::start(vec![
+            mapped_reply("mapped-b", 600),
+            Reply::Json(200, json!({"capabilities": []})),
+        ])
+        .await;
+        let gateway = client(&server.url, Some("system"), Some("mapped-a"));
+
+        gateway.disconnect();
+        assert!(gateway.allowed_capabilities().is_empty());
+        gateway.capabilities().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/api/gateway/auth/map");
+        assert_eq!(requests[0].body["system_token"], "system");
+        assert_eq!(requests[1].header("authorization"), Some("Bearer mapped-b"));
+    }
+
+    #[tokio::test]
+    async fn error_statuses_map_to_typed_errors() {
+        let cases = [
+            (Reply::Json(403, json!({"message": "无权访问"})), GatewayError::Forbidden("无权访问".to_string())),
+            (Reply::Json(404, json!({"error": "unknown"})), GatewayError::NotFound("unknown".to_string())),
+            (
+                Reply::JsonWithHeaders(429, vec![("retry-after", "7")], json!({})),
+                GatewayError::RateLimited { retry_after_secs: Some(7) },
+            ),
+            (Reply::Json(429, json!({})), GatewayError::RateLimited { retry_after_secs: None }),
+            (Reply::Json(503, json!({"message": "upstream down"})), GatewayError::Server("upstream down".to_string())),
+            (Reply::Json(400, json!({"message": "bad"})), GatewayError::BadRequest("400 Bad Request: bad".to_string())),
+        ];
+        for (reply, expected) in cases {
+            let server = MockServer::start(vec![reply]).await;
+            let err = connected(&server.url).capabilities().await.unwrap_err();
+            assert_eq!(err, expected);
+        }
+
+        // Without a system token a rejected mapped token cannot be replaced
+        let server = MockServer::start(vec![Reply::Json(401, json!({"message": "expired"}))]).await;
+        let err = connected(&server.url).capabilities().await.unwrap_err();
+        assert_eq!(err, GatewayError::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn mapped_token_is_cached_until_it_expires() {
+        let capabilities = Reply::Json(200, json!({"capabilities": []}));
+        let server = MockServer::start(vec![mapped_reply("t1", 600), capabilities.clone()]).await;
+        let gateway = client(&server.url, Some("system"), None);
+        gateway.capabilities().await.unwrap();
+        gateway.capabilities().await.unwrap();
+
+        let paths: Vec<_> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/api/gateway/auth/map", "/api/gateway/capabilities", "/api/gateway/capabilities"]);
+
+        // A lifetime within the expiry margin is mapped again on the next request
+        let server = MockServer::start(vec![
+            mapped_reply("t1", EXPIRY_MARGIN_SECS),
+            capabilities.clone(),
+            mapped_reply("t2", 600),
+            capabilities,
+        ])
+        .await;
+        let gateway = client(&server.url, Some("system"), None);
+        gateway.capabilities().await.unwrap();
+        gateway.capabilities().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[2].path, "/api/gateway/auth/map");
+        assert_eq!(requests[3].header("authorization"), Some("Bearer t2"));
+    }
+
+    #[tokio::test]
+    async fn rejected_token_is_remapped_once() {
+        let server = MockServer::start(vec![
+            Reply::Json(401, json!({"message": "expired"})),
+            mapped_reply("fresh", 600),
+            Reply::Json(200, json!({"capabilities": []})),
+        ])
+        .await;
+        let gateway = client(&server.url, Some("system"), Some("stale"));
+        gateway.capabilities().await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].header("authorization"), Some("Bearer stale"));
+        assert_eq!(requests[1].body["system_token"], "system");
+        assert_eq!(requests[2].header("authorization"), Some("Bearer fresh"));
+
+        // A second rejection is reported instead of re-mapping again
+        let server = MockServer::start(vec![
+            Reply::Json(401, json!({"message": "expired"})),
+            mapped_reply("fresh", 600),
+            Reply::Json(401, json!({"message": "revoked"})),
+        ])
+        .await;
+        let gateway = client(&server.url, Some("system"), Some("stale"));
+        let err = gateway.capabilities().await.unwrap_err();
+        assert_eq!(err, GatewayError::Unauthorized("revoked".to_string()));
+        assert_eq!(server.requests().len(), 3);
+    }
+}
@@ -0,0 +1,277 @@
+// History window - keeps prompts within budget by summarizing older turns
+//
+// Recent messages are sent verbatim. Older ones are folded into a rolling
+// summary written by the model and stored with the session, so each turn
+// only summarizes what was added since the last fold.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use super::provider::ProviderKind;
+use super::usage::estimate_tokens;
+use super::{AiClient, AiMessage, AiRequest};
+use crate::storage::AssistantSession;
+
+/// Prefix of the message carrying the conversation summary
+const SUMMARY_PREFIX: &str = "此前对话摘要：";
+
+/// History limits for one provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryBudget {
+    /// Token budget for summary plus verbatim history
+    pub max_tokens: u32,
+    /// Most recent messages that are never folded into the summary
+    pub keep_recent: usize,
+    /// Target length of the summary
+    pub summary_max_chars: usize,
+}
+
+impl Default for HistoryBudget {
+    fn default() -> Self {
+        Self {
+            max_tokens: 2500,
+            keep_recent: 6,
+            summary_max_chars: 400,
+        }
+    }
+}
+
+/// Per-provider history budgets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    pub budgets: HashMap<ProviderKind, HistoryBudget>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        let mut budgets = HashMap::new();
+        budgets.insert(ProviderKind::FastGpt, HistoryBudget::default());
+        // Local models usually run with a larger context window
+        budgets.insert(
+            ProviderKind::OpenAi,
+            HistoryBudget {
+                max_tokens: 3000,
+                ..HistoryBudget::default()
+            },
+        );
+        Self { budgets }
+    }
+}
+
+impl HistoryConfig {
+    pub fn budget_for(&self, provider: ProviderKind) -> HistoryBudget {
+        self.budgets.get(&provider).cloned().unwrap_or_default()
+    }
+}
+
+fn message_tokens(messages: &[AiMessage]) -> u32 {
+    messages.iter().map(|m| estimate_tokens(&m.content) + 4).sum()
+}
+
+impl AiClient {
+    /// Fit a session's messages into `budget`, updating the session summary
+    ///
+    /// `messages` is the full history including the new user message. The
+    /// session is modified in place; the caller persists it.
+    pub async fn window_history(
+        &self,
+        session: &mut AssistantSession,
+        messages: &[AiMessage],
+        budget: &HistoryBudget,
+    ) -> Vec<AiMessage> {
+        let covered = session.summary_upto.min(messages.len());
+        let mut pending = &messages[covered..];
+
+        let summary_tokens = session.summary.as_deref().map(estimate_tokens).unwrap_or(0);
+        if summary_tokens + message_tokens(pending) > budget.max_tokens {
+            let fold = pending.len().saturating_sub(budget.keep_recent.max(1));
+            if fold > 0 {
+                match self.summarize(session.summary.as_deref(), &pending[..fold], budget).await {
+                    Ok(summary) => {
+                        info!("Folded {} messages into summary of session {}", fold, session.id);
+                        session.summary = Some(summary);
+                        session.summary_upto = covered + fold;
+                        pending = &pending[fold..];
+                    }
+                    Err(e) => warn!("History summarization failed, truncating instead: {}", e),
+                }
+            }
+        }
+
+        // Still over budget (summary failed or recent turns are long): drop the
+        // oldest verbatim messages, always keeping the newest one
+        let summary_tokens = session.summary.as_deref().map(estimate_tokens).unwrap_or(0);
+        while pending.len() > 1 && summary_tokens + message_tokens(pending) > budget.max_tokens {
+            pending = &pending[1..];
+        }
+
+        let mut window = Vec::with_capacity(pending.len() + 1);
+        if let Some(summary) = session.summary.as_deref() {
+            window.push(AiMessage {
+                role: "system".to_string(),
+                content: format!("{}{}", SUMMARY_PREFIX, summary),
+            });
+        }
+        window.extend_from_slice(pending);
+        window
+    }
+
+    /// Ask the model to merge older messages into the running summary
+    async fn summarize(
+        &self,
+        previous: Option<&str>,
+        messages: &[AiMessage],
+        budget: &HistoryBudget,
+    ) -> Result<String, String> {
+        let summary = self.complete(&summary_request(previous, messages, budget)).await?;
+        let summary = summary.trim();
+        if summary.is_empty() {
+            return Err("Empty summary".to_string());
+        }
+        Ok(summary.to_string())
+    }
+}
+
+/// Request asking for `messages` to be merged into the `previous` summary
+fn summary_request(previous: Option<&str>, messages: &[AiMessage], budget: &HistoryBudget) -> AiRequest {
+    let mut transcript = String::new();
+    if let Some(previous) = previous {
+        transcript.push_str(&format!("已有摘要：{}\n\n", previous));
+    }
+    for m in messages {
+        let speaker = if m.role == "user" { "用户" } else { "助手" };
+        transcript.push_str(&format!("{}：{}\n", speaker, m.content));
+    }
+
+    let prompt = format!(
+        "请将以下对话内容合并为一段简洁的摘要，保留患者、检查、时间、已完成和未完成的操作等关键信息，\
+         不超过{}字，只输出摘要本身。\n\n{}",
+        budget.summary_max_chars, transcript
+    );
+    AiRequest {
+        messages: vec![AiMessage {
+            role: "user".to_string(),
+            content: prompt,
+        }],
+        context: None,
+        tools: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::fixture::{normalize_request, Exchange, FixtureFile};
+    use crate::ai::provider::ReplayProvider;
+    use std::path::PathBuf;
+
+    const SUMMARY: &str = "患者张三，CT已预约";
+
+    fn budget() -> HistoryBudget {
+        HistoryBudget {
+            max_tokens: 100,
+            keep_recent: 2,
+            summary_max_chars: 50,
+        }
+    }
+
+    /// Up to ten messages of 24 tokens each
+    fn messages(count: usize) -> Vec<AiMessage> {
+        "甲乙丙丁戊己庚辛壬癸"
+            .chars()
+            .take(count)
+            .enumerate()
+            .map(|(i, c)| AiMessage {
+                role: if i % 2 == 0 { "user" } else { "assistant" }.to_string(),
+                content: format!("{}{}", c, "检".repeat(19)),
+            })
+            .collect()
+    }
+
+    fn contents(messages: &[AiMessage]) -> Vec<String> {
+        messages.iter().map(|m| format!("{}: {}", m.role, m.content)).collect()
+    }
+
+    fn session() -> AssistantSession {
+        AssistantSession {
+            id: "history-test".to_string(),
+            user_id: "u1".to_string(),
+            messages: "[]".to_string(),
+            created_at: String::new(),
+            summary: None,
+            summary_upto: 0,
+            pending_intent: None,
+        }
+    }
+
+    /// Client replaying the given requests and answers from a fresh fixture file
+    fn replaying(exchanges: &[(AiRequest, &str)]) -> AiClient {
+        let file = FixtureFile {
+            exchanges: exchanges
+                .iter()
+                .map(|(request, response)| Exchange {
+                    key: normalize_request(request),
+                    provider: "Test".to_string(),
+                    request: request.clone(),
+                    response: response.to_string(),
+                    actions: Vec::new(),
+                    recorded_at: String::new(),
+                })
+                .collect(),
+        };
+        let path: PathBuf = std::env::temp_dir().join(format!("ew-history-{}.json", uuid::Uuid::new_v4()));
+        file.save(&path).unwrap();
+        // The fixture is read up front
+        let provider = ReplayProvider::new(path.clone());
+        std::fs::remove_file(&path).unwrap();
+        AiClient::with_provider(Box::new(provider))
+    }
+
+    #[tokio::test]
+    async fn history_within_budget_is_sent_verbatim() {
+        let client = replaying(&[]);
+        let mut session = session();
+        let history = messages(4);
+
+        let window = client.window_history(&mut session, &history, &budget()).await;
+        assert_eq!(contents(&window), contents(&history));
+        assert_eq!(session.summary, None);
+    }
+
+    #[tokio::test]
+    async fn older_messages_are_folded_into_summary_once() {
+        let history = messages(8);
+        let client = replaying(&[(summary_request(None, &history[..6], &budget()), SUMMARY)]);
+        let mut session = session();
+
+        let window = client.window_history(&mut session, &history, &budget()).await;
+        assert_eq!(session.summary.as_deref(), Some(SUMMARY));
+        assert_eq!(session.summary_upto, 6);
+        assert_eq!(window.len(), 3);
+        assert_eq!(window[0].role, "system");
+        assert_eq!(window[0].content, format!("{}{}", SUMMARY_PREFIX, SUMMARY));
+        assert_eq!(contents(&window[1..]), contents(&history[6..]));
+
+        // Next turn: the stored summary is reused without another model call
+        let history = messages(9);
+        let window = client.window_history(&mut session, &history, &budget()).await;
+        assert_eq!(session.summary_upto, 6);
+        assert_eq!(window.len(), 4);
+        assert_eq!(window[0].content, format!("{}{}", SUMMARY_PREFIX, SUMMARY));
+        assert_eq!(contents(&window[1..]), contents(&history[6..]));
+    }
+
+    #[tokio::test]
+    async fn failed_summary_falls_back_to_dropping_old_messages() {
+        // Nothing recorded, so the summary request fails
+        let client = replaying(&[]);
+        let mut session = session();
+        let history = messages(8);
+
+        let window = client.window_history(&mut session, &history, &budget()).await;
+        assert_eq!(session.summary, None);
+        assert_eq!(session.summary_upto, 0);
+        assert_eq!(contents(&window), contents(&history[4..]));
+        assert!(message_tokens(&window) <= budget().max_tokens);
+    }
+}
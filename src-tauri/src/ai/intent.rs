@@ -0,0 +1,680 @@
+// Intent classification - fast local rules with a model fallback
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use super::slots::Slot;
+use super::usage::UsageMeter;
+use super::{AiClient, AiMessage, AiRequest};
+use crate::auth::AuthState;
+use crate::config::{AppConfig, BusinessSystem};
+
+/// Rule matches at or above this confidence skip the model round trip
+pub(crate) const RULE_CONFIDENCE_THRESHOLD: f32 = 0.7;
+
+/// Kind of request the user made
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentKind {
+    /// Open a business system
+    Navigate,
+    /// Look up a patient or their exams
+    SearchPatient,
+    /// Switch between open browser tabs
+    SwitchTab,
+    /// Local file operation (organize, move, copy, delete, rename)
+    FileOperation,
+    CreateReminder,
+    /// Anything else, answered by the model
+    Chat,
+}
+
+/// Where the classification came from
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntentSource {
+    Rules,
+    Model,
+}
+
+/// Date range resolved from expressions like "上周" or "最近3天"
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TimeRange {
+    /// Expression as written by the user
+    pub label: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+/// Parameters extracted from the message
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct IntentSlots {
+    /// Business system id, e.g. "ris"
+    pub system: Option<String>,
+    pub patient_name: Option<String>,
+    pub exam_type: Option<String>,
+    pub time_range: Option<TimeRange>,
+    /// "next", "previous" or a 1-based tab index
+    pub tab: Option<String>,
+    /// "organize", "move", "copy", "delete" or "rename"
+    pub file_operation: Option<String>,
+    pub reminder_content: Option<String>,
+}
+
+/// Classified user intent
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Intent {
+    pub kind: IntentKind,
+    pub slots: IntentSlots,
+    pub confidence: f32,
+    pub source: IntentSource,
+}
+
+impl IntentSlots {
+    /// Take every slot that is still empty from `other`
+    pub fn fill_missing(&mut self, other: IntentSlots) {
+        self.system = self.system.take().or(other.system);
+        self.patient_name = self.patient_name.take().or(other.patient_name);
+        self.exam_type = self.exam_type.take().or(other.exam_type);
+        self.time_range = self.time_range.take().or(other.time_range);
+        self.tab = self.tab.take().or(other.tab);
+        self.file_operation = self.file_operation.take().or(other.file_operation);
+        self.reminder_content = self.reminder_content.take().or(other.reminder_content);
+    }
+}
+
+impl Intent {
+    fn chat(confidence: f32, source: IntentSource) -> Self {
+        Self {
+            kind: IntentKind::Chat,
+            slots: IntentSlots::default(),
+            confidence,
+            source,
+        }
+    }
+}
+
+/// Exam type keywords and their normalized names
+const EXAM_TYPES: &[(&str, &str)] = &[
+    ("PET-CT", "PET-CT"),
+    ("PET", "PET-CT"),
+    ("CT", "CT"),
+    ("MRI", "MRI"),
+    ("核磁", "MRI"),
+    ("磁共振", "MRI"),
+    ("DR", "XRay"),
+    ("X光", "XRay"),
+    ("X线", "XRay"),
+    ("B超", "US"),
+    ("超声", "US"),
+    ("彩超", "US"),
+    ("胃镜", "Gastroscopy"),
+    ("肠镜", "Colonoscopy"),
+    ("病理", "Pathology"),
+];
+
+const NAVIGATE_WORDS: &[&str] = &["打开", "进入", "切换到", "跳转", "去", "open"];
+const SEARCH_WORDS: &[&str] = &["查", "搜索", "找", "调阅", "看一下", "看看"];
+const PATIENT_WORDS: &[&str] = &["患者", "病人"];
+const TAB_WORDS: &[&str] = &["标签页", "标签", "tab"];
+const REMINDER_WORDS: &[&str] = &["提醒我", "设置提醒", "创建提醒", "添加提醒", "提醒一下"];
+const FILE_WORDS: &[&str] = &["文件", "文件夹", "目录"];
+const FILE_OPERATIONS: &[(&str, &str)] = &[
+    ("整理", "organize"),
+    ("归类", "organize"),
+    ("移动", "move"),
+    ("复制", "copy"),
+    ("拷贝", "copy"),
+    ("删除", "delete"),
+    ("重命名", "rename"),
+];
+
+/// Characters that end a patient name following "患者"/"病人"
+const NAME_STOP_CHARS: &[char] = &[
+    '的', '做', '检', '最', '近', '今', '昨', '本', '上', '这', '那', '吗', '呢', '有', '在', '是', '和',
+];
+
+/// Local keyword matcher over the configured business systems
+pub struct IntentMatcher {
+    /// (system id, lowercase alias)
+    aliases: Vec<(String, String)>,
+}
+
+impl IntentMatcher {
+    pub fn new(systems: &[BusinessSystem]) -> Self {
+        let mut aliases = Vec::new();
+        for system in systems.iter().filter(|s| s.enabled) {
+            aliases.push((system.id.clone(), system.id.to_lowercase()));
+
+            // "RIS (放射信息系统)" -> "ris", "放射信息系统", "放射"
+            let (short, long) = match system.name.split_once('(') {
+                Some((short, rest)) => (short.trim(), rest.trim_end_matches(')').trim()),
+                None => (system.name.trim(), ""),
+            };
+            for alias in [short, long] {
+                if !alias.is_empty() {
+                    aliases.push((system.id.clone(), alias.to_lowercase()));
+                }
+            }
+            if let Some(prefix) = long.strip_suffix("信息系统") {
+                if !prefix.is_empty() {
+                    aliases.push((system.id.clone(), prefix.to_string()));
+                }
+            }
+        }
+        // Prefer longer aliases so overlapping names resolve to the most specific system
+        aliases.sort_by_key(|(_, alias)| std::cmp::Reverse(alias.chars().count()));
+        Self { aliases }
+    }
+
+    /// Classify with rules only
+    pub fn classify(&self, message: &str) -> Intent {
+        self.classify_at(message, Local::now().date_naive())
+    }
+
+    fn classify_at(&self, message: &str, today: NaiveDate) -> Intent {
+        let text = message.trim();
+        let lower = text.to_lowercase();
+
+        let mut slots = IntentSlots {
+            system: self.match_system(&lower),
+            exam_type: match_exam_type(text),
+            time_range: match_time_range(text, today),
+            patient_name: match_patient_name(text),
+            ..Default::default()
+        };
+
+        if contains_any(&lower, REMINDER_WORDS) {
+            slots.reminder_content = reminder_content(text);
+            return rule_intent(IntentKind::CreateReminder, slots, 0.9);
+        }
+
+        if contains_any(&lower, FILE_WORDS) {
+            if let Some((_, op)) = FILE_OPERATIONS.iter().find(|(word, _)| text.contains(word)) {
+                slots.file_operation = Some(op.to_string());
+                return rule_intent(IntentKind::FileOperation, slots, 0.85);
+            }
+        }
+
+        if contains_any(&lower, TAB_WORDS) {
+            slots.tab = match_tab(text);
+            let confidence = if slots.tab.is_some() { 0.9 } else { 0.6 };
+            return rule_intent(IntentKind::SwitchTab, slots, confidence);
+        }
+
+        let mentions_patient = contains_any(text, PATIENT_WORDS) || slots.patient_name.is_some();
+        let wants_search = contains_any(text, SEARCH_WORDS);
+        if mentions_patient || (wants_search && slots.exam_type.is_some()) {
+            let confidence = match (wants_search, mentions_patient) {
+                (true, true) => 0.9,
+                (true, false) | (false, true) => 0.75,
+                (false, false) => 0.5,
+            };
+            return rule_intent(IntentKind::SearchPatient, slots, confidence);
+        }
+
+        if slots.system.is_some() && contains_any(&lower, NAVIGATE_WORDS) {
+            return rule_intent(IntentKind::Navigate, slots, 0.9);
+        }
+
+        Intent::chat(0.3, IntentSource::Rules)
+    }
+
+    fn match_system(&self, lower: &str) -> Option<String> {
+        self.aliases
+            .iter()
+            .find(|(_, alias)| contains_word(lower, alias))
+            .map(|(id, _)| id.clone())
+    }
+
+    fn system_ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.aliases.iter().map(|(id, _)| id.as_str()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Read one slot from a short follow-up answer such as "张三" or "第二个"
+    pub fn answer_slot(&self, slot: Slot, answer: &str) -> Option<String> {
+        let text = answer.trim().trim_end_matches(['。', '.', '！', '!']);
+        match slot {
+            Slot::System => self.match_system(&text.to_lowercase()),
+            Slot::PatientName => match_patient_name(text).or_else(|| {
+                let name = text.trim_start_matches(['是', '叫']);
+                let count = name.chars().count();
+                ((2..=4).contains(&count) && name.chars().all(is_han)).then(|| name.to_string())
+            }),
+            Slot::Tab => match_tab(text).or_else(|| {
+                let mut chars = text.chars();
+                let first = chars.next()?;
+                let rest = chars.as_str();
+                (rest.is_empty() || rest == "个").then(|| chinese_number(first)).flatten().map(|n| n.to_string())
+            }),
+            Slot::FileOperation => FILE_OPERATIONS
+                .iter()
+                .find(|(word, _)| text.contains(word))
+                .map(|(_, op)| op.to_string()),
+            Slot::ReminderContent => reminder_content(text)
+                .or_else(|| Some(text.to_string()))
+                .filter(|content| !content.is_empty()),
+        }
+    }
+}
+
+fn rule_intent(kind: IntentKind, slots: IntentSlots, confidence: f32) -> Intent {
+    Intent {
+        kind,
+        slots,
+        confidence,
+        source: IntentSource::Rules,
+    }
+}
+
+fn contains_any(text: &str, words: &[&str]) -> bool {
+    words.iter().any(|w| text.contains(w))
+}
+
+/// Substring match that does not split ASCII words ("ris" must not match "paris")
+fn contains_word(text: &str, word: &str) -> bool {
+    if !word.is_ascii() {
+        return text.contains(word);
+    }
+    text.match_indices(word).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + word.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric())
+            && !after.is_some_and(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+fn match_exam_type(text: &str) -> Option<String> {
+    let upper = text.to_uppercase();
+    EXAM_TYPES
+        .iter()
+        .find(|(keyword, _)| contains_word(&upper, &keyword.to_uppercase()))
+        .map(|(_, normalized)| normalized.to_string())
+}
+
+fn match_patient_name(text: &str) -> Option<String> {
+    for word in PATIENT_WORDS {
+        for (i, _) in text.match_indices(word) {
+            let name: String = text[i + word.len()..]
+                .trim_start_matches([' ', '：', ':'])
+                .chars()
+                .take_while(|c| is_han(*c) && !NAME_STOP_CHARS.contains(c))
+                .take(4)
+                .collect();
+            if name.chars().count() >= 2 {
+                return Some(name);
+            }
+        }
+    }
+    None
+}
+
+fn is_han(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}')
+}
+
+fn match_tab(text: &str) -> Option<String> {
+    if text.contains("下一个") || text.contains("下个") {
+        return Some("next".to_string());
+    }
+    if text.contains("上一个") || text.contains("上个") || text.contains("前一个") {
+        return Some("previous".to_string());
+    }
+    let after = text.split_once('第')?.1;
+    let first = after.chars().next()?;
+    chinese_number(first).map(|n| n.to_string())
+}
+
+fn reminder_content(text: &str) -> Option<String> {
+    REMINDER_WORDS
+        .iter()
+        .find_map(|w| text.split_once(w).map(|(_, rest)| rest))
+        .map(|rest| rest.trim_start_matches(['：', ':', '，', ',', ' ']).trim().to_string())
+        .filter(|rest| !rest.is_empty())
+}
+
+fn chinese_number(c: char) -> Option<u32> {
+    match c {
+        '一' => Some(1),
+        '二' | '两' => Some(2),
+        '三' => Some(3),
+        '四' => Some(4),
+        '五' => Some(5),
+        '六' => Some(6),
+        '七' => Some(7),
+        '八' => Some(8),
+        '九' => Some(9),
+        '十' => Some(10),
+        _ => c.to_digit(10),
+    }
+}
+
+/// Resolve common relative date expressions
+fn match_time_range(text: &str, today: NaiveDate) -> Option<TimeRange> {
+    let range = |label: &str, from: NaiveDate, to: NaiveDate| {
+        Some(TimeRange {
+            label: label.to_string(),
+            from,
+            to,
+        })
+    };
+
+    if let Some(recent) = match_recent(text, today) {
+        return Some(recent);
+    }
+
+    let week_start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let month_start = today.with_day(1)?;
+
+    if text.contains("今天") || text.contains("今日") {
+        range("今天", today, today)
+    } else if text.contains("昨天") || text.contains("昨日") {
+        let day = today - Duration::days(1);
+        range("昨天", day, day)
+    } else if text.contains("前天") {
+        let day = today - Duration::days(2);
+        range("前天", day, day)
+    } else if text.contains("本周") || text.contains("这周") || text.contains("这个星期") {
+        range("本周", week_start, today)
+    } else if text.contains("上周") || text.contains("上个星期") {
+        range("上周", week_start - Duration::days(7), week_start - Duration::days(1))
+    } else if text.contains("本月") || text.contains("这个月") {
+        range("本月", month_start, today)
+    } else if text.contains("上个月") || text.contains("上月") {
+        let last_month_end = month_start - Duration::days(1);
+        range("上个月", last_month_end.with_day(1)?, last_month_end)
+    } else {
+        None
+    }
+}
+
+/// "最近3天" / "近七天" / "最近两周" / "近3个月"
+fn match_recent(text: &str, today: NaiveDate) -> Option<TimeRange> {
+    const UNITS: &[(&str, i64)] = &[("天", 1), ("日", 1), ("周", 7), ("星期", 7), ("个月", 30), ("月", 30), ("年", 365)];
+
+    for prefix in ["最近", "近"] {
+        for (start, _) in text.match_indices(prefix) {
+            let rest = &text[start + prefix.len()..];
+            let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+            let (count, rest) = if !digits.is_empty() {
+                (digits.parse::<i64>().ok(), &rest[digits.len()..])
+            } else {
+                match rest.chars().next() {
+                    Some(c) if chinese_number(c).is_some() => (chinese_number(c).map(i64::from), &rest[c.len_utf8()..]),
+                    // No count given, e.g. "近月"
+                    _ => (Some(1), rest),
+                }
+            };
+            let Some((unit, days)) = UNITS.iter().find(|(unit, _)| rest.starts_with(unit)) else {
+                continue;
+            };
+            // Counts too large for a date, e.g. "最近9999999999天", are not a range
+            let from = count?
+                .max(1)
+                .checked_mul(*days)
+                .and_then(|span| Duration::try_days(span - 1))
+                .and_then(|span| today.checked_sub_signed(span))?;
+            let label_end = text.len() - rest.len() + unit.len();
+            return Some(TimeRange {
+                label: text[start..label_end].to_string(),
+                from,
+                to: today,
+            });
+        }
+    }
+    None
+}
+
+/// Constrained prompt for model-based classification
+fn intent_prompt(system_ids: &[&str], today: NaiveDate) -> String {
+    format!(
+        "你是意图识别器。只输出一个JSON对象，不要输出其他内容。\n\
+         格式: {{\"intent\": \"navigate|search_patient|switch_tab|file_operation|create_reminder|chat\", \
+         \"system\": 系统ID或null, \"patient_name\": 字符串或null, \"exam_type\": 字符串或null, \
+         \"time_from\": \"YYYY-MM-DD\"或null, \"time_to\": \"YYYY-MM-DD\"或null, \
+         \"tab\": 字符串或null, \"file_operation\": 字符串或null, \"reminder_content\": 字符串或null}}\n\
+         可用系统ID: {}\n今天日期: {}",
+        system_ids.join(", "),
+        today
+    )
+}
+
+/// Parse the model's JSON answer; unusable answers become `Chat`
+fn parse_model_intent(content: &str, known_systems: &[&str]) -> Intent {
+    let json = match (content.find('{'), content.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &content[start..=end],
+        _ => {
+            warn!("Intent model returned no JSON: {}", content);
+            return Intent::chat(0.5, IntentSource::Model);
+        }
+    };
+    let Ok(value) = serde_json::from_str::<Value>(json) else {
+        warn!("Intent model returned invalid JSON: {}", json);
+        return Intent::chat(0.5, IntentSource::Model);
+    };
+
+    let text = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|s| !s.is_empty() && *s != "null")
+            .map(str::to_string)
+    };
+    let date = |key: &str| text(key).and_then(|d| NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok());
+
+    let kind = match text("intent").as_deref() {
+        Some("navigate") => IntentKind::Navigate,
+        Some("search_patient") => IntentKind::SearchPatient,
+        Some("switch_tab") => IntentKind::SwitchTab,
+        Some("file_operation") => IntentKind::FileOperation,
+        Some("create_reminder") => IntentKind::CreateReminder,
+        _ => IntentKind::Chat,
+    };
+
+    let time_range = match (date("time_from"), date("time_to")) {
+        (Some(from), Some(to)) => Some(TimeRange {
+            label: format!("{} ~ {}", from, to),
+            from,
+            to,
+        }),
+        (Some(day), None) | (None, Some(day)) => Some(TimeRange {
+            label: day.to_string(),
+            from: day,
+            to: day,
+        }),
+        (None, None) => None,
+    };
+
+    Intent {
+        kind,
+        slots: IntentSlots {
+            // Only accept systems we actually know about
+            system: text("system")
+                .map(|s| s.to_lowercase())
+                .filter(|s| known_systems.contains(&s.as_str())),
+            patient_name: text("patient_name"),
+            exam_type: text("exam_type"),
+            time_range,
+            tab: text("tab"),
+            file_operation: text("file_operation"),
+            reminder_content: text("reminder_content"),
+        },
+        confidence: 0.8,
+        source: IntentSource::Model,
+    }
+}
+
+impl AiClient {
+    /// Get intent from user message
+    ///
+    /// Tries the local matcher first and only asks the model when no rule
+    /// matched with enough confidence.
+    pub async fn parse_intent(&self, message: &str, systems: &[BusinessSystem]) -> Result<Intent, String> {
+        let matcher = IntentMatcher::new(systems);
+        let today = Local::now().date_naive();
+        let intent = matcher.classify_at(message, today);
+        if intent.confidence >= RULE_CONFIDENCE_THRESHOLD {
+            info!("Intent matched by rules: {:?}", intent.kind);
+            return Ok(intent);
+        }
+
+        let system_ids = matcher.system_ids();
+        let request = AiRequest {
+            messages: vec![
+                AiMessage {
+                    role: "system".to_string(),
+                    content: intent_prompt(&system_ids, today),
+                },
+                AiMessage {
+                    role: "user".to_string(),
+                    content: message.to_string(),
+                },
+            ],
+            context: None,
+            tools: Vec::new(),
+        };
+
+        let content = self.complete(&request).await?;
+        let mut model_intent = parse_model_intent(&content, &system_ids);
+        info!("Intent classified by model: {:?}", model_intent.kind);
+
+        // Keep slots the rules already found when the model left them out
+        model_intent.slots.fill_missing(intent.slots);
+        Ok(model_intent)
+    }
+}
+
+/// Tauri command for intent classification
+
+#[tauri::command]
+pub async fn ai_parse_intent(auth: tauri::State<'_, AuthState>, message: String) -> Result<Intent, String> {
+    let user = auth.require_user()?;
+    let config = AppConfig::load();
+    let client = AiClient::from_config(&config.ai).with_usage(UsageMeter::new(&user.id, None, &config.ai.quota));
+    client.parse_intent(&message, &config.business_systems).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher() -> IntentMatcher {
+        IntentMatcher::new(&AppConfig::default().business_systems)
+    }
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn navigates_to_system_by_id_or_name() {
+        let matcher = matcher();
+        let today = day(2024, 5, 15);
+
+        let intent = matcher.classify_at("打开RIS", today);
+        assert_eq!(intent.kind, IntentKind::Navigate);
+        assert_eq!(intent.slots.system.as_deref(), Some("ris"));
+
+        let intent = matcher.classify_at("进入病理信息系统", today);
+        assert_eq!(intent.kind, IntentKind::Navigate);
+        assert_eq!(intent.slots.system.as_deref(), Some("pis"));
+
+        // "ris" inside another word is not the system
+        assert_eq!(matcher.classify_at("open paris", today).kind, IntentKind::Chat);
+    }
+
+    #[test]
+    fn extracts_patient_exam_and_time_range() {
+        let intent = matcher().classify_at("查一下患者张三最近3天的CT", day(2024, 5, 15));
+
+        assert_eq!(intent.kind, IntentKind::SearchPatient);
+        assert!(intent.confidence >= RULE_CONFIDENCE_THRESHOLD);
+        assert_eq!(intent.slots.patient_name.as_deref(), Some("张三"));
+        assert_eq!(intent.slots.exam_type.as_deref(), Some("CT"));
+        assert_eq!(
+            intent.slots.time_range,
+            Some(TimeRange {
+                label: "最近3天".to_string(),
+                from: day(2024, 5, 13),
+                to: day(2024, 5, 15),
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_relative_dates() {
+        let today = day(2024, 5, 15); // a Wednesday
+        let range = |text: &str| match_time_range(text, today).map(|r| (r.from, r.to));
+
+        assert_eq!(range("昨天的报告"), Some((day(2024, 5, 14), day(2024, 5, 14))));
+        assert_eq!(range("本周"), Some((day(2024, 5, 13), today)));
+        assert_eq!(range("上周"), Some((day(2024, 5, 6), day(2024, 5, 12))));
+        assert_eq!(range("上个月"), Some((day(2024, 4, 1), day(2024, 4, 30))));
+        assert_eq!(range("近两周"), Some((day(2024, 5, 2), today)));
+        assert_eq!(range("近月"), Some((day(2024, 4, 16), today)));
+        assert_eq!(range("没有时间"), None);
+    }
+
+    #[test]
+    fn huge_recent_count_is_not_a_range() {
+        let today = day(2024, 5, 15);
+        assert_eq!(match_time_range("最近9999999999天的检查", today), None);
+        assert_eq!(match_time_range("近99999999999999999999年", today), None);
+
+        let intent = matcher().classify_at("查患者张三最近9999999999天", today);
+        assert_eq!(intent.kind, IntentKind::SearchPatient);
+        assert_eq!(intent.slots.time_range, None);
+    }
+
+    #[test]
+    fn classifies_tabs_files_and_reminders() {
+        let matcher = matcher();
+        let today = day(2024, 5, 15);
+
+        let intent = matcher.classify_at("切换到第三个标签页", today);
+        assert_eq!(intent.kind, IntentKind::SwitchTab);
+        assert_eq!(intent.slots.tab.as_deref(), Some("3"));
+
+        let intent = matcher.classify_at("帮我整理下载文件夹", today);
+        assert_eq!(intent.kind, IntentKind::FileOperation);
+        assert_eq!(intent.slots.file_operation.as_deref(), Some("organize"));
+
+        let intent = matcher.classify_at("提醒我：下午三点开会", today);
+        assert_eq!(intent.kind, IntentKind::CreateReminder);
+        assert_eq!(intent.slots.reminder_content.as_deref(), Some("下午三点开会"));
+
+        let intent = matcher.classify_at("这个病灶是什么意思", today);
+        assert_eq!(intent.kind, IntentKind::Chat);
+        assert!(intent.confidence < RULE_CONFIDENCE_THRESHOLD);
+    }
+
+    #[test]
+    fn reads_follow_up_answers() {
+        let matcher = matcher();
+        assert_eq!(matcher.answer_slot(Slot::PatientName, "叫李四。"), Some("李四".to_string()));
+        assert_eq!(matcher.answer_slot(Slot::Tab, "二"), Some("2".to_string()));
+        assert_eq!(matcher.answer_slot(Slot::System, "病理"), Some("pis".to_string()));
+        assert_eq!(matcher.answer_slot(Slot::PatientName, "不知道他叫什么"), None);
+    }
+
+    #[test]
+    fn model_answer_is_parsed_and_checked() {
+        let content = "结果如下：{\"intent\": \"search_patient\", \"system\": \"RIS\", \"patient_name\": \"王五\", \
+                       \"time_from\": \"2024-05-01\", \"time_to\": null}";
+        let intent = parse_model_intent(content, &["ris", "pis"]);
+        assert_eq!(intent.kind, IntentKind::SearchPatient);
+        assert_eq!(intent.source, IntentSource::Model);
+        assert_eq!(intent.slots.system.as_deref(), Some("ris"));
+        assert_eq!(intent.slots.patient_name.as_deref(), Some("王五"));
+        assert_eq!(intent.slots.time_range.map(|r| r.from), Some(day(2024, 5, 1)));
+
+        let unknown = parse_model_intent("{\"intent\": \"navigate\", \"system\": \"his\"}", &["ris"]);
+        assert_eq!(unknown.slots.system, None);
+        assert_eq!(parse_model_intent("不是JSON", &["ris"]).kind, IntentKind::Chat);
+    }
+}
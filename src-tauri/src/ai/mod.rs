@@ -0,0 +1,207 @@
+// AI module - assistant client and provider integration
+pub mod actions;
+pub mod agent;
+pub mod cache;
+pub mod capabilities;
+pub mod citations;
+pub mod context;
+pub mod gateway;
+pub mod history;
+pub mod intent;
+pub mod prompts;
+pub mod provider;
+pub mod redact;
+pub mod resilience;
+pub mod session;
+pub mod slots;
+pub mod stream;
+pub mod usage;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
+
+use crate::config::AiConfig;
+use capabilities::ToolDefinition;
+use citations::CitationSources;
+use crate::core::security::{RiskAssessment, RiskEngine};
+use provider::{AiProvider, FastGptProvider, Transport};
+use resilience::ResilienceConfig;
+use usage::UsageMeter;
+use redact::Redactor;
+
+/// Role of messages carrying action results back to the model
+pub const TOOL_ROLE: &str = "tool";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiRequest {
+    pub messages: Vec<AiMessage>,
+    pub context: Option<String>,
+    /// Capabilities the model may call, for providers with function calling
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolDefinition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiResponse {
+    pub content: String,
+    pub actions: Vec<AiAction>,
+    /// Action entries that were rejected while parsing
+    #[serde(default)]
+    pub diagnostics: Vec<ActionDiagnostic>,
+    /// Sources backing statements in `content`
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    #[serde(default)]
+    pub grounding: Grounding,
+}
+
+impl AiResponse {
+    /// Plain answer produced without the model
+    pub fn text(content: String) -> Self {
+        Self {
+            content,
+            actions: Vec::new(),
+            diagnostics: Vec::new(),
+            citations: Vec::new(),
+            grounding: Grounding::NotChecked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiAction {
+    pub action_type: ActionType,
+    pub target: String,
+    pub value: Option<String>,
+    /// Business system for `execute` actions (e.g. "ris")
+    #[serde(default)]
+    pub system: Option<String>,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+    /// Assessment from `RiskEngine`, attached before the action leaves the backend
+    #[serde(default)]
+    pub risk: Option<RiskAssessment>,
+}
+
+pub use actions::{ActionDiagnostic, ActionType};
+pub use citations::{Citation, Grounding};
+pub use stream::AiState;
+
+pub struct AiClient {
+    provider: Box<dyn AiProvider>,
+    redactor: Option<Arc<Redactor>>,
+    meter: Option<UsageMeter>,
+}
+
+impl AiClient {
+    /// Create a FastGPT client
+    pub fn new(endpoint: String, api_key: String) -> Self {
+        let transport = Transport::new(&endpoint, ResilienceConfig::default());
+        Self::with_provider(Box::new(FastGptProvider::new(transport, endpoint, api_key)))
+    }
+
+    /// Create a client backed by a specific provider
+    pub fn with_provider(provider: Box<dyn AiProvider>) -> Self {
+        Self {
+            provider,
+            redactor: None,
+            meter: None,
+        }
+    }
+
+    /// Create a client from the AI section of the app configuration
+    ///
+    /// Redaction is enabled with a fresh, request-scoped vault; use
+    /// `with_redactor` to share a vault across a conversation.
+    pub fn from_config(config: &AiConfig) -> Self {
+        let redactor = config.redaction.enabled.then(|| Arc::new(Redactor::new(&config.redaction)));
+        Self::with_provider(provider::from_config(config)).with_redactor(redactor)
+    }
+
+    /// Replace the PHI redactor, e.g. with the one bound to a session
+    pub fn with_redactor(mut self, redactor: Option<Arc<Redactor>>) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// Name of the active provider
+    pub fn provider_name(&self) -> &'static str {
+        self.provider.name()
+    }
+
+    /// Send chat request to the configured provider
+    pub async fn chat(&self, request: AiRequest) -> Result<AiResponse, String> {
+        let content = self.complete(&request).await?;
+        Ok(Self::build_response(&content, &CitationSources::from_request(&request)))
+    }
+
+    /// Send a request through the provider with quotas enforced and PHI redacted
+    async fn complete(&self, request: &AiRequest) -> Result<String, String> {
+        let prompt_tokens = self.check_quota(request)?;
+        let started = Instant::now();
+        let result = match &self.redactor {
+            Some(redactor) => {
+                let redacted = redactor.redact_request(request);
+                self.provider
+                    .complete(&redacted)
+                    .await
+                    .map(|content| redactor.restore(&content))
+            }
+            None => self.provider.complete(request).await,
+        };
+        self.record_usage(prompt_tokens, &result, started);
+        result
+    }
+
+    /// Build the final response from the raw model output
+    fn build_response(content: &str, sources: &CitationSources) -> AiResponse {
+        // Parse actions from response
+        let mut parsed = Self::parse_actions(content);
+        capabilities::check_actions(&mut parsed);
+        assess_actions(&mut parsed.actions);
+        let (content, citations) = citations::extract(&parsed.content, sources);
+
+        info!(
+            "Received AI response with {} actions ({} rejected), {} citations",
+            parsed.actions.len(),
+            parsed.diagnostics.len(),
+            citations.len()
+        );
+
+        AiResponse {
+            content,
+            actions: parsed.actions,
+            diagnostics: parsed.diagnostics,
+            grounding: citations::grounding(&citations, sources),
+            citations,
+        }
+    }
+
+    /// Parse actions from AI response, stripping the action markup from the content
+    pub fn parse_actions(content: &str) -> actions::ParsedResponse {
+        actions::parse(content)
+    }
+}
+
+/// Attach a `RiskEngine` assessment to every action
+///
+/// Low risk actions may run automatically, medium risk ones need a preview
+/// and high risk ones an explicit confirmation (see `ExecutionMode`).
+pub fn assess_actions(actions: &mut [AiAction]) {
+    if actions.is_empty() {
+        return;
+    }
+    let engine = RiskEngine::shared();
+    for action in actions {
+        action.risk = Some(engine.assess(action.action_type.as_str(), &action.target));
+    }
+}
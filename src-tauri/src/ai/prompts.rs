@@ -0,0 +1,283 @@
+// Prompt templates - system prompts per business system and user role
+//
+// Templates live in prompts.json in the config directory and are reloaded
+// whenever the file changes, so prompts can be tuned without a new build.
+// A file that fails validation is rejected as a whole and the previously
+// loaded templates stay in use.
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use tracing::{error, info};
+
+use super::context::PageContext;
+use super::AiMessage;
+use crate::auth::AuthState;
+use crate::config::{AppConfig, BusinessSystem};
+use crate::permissions::Permission;
+
+/// Variables a template may reference as `{{name}}`
+pub const VARIABLES: &[&str] = &["system_name", "page", "role", "date"];
+
+const DEFAULT_TEMPLATE: &str = "你是{{system_name}}的智能助手，服务对象是医院的{{role}}。\
+     当前页面：{{page}}。今天是{{date}}。\
+     请用简洁、专业的中文回答；需要操作页面时使用动作指令，不要编造患者信息或检查结果。";
+
+/// A system prompt template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    pub id: String,
+    /// Bumped whenever the text changes, logged with every use
+    pub version: u32,
+    /// `BusinessSystem.id` this template applies to, any system when unset
+    #[serde(default)]
+    pub system: Option<String>,
+    /// User role this template applies to, any role when unset
+    #[serde(default)]
+    pub role: Option<String>,
+    pub template: String,
+}
+
+impl PromptTemplate {
+    fn builtin() -> Self {
+        Self {
+            id: "builtin".to_string(),
+            version: 1,
+            system: None,
+            role: None,
+            template: DEFAULT_TEMPLATE.to_string(),
+        }
+    }
+
+    /// Match quality for a system and role, `None` if the template does not apply
+    fn specificity(&self, system: Option<&str>, role: Option<&str>) -> Option<u8> {
+        let system_score = match self.system.as_deref() {
+            None => 0,
+            Some(s) if Some(s) == system => 2,
+            Some(_) => return None,
+        };
+        let role_score = match self.role.as_deref() {
+            None => 0,
+            Some(r) if role.is_some_and(|role| role.eq_ignore_ascii_case(r)) => 1,
+            Some(_) => return None,
+        };
+        Some(system_score + role_score)
+    }
+
+    pub fn render(&self, vars: &PromptVars) -> String {
+        let mut text = self.template.clone();
+        for (name, value) in [
+            ("system_name", &vars.system_name),
+            ("page", &vars.page),
+            ("role", &vars.role),
+            ("date", &vars.date),
+        ] {
+            text = text.replace(&format!("{{{{{}}}}}", name), value);
+        }
+        text
+    }
+}
+
+/// Values substituted into a template
+#[derive(Debug, Clone)]
+pub struct PromptVars {
+    pub system_name: String,
+    pub page: String,
+    pub role: String,
+    pub date: String,
+}
+
+impl PromptVars {
+    pub fn new(systems: &[BusinessSystem], page: Option<&PageContext>) -> Self {
+        let system = page.and_then(|p| p.page.system.as_deref());
+        let system_name = system
+            .and_then(|id| systems.iter().find(|s| s.id == id))
+            .map(|s| s.name.clone())
+            .unwrap_or_else(|| "医院信息系统".to_string());
+        let page_name = page
+            .map(|p| if p.page.title.is_empty() { p.page.path.clone() } else { p.page.title.clone() })
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "无".to_string());
+        let role = page
+            .and_then(|p| p.user.as_ref())
+            .and_then(|u| u.role.clone())
+            .unwrap_or_else(|| "医务人员".to_string());
+
+        Self {
+            system_name,
+            page: page_name,
+            role,
+            date: Local::now().format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+/// Contents of prompts.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptFile {
+    pub templates: Vec<PromptTemplate>,
+}
+
+/// Loaded templates plus what is needed to notice file changes
+#[derive(Debug)]
+pub struct PromptRegistry {
+    templates: Vec<PromptTemplate>,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl PromptRegistry {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            templates: Vec::new(),
+            path,
+            modified: None,
+        }
+    }
+
+    pub fn templates(&self) -> &[PromptTemplate] {
+        &self.templates
+    }
+
+    /// Reload the file if it changed since the last load
+    pub fn refresh(&mut self, systems: &[BusinessSystem]) {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        if modified.is_none() {
+            if !self.templates.is_empty() {
+                info!("Prompt file {} removed, using built-in prompt", self.path.display());
+            }
+            self.templates.clear();
+            return;
+        }
+        if let Err(e) = self.reload(systems) {
+            error!("{}", e);
+        }
+    }
+
+    /// Load and validate the file, keeping the current templates on failure
+    pub fn reload(&mut self, systems: &[BusinessSystem]) -> Result<(), String> {
+        let templates = load_templates(&self.path, systems)?;
+        info!("Loaded {} prompt templates from {}", templates.len(), self.path.display());
+        self.templates = templates;
+        Ok(())
+    }
+
+    /// Most specific template for a system and role; system beats role
+    pub fn select(&self, system: Option<&str>, role: Option<&str>) -> PromptTemplate {
+        self.templates
+            .iter()
+            .filter_map(|t| t.specificity(system, role).map(|score| (score, t)))
+            .max_by_key(|(score, _)| *score)
+            .map(|(_, t)| t.clone())
+            .unwrap_or_else(PromptTemplate::builtin)
+    }
+}
+
+fn load_templates(path: &Path, systems: &[BusinessSystem]) -> Result<Vec<PromptTemplate>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("无法读取提示词模板 {}: {}", path.display(), e))?;
+    let file: PromptFile = serde_json::from_str(&content)
+        .map_err(|e| format!("提示词模板格式错误 {}: {}", path.display(), e))?;
+    validate(&file.templates, systems)
+        .map_err(|e| format!("提示词模板无效 {}: {}", path.display(), e))?;
+    Ok(file.templates)
+}
+
+/// Check ids, selectors and placeholders of a template set
+pub fn validate(templates: &[PromptTemplate], systems: &[BusinessSystem]) -> Result<(), String> {
+    let mut ids = HashSet::new();
+    let mut selectors = HashSet::new();
+    for t in templates {
+        if t.id.trim().is_empty() {
+            return Err("模板缺少 id".to_string());
+        }
+        if !ids.insert(t.id.as_str()) {
+            return Err(format!("模板 id 重复: {}", t.id));
+        }
+        if t.version == 0 {
+            return Err(format!("模板 {} 的版本号必须大于 0", t.id));
+        }
+        if t.template.trim().is_empty() {
+            return Err(format!("模板 {} 内容为空", t.id));
+        }
+        if let Some(system) = &t.system {
+            if !systems.iter().any(|s| &s.id == system) {
+                return Err(format!("模板 {} 引用了未配置的系统: {}", t.id, system));
+            }
+        }
+        let selector = (t.system.clone(), t.role.as_deref().map(str::to_lowercase));
+        if !selectors.insert(selector) {
+            return Err(format!("模板 {} 与其他模板的适用系统和角色相同", t.id));
+        }
+        check_placeholders(&t.template).map_err(|e| format!("模板 {}: {}", t.id, e))?;
+    }
+    Ok(())
+}
+
+fn check_placeholders(template: &str) -> Result<(), String> {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or("存在未闭合的 {{")?;
+        let name = after[..end].trim();
+        if !VARIABLES.contains(&name) {
+            return Err(format!("未知变量 {{{{{}}}}}，可用变量: {}", name, VARIABLES.join(", ")));
+        }
+        rest = &after[end + 2..];
+    }
+    Ok(())
+}
+
+fn prompt_path() -> PathBuf {
+    AppConfig::config_dir().join("prompts.json")
+}
+
+fn registry() -> &'static Mutex<PromptRegistry> {
+    static REGISTRY: OnceLock<Mutex<PromptRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(PromptRegistry::new(prompt_path())))
+}
+
+/// Rendered system prompt and the template it came from
+#[derive(Debug, Clone)]
+pub struct SystemPrompt {
+    pub message: AiMessage,
+    /// "{id}@v{version}" of the template
+    pub version: String,
+}
+
+/// System prompt for a turn, picked by the page's business system and the user's role
+pub fn system_prompt(systems: &[BusinessSystem], page: Option<&PageContext>) -> SystemPrompt {
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.refresh(systems);
+
+    let system = page.and_then(|p| p.page.system.as_deref());
+    let role = page.and_then(|p| p.user.as_ref()).and_then(|u| u.role.as_deref());
+    let template = registry.select(system, role);
+    info!("Using prompt template {} v{}", template.id, template.version);
+
+    SystemPrompt {
+        message: AiMessage {
+            role: "system".to_string(),
+            content: template.render(&PromptVars::new(systems, page)),
+        },
+        version: format!("{}@v{}", template.id, template.version),
+    }
+}
+
+/// Tauri commands for prompt templates
+
+#[tauri::command]
+pub fn ai_reload_prompts(auth: tauri::State<AuthState>) -> Result<Vec<PromptTemplate>, String> {
+    auth.authorize("ai_reload_prompts", None, Permission::Admin)?;
+    let systems = AppConfig::load().business_systems;
+    let mut registry = registry().lock().map_err(|e| e.to_string())?;
+    registry.reload(&systems)?;
+    Ok(registry.templates().to_vec())
+}
@@ -0,0 +1,205 @@
+// FastGPT provider - /api/v1/chat/completion
+use async_trait::async_trait;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::Notify;
+
+use super::{openai_delta, read_sse, wire_messages, AiProvider, DeltaSink, Transport};
+use crate::ai::{AiMessage, AiRequest};
+
+const NAME: &str = "FastGPT";
+
+/// FastGPT API request structure
+#[derive(Debug, Serialize)]
+struct FastGPTRequest {
+    query: String,
+    history: Vec<AiMessage>,
+    stream: bool,
+}
+
+/// FastGPT API response structure
+#[derive(Debug, Deserialize)]
+struct FastGPTResponse {
+    #[serde(rename = "data")]
+    data: Option<FastGPTData>,
+    #[serde(default)]
+    choices: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FastGPTData {
+    #[serde(rename = "content")]
+    content: String,
+}
+
+pub struct FastGptProvider {
+    transport: Transport,
+    endpoint: String,
+    api_key: String,
+}
+
+impl FastGptProvider {
+    pub fn new(transport: Transport, endpoint: String, api_key: String) -> Self {
+        Self {
+            transport,
+            endpoint,
+            api_key,
+        }
+    }
+
+    fn build(&self, request: &AiRequest, stream: bool) -> RequestBuilder {
+        let url = format!("{}/api/v1/chat/completion", self.endpoint);
+
+        let mut history = Vec::with_capacity(request.messages.len() + 1);
+        // FastGPT has no separate context field; the context leads the history as for OpenAI
+        if let Some(context) = request.context.as_deref().filter(|c| !c.is_empty()) {
+            history.push(AiMessage {
+                role: "system".to_string(),
+                content: context.to_string(),
+            });
+        }
+        history.extend(wire_messages(&request.messages));
+        let last_message = history.pop()
+            .map(|m| m.content)
+            .unwrap_or_default();
+
+        let fastgpt_request = FastGPTRequest {
+            query: last_message,
+            history,
+            stream,
+        };
+
+        self.transport
+            .http
+            .post(url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Content-Type", "application/json")
+            .json(&fastgpt_request)
+    }
+}
+
+#[async_trait]
+impl AiProvider for FastGptProvider {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn complete(&self, request: &AiRequest) -> Result<String, String> {
+        let response = self.transport.send(NAME, self.build(request, false), None).await?;
+
+        let fastgpt_response: FastGPTResponse = response.json().await
+            .map_err(|e| e.to_string())?;
+
+        // Older deployments answer with `data.content`, newer ones with OpenAI-style `choices`
+        let content = fastgpt_response.data
+            .map(|d| d.content)
+            .or_else(|| {
+                fastgpt_response.choices.first()
+                    .and_then(|c| c.pointer("/message/content"))
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            })
+            .unwrap_or_default();
+
+        Ok(content)
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &AiRequest,
+        cancel: &Notify,
+        on_delta: DeltaSink<'_>,
+    ) -> Result<String, String> {
+        let builder = self.build(request, true).header("Accept", "text/event-stream");
+        let response = self.transport.send(NAME, builder, Some(cancel)).await?;
+        read_sse(NAME, response, cancel, on_delta, openai_delta).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::mock_server::{MockServer, Reply};
+    use crate::ai::resilience::ResilienceConfig;
+    use serde_json::json;
+
+    fn provider(server: &MockServer) -> FastGptProvider {
+        let transport = Transport::new(&server.url, ResilienceConfig::default());
+        FastGptProvider::new(transport, server.url.clone(), "fastgpt-key".to_string())
+    }
+
+    fn request() -> AiRequest {
+        AiRequest {
+            messages: vec![
+                AiMessage { role: "user".to_string(), content: "你好".to_string() },
+                AiMessage { role: "assistant".to_string(), content: "您好".to_string() },
+                AiMessage { role: "user".to_string(), content: "今天有几个检查？".to_string() },
+            ],
+            context: None,
+            tools: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_last_message_as_query() {
+        let server = MockServer::start(vec![Reply::Json(200, json!({ "data": { "content": "3个" } }))]).await;
+        let content = provider(&server).complete(&request()).await.unwrap();
+        assert_eq!(content, "3个");
+
+        let sent = &server.requests()[0];
+        assert_eq!(sent.path, "/api/v1/chat/completion");
+        assert_eq!(sent.header("authorization"), Some("Bearer fastgpt-key"));
+        assert_eq!(sent.body["query"], "今天有几个检查？");
+        assert_eq!(sent.body["stream"], false);
+        assert_eq!(sent.body["history"].as_array().unwrap().len(), 2);
+        assert_eq!(sent.body["history"][1]["role"], "assistant");
+    }
+
+    #[tokio::test]
+    async fn sends_context_as_leading_system_message() {
+        let server = MockServer::start(vec![Reply::Json(200, json!({ "data": { "content": "3个" } }))]).await;
+        let request = AiRequest {
+            context: Some("当前页面: RIS 检查列表\n引用页面数据时标注来源".to_string()),
+            ..request()
+        };
+        provider(&server).complete(&request).await.unwrap();
+
+        let body = &server.requests()[0].body;
+        assert_eq!(body["query"], "今天有几个检查？");
+        assert_eq!(
+            body["history"],
+            json!([
+                { "role": "system", "content": "当前页面: RIS 检查列表\n引用页面数据时标注来源" },
+                { "role": "user", "content": "你好" },
+                { "role": "assistant", "content": "您好" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_openai_style_choices() {
+        let body = json!({ "choices": [{ "message": { "role": "assistant", "content": "共5个" } }] });
+        let server = MockServer::start(vec![Reply::Json(200, body)]).await;
+        assert_eq!(provider(&server).complete(&request()).await.unwrap(), "共5个");
+    }
+
+    #[tokio::test]
+    async fn streams_events_split_across_chunks() {
+        let server = MockServer::start(vec![Reply::Chunks(vec![
+            "data: {\"choices\":[{\"delta\":{\"content\":\"共\"}}]}\n\ndata: {\"choi".to_string(),
+            "ces\":[{\"delta\":{\"content\":\"5个\"}}]}\n\n".to_string(),
+            "data: [DONE]\n\ndata: {\"choices\":[{\"delta\":{\"content\":\"多余\"}}]}\n\n".to_string(),
+        ])])
+        .await;
+
+        let mut deltas = Vec::new();
+        let content = provider(&server)
+            .complete_stream(&request(), &Notify::new(), &mut |d: &str| deltas.push(d.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(content, "共5个");
+        assert_eq!(deltas, vec!["共", "5个"]);
+        assert_eq!(server.requests()[0].body["stream"], true);
+    }
+}
@@ -0,0 +1,399 @@
+// Record/replay provider - captures AI traffic to a fixture file and plays it back
+//
+// Recording wraps the configured backend and appends every successful
+// exchange to the fixture file. Replay answers from that file without any
+// network access, so prompt and parser changes can be checked against
+// recorded scenarios. Exchanges are captured below the redaction layer and
+// therefore contain placeholders instead of patient data.
+use async_trait::async_trait;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use super::{cancelled, AiProvider, DeltaSink};
+use crate::ai::{actions, AiAction, AiRequest};
+
+/// Environment variable overriding the configured fixture mode
+pub const MODE_ENV: &str = "EW_AI_FIXTURE_MODE";
+/// Environment variable overriding the configured fixture file
+pub const PATH_ENV: &str = "EW_AI_FIXTURE_PATH";
+
+/// Characters per streamed delta during replay
+const REPLAY_CHUNK_CHARS: usize = 8;
+
+/// Whether AI traffic is recorded or replayed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureMode {
+    #[default]
+    Off,
+    Record,
+    Replay,
+}
+
+/// Record/replay settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FixtureConfig {
+    #[serde(default)]
+    pub mode: FixtureMode,
+    /// Fixture file, `ai_fixtures.json` in the data directory when unset
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+impl FixtureConfig {
+    /// Settings with `EW_AI_FIXTURE_MODE` / `EW_AI_FIXTURE_PATH` applied
+    pub fn resolved(&self) -> Self {
+        let mut config = self.clone();
+        if let Ok(mode) = std::env::var(MODE_ENV) {
+            match mode.trim().to_lowercase().as_str() {
+                "off" | "" => config.mode = FixtureMode::Off,
+                "record" => config.mode = FixtureMode::Record,
+                "replay" => config.mode = FixtureMode::Replay,
+                other => warn!("Ignoring unknown {} value: {}", MODE_ENV, other),
+            }
+        }
+        if let Ok(path) = std::env::var(PATH_ENV) {
+            if !path.trim().is_empty() {
+                config.path = Some(PathBuf::from(path.trim()));
+            }
+        }
+        config
+    }
+
+    pub fn fixture_path(&self) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| {
+            dirs::data_local_dir()
+                .unwrap_or_else(|| PathBuf::from("."))
+                .join("EWDesktopAgent")
+                .join("ai_fixtures.json")
+        })
+    }
+}
+
+/// One recorded request and the raw model output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    /// Normalized request used for matching
+    pub key: String,
+    pub provider: String,
+    pub request: AiRequest,
+    pub response: String,
+    /// Actions parsed from `response` when it was recorded
+    #[serde(default)]
+    pub actions: Vec<AiAction>,
+    pub recorded_at: String,
+}
+
+/// Contents of a fixture file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FixtureFile {
+    pub exchanges: Vec<Exchange>,
+}
+
+impl FixtureFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("无法读取 AI 回放文件 {}: {}", path.display(), e))?;
+        serde_json::from_str(&content)
+            .map_err(|e| format!("AI 回放文件格式错误 {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, content).map_err(|e| e.to_string())
+    }
+}
+
+/// Matching key of a request: roles and contents with whitespace collapsed
+/// and calendar dates masked, so re-runs on another day still match
+pub fn normalize_request(request: &AiRequest) -> String {
+    static DATE: OnceLock<Regex> = OnceLock::new();
+    let date = DATE.get_or_init(|| Regex::new(r"\d{4}-\d{2}-\d{2}").expect("valid date pattern"));
+    let normalize = |text: &str| {
+        let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        date.replace_all(&collapsed, "<date>").into_owned()
+    };
+
+    let mut key = String::new();
+    if let Some(context) = request.context.as_deref() {
+        key.push_str(&format!("context: {}\n", normalize(context)));
+    }
+    for message in &request.messages {
+        key.push_str(&format!("{}: {}\n", message.role, normalize(&message.content)));
+    }
+    key
+}
+
+/// Records every successful exchange of the wrapped provider
+pub struct RecordingProvider {
+    inner: Box<dyn AiProvider>,
+    path: PathBuf,
+    /// Serializes read-modify-write cycles on the fixture file
+    lock: Mutex<()>,
+}
+
+impl RecordingProvider {
+    pub fn new(inner: Box<dyn AiProvider>, path: PathBuf) -> Self {
+        info!("Recording {} traffic to {}", inner.name(), path.display());
+        Self {
+            inner,
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn record(&self, request: &AiRequest, response: &str) {
+        let exchange = Exchange {
+            key: normalize_request(request),
+            provider: self.inner.name().to_string(),
+            request: request.clone(),
+            response: response.to_string(),
+            actions: actions::parse(response).actions,
+            recorded_at: chrono::Utc::now().to_rfc3339(),
+        };
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = if self.path.exists() {
+            match FixtureFile::load(&self.path) {
+                Ok(file) => file,
+                Err(e) => {
+                    // Never overwrite a fixture file we could not read
+                    warn!("Exchange not recorded: {}", e);
+                    return;
+                }
+            }
+        } else {
+            FixtureFile::default()
+        };
+        file.exchanges.push(exchange);
+        if let Err(e) = file.save(&self.path) {
+            warn!("Failed to write AI fixture file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[async_trait]
+impl AiProvider for RecordingProvider {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn complete(&self, request: &AiRequest) -> Result<String, String> {
+        let content = self.inner.complete(request).await?;
+        self.record(request, &content);
+        Ok(content)
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &AiRequest,
+        cancel: &Notify,
+        on_delta: DeltaSink<'_>,
+    ) -> Result<String, String> {
+        let content = self.inner.complete_stream(request, cancel, on_delta).await?;
+        self.record(request, &content);
+        Ok(content)
+    }
+}
+
+/// Replay positions per fixture file, keyed by normalized request
+type Cursors = Arc<Mutex<HashMap<String, usize>>>;
+
+/// Shared replay positions for a fixture file
+///
+/// A provider is built for every command, so the positions live for the
+/// whole process; otherwise a repeated request would always get its first answer.
+fn cursors_for(path: &Path) -> Cursors {
+    static CURSORS: OnceLock<Mutex<HashMap<PathBuf, Cursors>>> = OnceLock::new();
+    let cursors = CURSORS.get_or_init(Default::default);
+    let mut cursors = match cursors.lock() {
+        Ok(cursors) => cursors,
+        Err(e) => e.into_inner(),
+    };
+    cursors.entry(path.to_path_buf()).or_default().clone()
+}
+
+/// Answers requests from a fixture file without network access
+///
+/// Exchanges with the same key are returned in recorded order; once they
+/// are used up the last one is repeated.
+pub struct ReplayProvider {
+    path: PathBuf,
+    exchanges: Result<HashMap<String, Vec<String>>, String>,
+    cursors: Cursors,
+}
+
+impl ReplayProvider {
+    pub fn new(path: PathBuf) -> Self {
+        let exchanges = FixtureFile::load(&path).map(|file| {
+            let mut exchanges: HashMap<String, Vec<String>> = HashMap::new();
+            for exchange in file.exchanges {
+                exchanges.entry(exchange.key).or_default().push(exchange.response);
+            }
+            exchanges
+        });
+        match &exchanges {
+            Ok(exchanges) => info!("Replaying {} AI requests from {}", exchanges.len(), path.display()),
+            Err(e) => warn!("{}", e),
+        }
+        Self {
+            cursors: cursors_for(&path),
+            path,
+            exchanges,
+        }
+    }
+
+    fn lookup(&self, request: &AiRequest) -> Result<String, String> {
+        let exchanges = self.exchanges.as_ref().map_err(Clone::clone)?;
+        let key = normalize_request(request);
+        let Some(responses) = exchanges.get(&key) else {
+            let last = request
+                .messages
+                .last()
+                .map(|m| m.content.chars().take(80).collect::<String>())
+                .unwrap_or_default();
+            warn!("No recorded AI exchange matches request:\n{}", key);
+            return Err(format!(
+                "AI 回放文件 {} 中没有与该请求匹配的记录（最后一条消息: {}）",
+                self.path.display(),
+                last
+            ));
+        };
+
+        let mut cursors = self.cursors.lock().map_err(|e| e.to_string())?;
+        let cursor = cursors.entry(key).or_insert(0);
+        let response = responses[(*cursor).min(responses.len() - 1)].clone();
+        *cursor += 1;
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl AiProvider for ReplayProvider {
+    fn name(&self) -> &'static str {
+        "Replay"
+    }
+
+    async fn complete(&self, request: &AiRequest) -> Result<String, String> {
+        self.lookup(request)
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &AiRequest,
+        cancel: &Notify,
+        on_delta: DeltaSink<'_>,
+    ) -> Result<String, String> {
+        let content = self.lookup(request)?;
+        // Stream in small pieces so markup split across deltas is exercised too
+        let chars: Vec<char> = content.chars().collect();
+        for chunk in chars.chunks(REPLAY_CHUNK_CHARS) {
+            let delta: String = chunk.iter().collect();
+            on_delta(&delta);
+            tokio::select! {
+                biased;
+                _ = cancel.notified() => return Err(cancelled()),
+                _ = tokio::task::yield_now() => {}
+            }
+        }
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{AiClient, AiMessage};
+
+    /// Backend answering with scripted replies in order
+    struct Scripted(Mutex<Vec<&'static str>>);
+
+    #[async_trait]
+    impl AiProvider for Scripted {
+        fn name(&self) -> &'static str {
+            "Scripted"
+        }
+
+        async fn complete(&self, _request: &AiRequest) -> Result<String, String> {
+            Ok(self.0.lock().unwrap().remove(0).to_string())
+        }
+
+        async fn complete_stream(
+            &self,
+            request: &AiRequest,
+            _cancel: &Notify,
+            _on_delta: DeltaSink<'_>,
+        ) -> Result<String, String> {
+            self.complete(request).await
+        }
+    }
+
+    fn turn(history: &mut Vec<AiMessage>, role: &str, content: &str) -> AiRequest {
+        history.push(AiMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        });
+        AiRequest {
+            messages: history.clone(),
+            context: Some("当前页面: RIS 2024-05-15".to_string()),
+            tools: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn recorded_conversation_replays_across_commands() {
+        let path = std::env::temp_dir().join(format!("ew-fixture-{}.json", uuid::Uuid::new_v4()));
+        let answers = vec!["共3个检查", "正在打开[action:navigate:https://ris.local/list]", "已刷新", "仍是3个"];
+
+        // Record: one conversation, the last request sent twice
+        let recorder = RecordingProvider::new(Box::new(Scripted(Mutex::new(answers.clone()))), path.clone());
+        let mut history = Vec::new();
+        let mut requests = Vec::new();
+        for (message, answer) in [("今天几个检查？", answers[0]), ("打开RIS", answers[1]), ("刷新", answers[2])] {
+            let request = turn(&mut history, "user", message);
+            assert_eq!(recorder.complete(&request).await.unwrap(), answer);
+            requests.push(request);
+            turn(&mut history, "assistant", answer);
+        }
+        assert_eq!(recorder.complete(&requests[2]).await.unwrap(), answers[3]);
+
+        let file = FixtureFile::load(&path).unwrap();
+        assert_eq!(file.exchanges.len(), 4);
+        assert_eq!(file.exchanges[1].actions.len(), 1);
+
+        // Replay on another day, with a new client per command as the app does
+        let replayed = |request: AiRequest| {
+            let path = path.clone();
+            async move {
+                let client = AiClient::with_provider(Box::new(ReplayProvider::new(path)));
+                let request = AiRequest {
+                    context: request.context.map(|c| c.replace("2024-05-15", "2025-01-02")),
+                    ..request
+                };
+                client.chat(request).await
+            }
+        };
+        assert_eq!(replayed(requests[0].clone()).await.unwrap().content, "共3个检查");
+        let navigate = replayed(requests[1].clone()).await.unwrap();
+        assert_eq!(navigate.actions.len(), 1);
+        assert_eq!(navigate.actions[0].target, "https://ris.local/list");
+        assert_eq!(navigate.content, "正在打开");
+        assert_eq!(replayed(requests[2].clone()).await.unwrap().content, "已刷新");
+        assert_eq!(replayed(requests[2].clone()).await.unwrap().content, "仍是3个");
+        // Used up: the last answer repeats
+        assert_eq!(replayed(requests[2].clone()).await.unwrap().content, "仍是3个");
+
+        let unknown = turn(&mut history, "user", "没有录制的问题");
+        assert!(replayed(unknown).await.is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
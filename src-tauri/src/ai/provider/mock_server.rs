@@ -0,0 +1,138 @@
+// Local HTTP server for provider and transport tests
+//
+// Each connection is answered with the next scripted reply; the last one is
+// repeated once the script runs out. Requests are recorded for inspection.
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Scripted answer to one request
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// Status and JSON body
+    Json(u16, Value),
+    /// Status, extra header lines and JSON body
+    JsonWithHeaders(u16, Vec<(&'static str, &'static str)>, Value),
+    /// Server-sent events, each element written separately with a pause in between
+    Chunks(Vec<String>),
+    /// Read the request and never answer
+    Hang,
+}
+
+/// A request as received by the server
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub path: String,
+    /// Header lines, names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Value,
+}
+
+impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl MockServer {
+    pub async fn start(script: Vec<Reply>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock server");
+        let url = format!("http://{}", listener.local_addr().expect("local addr"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                let reply = script[served.min(script.len() - 1)].clone();
+                served += 1;
+                tokio::spawn(serve(stream, reply, recorded.clone()));
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn serve(mut stream: TcpStream, reply: Reply, recorded: Arc<Mutex<Vec<Recorded>>>) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    recorded.lock().unwrap().push(request);
+
+    match reply {
+        Reply::Json(status, body) => write_json(&mut stream, status, &[], body).await,
+        Reply::JsonWithHeaders(status, headers, body) => write_json(&mut stream, status, &headers, body).await,
+        Reply::Chunks(chunks) => {
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n";
+            let _ = stream.write_all(head.as_bytes()).await;
+            for chunk in chunks {
+                let _ = stream.write_all(chunk.as_bytes()).await;
+                let _ = stream.flush().await;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+        Reply::Hang => std::future::pending::<()>().await,
+    }
+    let _ = stream.shutdown().await;
+}
+
+async fn write_json(stream: &mut TcpStream, status: u16, headers: &[(&str, &str)], body: Value) {
+    let body = body.to_string();
+    let extra: String = headers.iter().map(|(n, v)| format!("{}: {}\r\n", n, v)).collect();
+    let head = format!(
+        "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n",
+        status,
+        body.len(),
+        extra
+    );
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(body.as_bytes()).await;
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Recorded> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        data.extend_from_slice(&buf[..n]);
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+    let mut lines = head.lines();
+    let path = lines.next()?.split_whitespace().nth(1)?.to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|l| l.split_once(':'))
+        .map(|(n, v)| (n.trim().to_lowercase(), v.trim().to_string()))
+        .collect();
+    let length = headers
+        .iter()
+        .find(|(n, _)| n == "content-length")
+        .and_then(|(_, v)| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    while data.len() < head_end + length {
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let body = serde_json::from_slice(&data[head_end..]).unwrap_or(Value::Null);
+    Some(Recorded { path, headers, body })
+}
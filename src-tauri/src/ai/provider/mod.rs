@@ -0,0 +1,443 @@
+// AI providers - backend-specific request/response handling
+pub mod fastgpt;
+pub mod fixture;
+pub mod openai;
+#[cfg(test)]
+pub(crate) mod mock_server;
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, warn};
+
+use super::resilience::{breaker_for, CircuitBreaker, ResilienceConfig};
+use super::{AiMessage, AiRequest, TOOL_ROLE};
+use crate::config::AiConfig;
+
+pub use fastgpt::FastGptProvider;
+pub use fixture::{FixtureConfig, FixtureMode, RecordingProvider, ReplayProvider};
+pub use openai::OpenAiProvider;
+
+/// Callback receiving streamed text deltas
+pub type DeltaSink<'a> = &'a mut (dyn FnMut(&str) + Send);
+
+/// Chat backend that `AiClient` dispatches to
+///
+/// Providers return the raw model output; action parsing and markup
+/// filtering are done by `AiClient` so they behave the same for every backend.
+#[async_trait]
+pub trait AiProvider: Send + Sync {
+    /// Short provider name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Send a chat request and wait for the full answer
+    async fn complete(&self, request: &AiRequest) -> Result<String, String>;
+
+    /// Send a chat request in streaming mode, forwarding raw deltas to `on_delta`
+    async fn complete_stream(
+        &self,
+        request: &AiRequest,
+        cancel: &Notify,
+        on_delta: DeltaSink<'_>,
+    ) -> Result<String, String>;
+}
+
+/// Provider kind selected per deployment
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+pub enum ProviderKind {
+    #[default]
+    #[serde(rename = "fastgpt")]
+    FastGpt,
+    /// OpenAI-compatible `/chat/completions` servers (vLLM, Ollama, LocalAI, ...)
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+/// Build the provider configured for this deployment, honouring record/replay mode
+pub fn from_config(config: &AiConfig) -> Box<dyn AiProvider> {
+    let fixtures = config.fixtures.resolved();
+    match fixtures.mode {
+        FixtureMode::Off => backend(config),
+        FixtureMode::Record => Box::new(RecordingProvider::new(backend(config), fixtures.fixture_path())),
+        FixtureMode::Replay => Box::new(ReplayProvider::new(fixtures.fixture_path())),
+    }
+}
+
+fn backend(config: &AiConfig) -> Box<dyn AiProvider> {
+    let transport = Transport::new(&config.endpoint, config.resilience.clone());
+    match config.provider {
+        ProviderKind::FastGpt => Box::new(FastGptProvider::new(
+            transport,
+            config.endpoint.clone(),
+            config.api_key.clone(),
+        )),
+        ProviderKind::OpenAi => Box::new(OpenAiProvider::new(
+            transport,
+            config.endpoint.clone(),
+            config.api_key.clone(),
+            config.model.clone().unwrap_or_default(),
+        )),
+    }
+}
+
+/// Messages as sent on the wire
+///
+/// Neither backend has tool call ids for our action results, so tool
+/// messages are sent as user messages with a marker prefix.
+pub(crate) fn wire_messages(messages: &[AiMessage]) -> Vec<AiMessage> {
+    messages
+        .iter()
+        .map(|m| {
+            if m.role == TOOL_ROLE {
+                AiMessage {
+                    role: "user".to_string(),
+                    content: format!("[动作执行结果]\n{}", m.content),
+                }
+            } else {
+                m.clone()
+            }
+        })
+        .collect()
+}
+
+/// Error message used when a request is cancelled
+pub(crate) fn cancelled() -> String {
+    "请求已取消".to_string()
+}
+
+/// HTTP client with the configured connect and read timeouts
+pub(crate) fn http_client(config: &ResilienceConfig) -> Client {
+    Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+        .read_timeout(Duration::from_secs(config.read_timeout_secs))
+        .build()
+        .unwrap_or_else(|e| {
+            warn!("Failed to build HTTP client, using defaults: {}", e);
+            Client::new()
+        })
+}
+
+/// HTTP client with timeouts, retries and the endpoint's circuit breaker
+pub struct Transport {
+    pub http: Client,
+    config: ResilienceConfig,
+    breaker: Arc<CircuitBreaker>,
+}
+
+/// Outcome of a single failed attempt
+enum Failure {
+    /// Backend unreachable or overloaded, worth retrying
+    Transient(String),
+    /// The backend answered with an error; it is up but retrying will not help
+    Rejected(String),
+    Cancelled,
+}
+
+impl Transport {
+    pub fn new(endpoint: &str, config: ResilienceConfig) -> Self {
+        Self {
+            http: http_client(&config),
+            config,
+            breaker: breaker_for(endpoint),
+        }
+    }
+
+    /// Send a request, retrying transient failures with jittered backoff
+    ///
+    /// Only the request itself is retried; a stream that fails after the
+    /// response arrived is reported to the caller as is.
+    pub async fn send(
+        &self,
+        provider: &str,
+        builder: RequestBuilder,
+        cancel: Option<&Notify>,
+    ) -> Result<Response, String> {
+        self.breaker.acquire()?;
+
+        let mut attempt = 0;
+        loop {
+            let Some(request) = builder.try_clone() else {
+                self.breaker.release();
+                return Err("Request body cannot be resent".to_string());
+            };
+
+            let error = match self.attempt(provider, request, cancel).await {
+                Ok(response) => {
+                    self.breaker.record_success();
+                    return Ok(response);
+                }
+                Err(Failure::Rejected(e)) => {
+                    self.breaker.record_success();
+                    return Err(e);
+                }
+                Err(Failure::Cancelled) => {
+                    self.breaker.release();
+                    return Err(cancelled());
+                }
+                Err(Failure::Transient(e)) => e,
+            };
+
+            if attempt >= self.config.max_retries {
+                self.breaker.record_failure(&self.config);
+                return Err(error);
+            }
+            attempt += 1;
+            let delay = self.config.backoff(attempt);
+            warn!("{} request failed ({}), retry {} in {:?}", provider, error, attempt, delay);
+
+            match cancel {
+                Some(cancel) => tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = cancel.notified() => {
+                        self.breaker.release();
+                        return Err(cancelled());
+                    }
+                },
+                None => tokio::time::sleep(delay).await,
+            }
+        }
+    }
+
+    /// Send once, mapping transport and HTTP errors to messages
+    async fn attempt(
+        &self,
+        provider: &str,
+        request: RequestBuilder,
+        cancel: Option<&Notify>,
+    ) -> Result<Response, Failure> {
+        let send = request.send();
+        let result = match cancel {
+            Some(cancel) => tokio::select! {
+                result = send => result,
+                _ = cancel.notified() => return Err(Failure::Cancelled),
+            },
+            None => send.await,
+        };
+
+        let response = result.map_err(|e| {
+            error!("{} API request failed: {}", provider, e);
+            if e.is_timeout() {
+                Failure::Transient("AI 服务响应超时".to_string())
+            } else if e.is_connect() || e.is_request() {
+                Failure::Transient(e.to_string())
+            } else {
+                Failure::Rejected(e.to_string())
+            }
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            error!("{} API error: {} - {}", provider, status, error_text);
+            let message = format!("API error: {}", status);
+            return Err(if status.is_server_error() || status.as_u16() == 429 {
+                Failure::Transient(message)
+            } else {
+                Failure::Rejected(message)
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+/// Read an SSE response, passing each extracted delta to `on_delta`
+///
+/// Returns the concatenated raw content once `[DONE]` or end of stream is reached.
+pub(crate) async fn read_sse(
+    provider: &str,
+    mut response: Response,
+    cancel: &Notify,
+    on_delta: DeltaSink<'_>,
+    extract: fn(&Value) -> Option<String>,
+) -> Result<String, String> {
+    let mut decoder = SseDecoder::default();
+    let mut content = String::new();
+
+    loop {
+        let chunk = tokio::select! {
+            chunk = response.chunk() => chunk.map_err(|e| {
+                error!("{} stream interrupted: {}", provider, e);
+                e.to_string()
+            })?,
+            _ = cancel.notified() => return Err(cancelled()),
+        };
+        let Some(chunk) = chunk else {
+            return Ok(content);
+        };
+
+        for payload in decoder.push(&chunk) {
+            if payload == "[DONE]" {
+                return Ok(content);
+            }
+            let Some(delta) = serde_json::from_str(&payload).ok().and_then(|v| extract(&v)) else {
+                continue;
+            };
+            content.push_str(&delta);
+            on_delta(&delta);
+        }
+    }
+}
+
+/// Delta text of an OpenAI-style completion chunk
+pub(crate) fn openai_delta(chunk: &Value) -> Option<String> {
+    chunk
+        .pointer("/choices/0/delta/content")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+/// Line-oriented SSE decoder that tolerates chunks split mid-line or mid-character
+#[derive(Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Feed raw bytes and return the `data:` payloads of all complete lines
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut payloads = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(data) = line.trim().strip_prefix("data:") {
+                payloads.push(data.trim().to_string());
+            }
+        }
+        payloads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock_server::{MockServer, Reply};
+    use super::*;
+    use crate::ai::resilience::ConnectionState;
+    use serde_json::json;
+    use std::time::Instant;
+
+    #[test]
+    fn tool_messages_are_sent_as_user_messages() {
+        let messages = vec![
+            AiMessage { role: "assistant".to_string(), content: "查询中".to_string() },
+            AiMessage { role: TOOL_ROLE.to_string(), content: "{\"results\":[]}".to_string() },
+        ];
+        let wire = wire_messages(&messages);
+        assert_eq!(wire[0].role, "assistant");
+        assert_eq!(wire[0].content, "查询中");
+        assert_eq!(wire[1].role, "user");
+        assert_eq!(wire[1].content, "[动作执行结果]\n{\"results\":[]}");
+    }
+
+    #[test]
+    fn sse_decoder_joins_split_lines_and_characters() {
+        let mut decoder = SseDecoder::default();
+        let event = "data: {\"text\":\"检查\"}\n\n".as_bytes();
+        // Split inside the multi-byte "检"
+        let cut = event.iter().position(|&b| b >= 0x80).unwrap() + 1;
+        assert!(decoder.push(&event[..cut]).is_empty());
+        assert_eq!(decoder.push(&event[cut..]), vec!["{\"text\":\"检查\"}"]);
+        assert_eq!(decoder.push(b": keep-alive\ndata: [DONE]\n"), vec!["[DONE]"]);
+    }
+
+    /// Fast settings; every mock server has its own port and so its own breaker
+    fn resilience(max_retries: u32, failure_threshold: u32) -> ResilienceConfig {
+        ResilienceConfig {
+            connect_timeout_secs: 1,
+            read_timeout_secs: 1,
+            max_retries,
+            backoff_base_ms: 1,
+            backoff_max_ms: 5,
+            failure_threshold,
+            cooldown_secs: 60,
+        }
+    }
+
+    async fn send(transport: &Transport, url: &str) -> Result<Response, String> {
+        let builder = transport.http.post(format!("{}/chat", url)).json(&json!({}));
+        transport.send("Test", builder, None).await
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let server = MockServer::start(vec![
+            Reply::Json(503, json!({})),
+            Reply::Json(500, json!({})),
+            Reply::Json(200, json!({"ok": true})),
+        ])
+        .await;
+        let transport = Transport::new(&server.url, resilience(2, 5));
+
+        let response = send(&transport, &server.url).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(server.requests().len(), 3);
+        assert_eq!(transport.breaker.status().state, ConnectionState::Online);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = MockServer::start(vec![Reply::Json(400, json!({})), Reply::Json(200, json!({}))]).await;
+        let transport = Transport::new(&server.url, resilience(2, 1));
+
+        let error = send(&transport, &server.url).await.unwrap_err();
+        assert_eq!(error, "API error: 400 Bad Request");
+        assert_eq!(server.requests().len(), 1);
+        // The backend answered, so it is not counted as offline
+        assert_eq!(transport.breaker.status().consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn read_timeout_is_retried_then_reported() {
+        let server = MockServer::start(vec![Reply::Hang]).await;
+        let transport = Transport::new(&server.url, resilience(1, 5));
+
+        let started = Instant::now();
+        let error = send(&transport, &server.url).await.unwrap_err();
+        assert_eq!(error, "AI 服务响应超时");
+        assert_eq!(server.requests().len(), 2);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(transport.breaker.status().consecutive_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_is_reported() {
+        // A listener whose accept queue is full drops further connection attempts
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut queued = Vec::new();
+        while let Ok(Ok(stream)) =
+            tokio::time::timeout(Duration::from_millis(200), tokio::net::TcpStream::connect(addr)).await
+        {
+            queued.push(stream);
+        }
+
+        let url = format!("http://{}", addr);
+        let transport = Transport::new(&url, resilience(0, 5));
+        let started = Instant::now();
+        let error = send(&transport, &url).await.unwrap_err();
+        assert_eq!(error, "AI 服务响应超时");
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[tokio::test]
+    async fn open_breaker_fails_fast() {
+        let server = MockServer::start(vec![Reply::Json(502, json!({}))]).await;
+        let transport = Transport::new(&server.url, resilience(0, 2));
+
+        assert_eq!(send(&transport, &server.url).await.unwrap_err(), "API error: 502 Bad Gateway");
+        assert_eq!(transport.breaker.status().state, ConnectionState::Online);
+        send(&transport, &server.url).await.unwrap_err();
+        assert_eq!(transport.breaker.status().state, ConnectionState::Offline);
+
+        let error = send(&transport, &server.url).await.unwrap_err();
+        assert_eq!(error, "AI 服务暂时不可用（离线），请稍后重试");
+        assert_eq!(server.requests().len(), 2);
+    }
+}
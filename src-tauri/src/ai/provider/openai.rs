@@ -0,0 +1,269 @@
+// OpenAI-compatible provider - {endpoint}/chat/completions
+//
+// Used for local inference servers; `endpoint` is the API base URL including
+// the version segment, e.g. `http://10.0.0.5:8000/v1`. Capabilities are
+// offered as tools on non-streaming requests; tool calls in the answer are
+// turned into `execute` action blocks for the shared action parser.
+use async_trait::async_trait;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tokio::sync::Notify;
+
+use super::{openai_delta, read_sse, wire_messages, AiProvider, DeltaSink, Transport};
+use crate::ai::capabilities::{capability_id, ToolDefinition};
+use crate::ai::{AiMessage, AiRequest};
+
+const NAME: &str = "OpenAI";
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<AiMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Tool<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: &'a ToolDefinition,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionResponse {
+    choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCall {
+    function: FunctionCall,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionCall {
+    name: String,
+    /// JSON-encoded arguments
+    #[serde(default)]
+    arguments: String,
+}
+
+impl ResponseMessage {
+    /// Answer text with tool calls appended as an action block
+    fn into_content(self) -> String {
+        let content = self.content.unwrap_or_default();
+        if self.tool_calls.is_empty() {
+            return content;
+        }
+        let actions: Vec<Value> = self
+            .tool_calls
+            .iter()
+            .map(|call| {
+                let id = capability_id(&call.function.name);
+                let params = match call.function.arguments.trim() {
+                    "" => Value::Object(Map::new()),
+                    // Unparseable arguments end up as an action diagnostic
+                    args => serde_json::from_str(args).unwrap_or_else(|_| Value::String(args.to_string())),
+                };
+                json!({
+                    "type": "execute",
+                    "system": id.split('.').next(),
+                    "capability": id,
+                    "params": params,
+                })
+            })
+            .collect();
+        format!("{}\n```json\n{}\n```", content, json!({ "actions": actions }))
+    }
+}
+
+pub struct OpenAiProvider {
+    transport: Transport,
+    endpoint: String,
+    api_key: String,
+    model: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(transport: Transport, endpoint: String, api_key: String, model: String) -> Self {
+        Self {
+            transport,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+        }
+    }
+
+    fn build(&self, request: &AiRequest, stream: bool) -> RequestBuilder {
+        let url = format!("{}/chat/completions", self.endpoint);
+
+        let mut messages = Vec::with_capacity(request.messages.len() + 1);
+        if let Some(context) = request.context.as_deref().filter(|c| !c.is_empty()) {
+            messages.push(AiMessage {
+                role: "system".to_string(),
+                content: context.to_string(),
+            });
+        }
+        messages.extend(wire_messages(&request.messages));
+
+        let body = ChatCompletionRequest {
+            model: &self.model,
+            messages,
+            stream,
+            // Streamed tool calls are not assembled, so tools are only offered without streaming
+            tools: if stream {
+                Vec::new()
+            } else {
+                request.tools.iter().map(|function| Tool { kind: "function", function }).collect()
+            },
+        };
+
+        let builder = self.transport.http.post(url).json(&body);
+        // Local servers usually run without authentication
+        if self.api_key.is_empty() {
+            builder
+        } else {
+            builder.bearer_auth(&self.api_key)
+        }
+    }
+}
+
+#[async_trait]
+impl AiProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn complete(&self, request: &AiRequest) -> Result<String, String> {
+        let response = self.transport.send(NAME, self.build(request, false), None).await?;
+
+        let completion: ChatCompletionResponse = response.json().await
+            .map_err(|e| e.to_string())?;
+
+        completion.choices
+            .into_iter()
+            .next()
+            .map(|c| c.message.into_content())
+            .ok_or_else(|| "Empty completion response".to_string())
+    }
+
+    async fn complete_stream(
+        &self,
+        request: &AiRequest,
+        cancel: &Notify,
+        on_delta: DeltaSink<'_>,
+    ) -> Result<String, String> {
+        let builder = self.build(request, true).header("Accept", "text/event-stream");
+        let response = self.transport.send(NAME, builder, Some(cancel)).await?;
+        read_sse(NAME, response, cancel, on_delta, openai_delta).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::mock_server::{MockServer, Reply};
+    use crate::ai::resilience::ResilienceConfig;
+    use crate::ai::TOOL_ROLE;
+
+    fn provider(server: &MockServer, api_key: &str) -> OpenAiProvider {
+        let transport = Transport::new(&server.url, ResilienceConfig::default());
+        OpenAiProvider::new(transport, format!("{}/v1/", server.url), api_key.to_string(), "qwen2-7b".to_string())
+    }
+
+    fn request() -> AiRequest {
+        AiRequest {
+            messages: vec![
+                AiMessage { role: "user".to_string(), content: "查一下患者".to_string() },
+                AiMessage { role: TOOL_ROLE.to_string(), content: "{}".to_string() },
+            ],
+            context: Some("当前页面: RIS".to_string()),
+            tools: vec![ToolDefinition {
+                name: "ris__query__patient".to_string(),
+                description: "查询患者".to_string(),
+                parameters: json!({ "type": "object", "properties": {} }),
+            }],
+        }
+    }
+
+    fn answer(message: Value) -> Reply {
+        Reply::Json(200, json!({ "choices": [{ "message": message }] }))
+    }
+
+    #[tokio::test]
+    async fn sends_model_context_and_tools() {
+        let server = MockServer::start(vec![answer(json!({ "content": "好的" }))]).await;
+        assert_eq!(provider(&server, "").complete(&request()).await.unwrap(), "好的");
+
+        let sent = &server.requests()[0];
+        assert_eq!(sent.path, "/v1/chat/completions");
+        assert_eq!(sent.header("authorization"), None);
+        assert_eq!(sent.body["model"], "qwen2-7b");
+        assert_eq!(sent.body["stream"], false);
+        let messages = sent.body["messages"].as_array().unwrap();
+        assert_eq!(messages[0], json!({ "role": "system", "content": "当前页面: RIS" }));
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(sent.body["tools"][0]["type"], "function");
+        assert_eq!(sent.body["tools"][0]["function"]["name"], "ris__query__patient");
+    }
+
+    #[tokio::test]
+    async fn turns_tool_calls_into_action_block() {
+        let message = json!({
+            "content": null,
+            "tool_calls": [{ "function": { "name": "ris__query__patient", "arguments": "{\"id\":\"P1\"}" } }],
+        });
+        let server = MockServer::start(vec![answer(message)]).await;
+        let content = provider(&server, "sk-local").complete(&request()).await.unwrap();
+        assert_eq!(server.requests()[0].header("authorization"), Some("Bearer sk-local"));
+
+        let parsed = crate::ai::actions::parse(&content);
+        assert_eq!(parsed.actions.len(), 1);
+        assert_eq!(parsed.actions[0].target, "ris.query.patient");
+        assert_eq!(parsed.actions[0].system.as_deref(), Some("ris"));
+        assert_eq!(parsed.actions[0].params["id"], "P1");
+    }
+
+    #[tokio::test]
+    async fn streams_without_tools_until_done() {
+        let server = MockServer::start(vec![Reply::Chunks(vec![
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n".to_string(),
+            "\ndata: {\"choices\":[{\"delta\":{\"content\":\"患者\"}}]}\n\nda".to_string(),
+            "ta: {\"choices\":[{\"delta\":{\"content\":\"张三\"}}]}\n\ndata: [DONE]\n\n".to_string(),
+        ])])
+        .await;
+
+        let mut deltas = Vec::new();
+        let content = provider(&server, "")
+            .complete_stream(&request(), &Notify::new(), &mut |d: &str| deltas.push(d.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(content, "患者张三");
+        assert_eq!(deltas, vec!["患者", "张三"]);
+
+        let sent = &server.requests()[0];
+        assert_eq!(sent.body["stream"], true);
+        assert!(sent.body.get("tools").is_none());
+    }
+
+    #[tokio::test]
+    async fn empty_choices_are_an_error() {
+        let server = MockServer::start(vec![Reply::Json(200, json!({ "choices": [] }))]).await;
+        assert!(provider(&server, "").complete(&request()).await.is_err());
+    }
+}
@@ -0,0 +1,368 @@
+// PHI redaction - replaces patient identifiers with placeholders before prompts leave the workstation
+//
+// The placeholder <-> value mapping is kept in memory for the lifetime of a
+// conversation only and is never written to disk.
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+use super::{AiMessage, AiRequest};
+
+/// Placeholders emitted by the redactor, e.g. `[ID_CARD_1]`
+const PLACEHOLDER_PATTERN: &str = r"\[[A-Z][A-Z_]*_\d+\]";
+
+/// Upper bound for holding back a possible partial placeholder while streaming
+const MAX_PLACEHOLDER_LEN: usize = 48;
+
+/// Detection rule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionRule {
+    /// Identifier kind, used as the placeholder prefix (e.g. "id_card" -> `[ID_CARD_1]`)
+    pub name: String,
+    /// Regular expression; if it has a capture group, only group 1 is replaced
+    pub pattern: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+/// Redaction settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    pub rules: Vec<RedactionRule>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        let rule = |name: &str, pattern: &str| RedactionRule {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            enabled: true,
+        };
+        Self {
+            enabled: true,
+            rules: vec![
+                // 18-digit resident ID card number, last digit may be X
+                rule(
+                    "id_card",
+                    r"[1-9]\d{5}(?:18|19|20)\d{2}(?:0[1-9]|1[0-2])(?:0[1-9]|[12]\d|3[01])\d{3}[\dXx]",
+                ),
+                // Mainland mobile number, optionally with +86 prefix
+                rule("phone", r"(?:\+?86[- ]?)?1[3-9]\d{9}"),
+                // Landline with area code, e.g. 010-12345678
+                rule("phone", r"0\d{2,3}-\d{7,8}"),
+                // Medical record / admission / outpatient numbers following a label
+                rule(
+                    "record_no",
+                    r"(?:病历号|病案号|住院号|门诊号|就诊号|检查号|登记号|MRN)\s*[:：]?\s*([A-Za-z0-9][A-Za-z0-9-]{3,19})",
+                ),
+                // Patient name following a label
+                rule(
+                    "patient_name",
+                    r"(?:患者|病人|姓名)\s*[:：]?\s*([\p{Han}&&[^的做检最近今昨本上这那吗呢有在是和及与]]{2,4})",
+                ),
+            ],
+        }
+    }
+}
+
+struct CompiledRule {
+    prefix: String,
+    regex: Regex,
+}
+
+#[derive(Default)]
+struct Vault {
+    /// value -> placeholder
+    placeholders: HashMap<String, String>,
+    /// placeholder -> value
+    values: HashMap<String, String>,
+    /// prefix -> last used index
+    counters: HashMap<String, u32>,
+}
+
+impl Vault {
+    fn placeholder_for(&mut self, prefix: &str, value: &str) -> String {
+        if let Some(existing) = self.placeholders.get(value) {
+            return existing.clone();
+        }
+        let counter = self.counters.entry(prefix.to_string()).or_insert(0);
+        *counter += 1;
+        let placeholder = format!("[{}_{}]", prefix, counter);
+        self.placeholders.insert(value.to_string(), placeholder.clone());
+        self.values.insert(placeholder.clone(), value.to_string());
+        placeholder
+    }
+}
+
+/// Redacts identifiers with stable placeholders and restores them in answers
+pub struct Redactor {
+    rules: Vec<CompiledRule>,
+    placeholder: Regex,
+    vault: Mutex<Vault>,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig) -> Self {
+        let rules = config
+            .rules
+            .iter()
+            .filter(|rule| config.enabled && rule.enabled)
+            .filter_map(|rule| match Regex::new(&rule.pattern) {
+                Ok(regex) => Some(CompiledRule {
+                    prefix: placeholder_prefix(&rule.name),
+                    regex,
+                }),
+                Err(e) => {
+                    warn!("Skipping invalid redaction rule {}: {}", rule.name, e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            rules,
+            placeholder: Regex::new(PLACEHOLDER_PATTERN).expect("valid placeholder pattern"),
+            vault: Mutex::new(Vault::default()),
+        }
+    }
+
+    /// Register a value known to be PHI, e.g. the current patient's name from the page
+    pub fn add_known(&self, kind: &str, value: &str) {
+        let value = value.trim();
+        if value.is_empty() {
+            return;
+        }
+        if let Ok(mut vault) = self.vault.lock() {
+            vault.placeholder_for(&placeholder_prefix(kind), value);
+        }
+    }
+
+    /// Replace all detected identifiers in `text`
+    pub fn redact(&self, text: &str) -> String {
+        let Ok(mut vault) = self.vault.lock() else {
+            return text.to_string();
+        };
+
+        let mut text = text.to_string();
+        for rule in &self.rules {
+            let mut out = String::with_capacity(text.len());
+            let mut last = 0;
+            for caps in rule.regex.captures_iter(&text) {
+                let Some(m) = caps.get(1).or_else(|| caps.get(0)) else {
+                    continue;
+                };
+                if !is_standalone(&text, m.start(), m.end()) {
+                    continue;
+                }
+                out.push_str(&text[last..m.start()]);
+                out.push_str(&vault.placeholder_for(&rule.prefix, m.as_str()));
+                last = m.end();
+            }
+            out.push_str(&text[last..]);
+            text = out;
+        }
+
+        // Then every known value, so a name detected once is hidden everywhere;
+        // longest first so overlapping values stay whole
+        let mut known: Vec<(&String, &String)> = vault.placeholders.iter().collect();
+        known.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+        for (value, placeholder) in known {
+            text = text.replace(value.as_str(), placeholder);
+        }
+        text
+    }
+
+    /// Redact every message and the context of a request
+    pub fn redact_request(&self, request: &AiRequest) -> AiRequest {
+        let redacted = AiRequest {
+            messages: request
+                .messages
+                .iter()
+                .map(|m| AiMessage {
+                    role: m.role.clone(),
+                    content: self.redact(&m.content),
+                })
+                .collect(),
+            context: request.context.as_deref().map(|c| self.redact(c)),
+            tools: request.tools.clone(),
+        };
+        info!("Redacted request, {} identifiers in session vault", self.len());
+        redacted
+    }
+
+    /// Put the real values back into model output
+    pub fn restore(&self, text: &str) -> String {
+        let Ok(vault) = self.vault.lock() else {
+            return text.to_string();
+        };
+        self.placeholder
+            .replace_all(text, |caps: &regex::Captures| {
+                let placeholder = &caps[0];
+                vault.values.get(placeholder).cloned().unwrap_or_else(|| placeholder.to_string())
+            })
+            .into_owned()
+    }
+
+    /// Number of identifiers mapped so far
+    pub fn len(&self) -> usize {
+        self.vault.lock().map(|v| v.values.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Placeholder prefix for a rule name, limited to what `PLACEHOLDER_PATTERN` matches
+///
+/// "id_card" -> "ID_CARD", "icd10" -> "ICD__"; names without letters fall back to "PHI".
+fn placeholder_prefix(name: &str) -> String {
+    let prefix: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_ascii_alphabetic() { c.to_ascii_uppercase() } else { '_' })
+        .skip_while(|c| *c == '_')
+        .collect();
+    if prefix.is_empty() {
+        "PHI".to_string()
+    } else {
+        prefix
+    }
+}
+
+/// Reject matches that are part of a longer alphanumeric token
+fn is_standalone(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    let first = text[start..end].chars().next();
+    let last = text[start..end].chars().next_back();
+
+    let glued = |edge: Option<char>, neighbour: Option<char>| {
+        matches!((edge, neighbour), (Some(e), Some(n)) if e.is_ascii_alphanumeric() && n.is_ascii_alphanumeric())
+    };
+    !glued(first, before) && !glued(last, after)
+}
+
+/// Restores placeholders in streamed text, holding back partial placeholders
+pub struct RestoreFilter<'a> {
+    redactor: &'a Redactor,
+    pending: String,
+}
+
+impl<'a> RestoreFilter<'a> {
+    pub fn new(redactor: &'a Redactor) -> Self {
+        Self {
+            redactor,
+            pending: String::new(),
+        }
+    }
+
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+        let hold_from = self.pending.rfind('[').filter(|&i| {
+            let tail = &self.pending[i..];
+            !tail.contains(']') && tail.len() < MAX_PLACEHOLDER_LEN
+        });
+        let cut = hold_from.unwrap_or(self.pending.len());
+        let ready: String = self.pending.drain(..cut).collect();
+        self.redactor.restore(&ready)
+    }
+
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        self.redactor.restore(&rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactionConfig::default())
+    }
+
+    #[test]
+    fn redacts_id_cards_including_check_digit_x() {
+        let redactor = redactor();
+        let text = redactor.redact("身份证 110105194912310021，家属 11010519491231002X");
+        assert_eq!(text, "身份证 [ID_CARD_1]，家属 [ID_CARD_2]");
+        assert_eq!(redactor.restore(&text), "身份证 110105194912310021，家属 11010519491231002X");
+    }
+
+    #[test]
+    fn redacts_mobile_and_landline_numbers() {
+        let redactor = redactor();
+        let text = redactor.redact("手机13812345678，备用+86 13912345678，座机010-12345678");
+        assert_eq!(text, "手机[PHONE_1]，备用[PHONE_2]，座机[PHONE_3]");
+    }
+
+    #[test]
+    fn redacts_labelled_record_numbers_and_names() {
+        let redactor = redactor();
+        let text = redactor.redact("患者张三的病历号：MR-20240001，住院号 ZY2024001");
+        assert_eq!(text, "患者[PATIENT_NAME_1]的病历号：[RECORD_NO_1]，住院号 [RECORD_NO_2]");
+        // Without a label a number is not a record number
+        assert_eq!(redactor.redact("检查编号 ZY2024002"), "检查编号 ZY2024002");
+    }
+
+    #[test]
+    fn numbers_inside_longer_tokens_are_kept() {
+        let redactor = redactor();
+        let text = "订单号 A213812345678901 与 9913812345678";
+        assert_eq!(redactor.redact(text), text);
+        assert!(redactor.is_empty());
+    }
+
+    #[test]
+    fn same_value_keeps_its_placeholder_across_turns() {
+        let redactor = redactor();
+        assert_eq!(redactor.redact("患者张三，电话13812345678"), "患者[PATIENT_NAME_1]，电话[PHONE_1]");
+        // Known values are hidden even where no rule matches
+        assert_eq!(redactor.redact("张三复查，再打13812345678"), "[PATIENT_NAME_1]复查，再打[PHONE_1]");
+        assert_eq!(redactor.len(), 2);
+
+        let answer = "[PATIENT_NAME_1]的电话是[PHONE_1]，[UNKNOWN_1]保持原样";
+        assert_eq!(redactor.restore(answer), "张三的电话是13812345678，[UNKNOWN_1]保持原样");
+    }
+
+    #[test]
+    fn rule_names_become_matching_prefixes() {
+        assert_eq!(placeholder_prefix("id_card"), "ID_CARD");
+        assert_eq!(placeholder_prefix("icd10"), "ICD__");
+        assert_eq!(placeholder_prefix("2nd-contact"), "ND_CONTACT");
+        assert_eq!(placeholder_prefix("病案"), "PHI");
+
+        let config = RedactionConfig {
+            enabled: true,
+            rules: vec![RedactionRule {
+                name: "icd10".to_string(),
+                pattern: r"ICD:(\w+)".to_string(),
+                enabled: true,
+            }],
+        };
+        let redactor = Redactor::new(&config);
+        let text = redactor.redact("诊断 ICD:C34");
+        assert_eq!(text, "诊断 ICD:[ICD___1]");
+        assert_eq!(redactor.restore(&text), "诊断 ICD:C34");
+    }
+
+    #[test]
+    fn restore_filter_joins_placeholders_split_across_deltas() {
+        let redactor = redactor();
+        redactor.add_known("patient_name", "张三");
+        let mut filter = RestoreFilter::new(&redactor);
+
+        assert_eq!(filter.push("已找到[PATIE"), "已找到");
+        assert_eq!(filter.push("NT_NAME_1]的报告"), "张三的报告");
+        assert_eq!(filter.push("，见[附件"), "，见");
+        assert_eq!(filter.finish(), "[附件");
+    }
+}
@@ -0,0 +1,261 @@
+// Resilience - timeouts, retry backoff and a circuit breaker for the AI backend
+//
+// Breakers are shared per endpoint for the whole process, so every `AiClient`
+// built from the config sees the same offline state.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::AppConfig;
+
+/// Timeout, retry and breaker settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResilienceConfig {
+    pub connect_timeout_secs: u64,
+    /// Maximum idle time between reads, also applies to streamed responses
+    pub read_timeout_secs: u64,
+    /// Retries after the first attempt for transient failures
+    pub max_retries: u32,
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64,
+    /// Consecutive failed requests before the breaker opens
+    pub failure_threshold: u32,
+    /// How long the breaker stays open before a probe request is let through
+    pub cooldown_secs: u64,
+}
+
+impl Default for ResilienceConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 5,
+            read_timeout_secs: 60,
+            max_retries: 2,
+            backoff_base_ms: 500,
+            backoff_max_ms: 8000,
+            failure_threshold: 5,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+impl ResilienceConfig {
+    /// Delay before retry number `attempt` (1-based): exponential with equal jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.backoff_base_ms.saturating_mul(1u64 << attempt.saturating_sub(1).min(16));
+        let capped = exp.min(self.backoff_max_ms);
+        let half = capped / 2;
+        let jitter = (uuid::Uuid::new_v4().as_u128() % (half as u128 + 1)) as u64;
+        Duration::from_millis(half + jitter)
+    }
+}
+
+/// Connection state shown in the UI
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Online,
+    /// Breaker is open, requests fail fast
+    Offline,
+    /// Cooldown elapsed, the next request probes the backend
+    Recovering,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionStatus {
+    pub state: ConnectionState,
+    pub consecutive_failures: u32,
+    /// Seconds until a probe request is allowed while offline
+    pub retry_in_secs: Option<u64>,
+}
+
+#[derive(Default)]
+struct BreakerInner {
+    failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+/// Circuit breaker guarding one backend endpoint
+#[derive(Default)]
+pub struct CircuitBreaker {
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    /// Ask to send a request; fails fast while the breaker is open
+    pub fn acquire(&self) -> Result<(), String> {
+        let mut inner = self.inner.lock().map_err(|e| e.to_string())?;
+        let Some(open_until) = inner.open_until else {
+            return Ok(());
+        };
+        // Half-open: let exactly one probe through
+        if Instant::now() >= open_until && !inner.probing {
+            inner.probing = true;
+            info!("AI backend breaker half-open, probing");
+            return Ok(());
+        }
+        Err("AI 服务暂时不可用（离线），请稍后重试".to_string())
+    }
+
+    /// The backend answered; close the breaker
+    pub fn record_success(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if inner.open_until.is_some() {
+                info!("AI backend back online");
+            }
+            *inner = BreakerInner::default();
+        }
+    }
+
+    /// The backend was unreachable or failed after all retries
+    pub fn record_failure(&self, config: &ResilienceConfig) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.failures += 1;
+            if inner.probing || inner.failures >= config.failure_threshold.max(1) {
+                warn!("AI backend offline after {} consecutive failures", inner.failures);
+                inner.open_until = Some(Instant::now() + Duration::from_secs(config.cooldown_secs));
+            }
+            inner.probing = false;
+        }
+    }
+
+    /// The request ended without telling anything about the backend (e.g. cancelled)
+    pub fn release(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.probing = false;
+        }
+    }
+
+    pub fn status(&self) -> ConnectionStatus {
+        let inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(e) => e.into_inner(),
+        };
+        let now = Instant::now();
+        let state = match inner.open_until {
+            None => ConnectionState::Online,
+            Some(until) if now < until => ConnectionState::Offline,
+            Some(_) => ConnectionState::Recovering,
+        };
+        ConnectionStatus {
+            state,
+            consecutive_failures: inner.failures,
+            retry_in_secs: inner
+                .open_until
+                .filter(|until| now < *until)
+                .map(|until| until.duration_since(now).as_secs().max(1)),
+        }
+    }
+}
+
+/// Shared breaker for an endpoint
+pub fn breaker_for(endpoint: &str) -> Arc<CircuitBreaker> {
+    static BREAKERS: OnceLock<Mutex<HashMap<String, Arc<CircuitBreaker>>>> = OnceLock::new();
+    let breakers = BREAKERS.get_or_init(Default::default);
+    let mut breakers = match breakers.lock() {
+        Ok(breakers) => breakers,
+        Err(e) => e.into_inner(),
+    };
+    breakers
+        .entry(endpoint.trim_end_matches('/').to_string())
+        .or_default()
+        .clone()
+}
+
+/// Tauri commands for connection state
+
+#[tauri::command]
+pub fn ai_connection_status() -> ConnectionStatus {
+    breaker_for(&AppConfig::load().ai.endpoint).status()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(cooldown_secs: u64) -> ResilienceConfig {
+        ResilienceConfig {
+            failure_threshold: 2,
+            cooldown_secs,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn backoff_grows_with_equal_jitter_up_to_the_cap() {
+        let config = ResilienceConfig {
+            backoff_base_ms: 100,
+            backoff_max_ms: 1000,
+            ..Default::default()
+        };
+        for (attempt, capped) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (40, 1000)] {
+            for _ in 0..50 {
+                let delay = config.backoff(attempt).as_millis() as u64;
+                assert!((capped / 2..=capped).contains(&delay), "attempt {}: {}ms", attempt, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn breaker_opens_after_threshold_and_fails_fast() {
+        let breaker = CircuitBreaker::default();
+        let config = config(60);
+
+        breaker.acquire().unwrap();
+        breaker.record_failure(&config);
+        assert_eq!(breaker.status().state, ConnectionState::Online);
+        breaker.acquire().unwrap();
+        breaker.record_failure(&config);
+
+        let status = breaker.status();
+        assert_eq!(status.state, ConnectionState::Offline);
+        assert_eq!(status.consecutive_failures, 2);
+        assert!(status.retry_in_secs.is_some_and(|secs| secs <= 60));
+        assert!(breaker.acquire().is_err());
+    }
+
+    #[test]
+    fn half_open_breaker_lets_one_probe_through_and_closes_on_success() {
+        let breaker = CircuitBreaker::default();
+        let config = config(0);
+        breaker.record_failure(&config);
+        breaker.record_failure(&config);
+        assert_eq!(breaker.status().state, ConnectionState::Recovering);
+
+        breaker.acquire().unwrap();
+        // Only one probe at a time
+        assert!(breaker.acquire().is_err());
+        breaker.record_success();
+
+        let status = breaker.status();
+        assert_eq!(status.state, ConnectionState::Online);
+        assert_eq!(status.consecutive_failures, 0);
+        breaker.acquire().unwrap();
+    }
+
+    #[test]
+    fn failed_or_abandoned_probe_reopens_or_frees_the_breaker() {
+        let breaker = CircuitBreaker::default();
+        breaker.record_failure(&config(0));
+        breaker.record_failure(&config(0));
+
+        breaker.acquire().unwrap();
+        breaker.release();
+        breaker.acquire().unwrap();
+
+        // A failed probe opens the breaker again at once
+        breaker.record_failure(&config(60));
+        assert_eq!(breaker.status().state, ConnectionState::Offline);
+        assert!(breaker.acquire().is_err());
+    }
+
+    #[test]
+    fn breakers_are_shared_per_endpoint() {
+        let a = breaker_for("http://breaker-test.invalid/v1/");
+        let b = breaker_for("http://breaker-test.invalid/v1");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &breaker_for("http://other-breaker-test.invalid/v1")));
+    }
+}
@@ -0,0 +1,280 @@
+// Assistant conversations - persisted in the assistant_sessions table
+use std::sync::Arc;
+use tauri::{AppHandle, Manager};
+use tracing::info;
+
+use super::cache::AnswerCache;
+use super::capabilities;
+use super::citations::CITATION_PROMPT;
+use super::context::{ContextUser, PageContext, PageSnapshot};
+use super::intent::IntentMatcher;
+use super::prompts;
+use super::redact::{RedactionConfig, Redactor};
+use super::slots::{self, PendingIntent, SlotTurn};
+use super::stream::emit_delta;
+use super::usage::UsageMeter;
+use super::{AiClient, AiMessage, AiRequest, AiResponse, AiState};
+use crate::auth::{AuthState, User};
+use crate::browser::BrowserState;
+use crate::config::{AiConfig, AppConfig, BusinessSystem};
+use crate::permissions;
+use crate::storage::{AssistantSession, Database};
+
+/// Decode the message list stored with a session
+pub fn session_messages(session: &AssistantSession) -> Result<Vec<AiMessage>, String> {
+    if session.messages.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(&session.messages).map_err(|e| e.to_string())
+}
+
+/// Load a session of the signed-in user or fail with a user-facing error
+pub fn load_conversation(auth: &AuthState, session_id: &str) -> Result<AssistantSession, String> {
+    let db = Database::open_default()?;
+    let session = db.load_session(session_id)?
+        .ok_or_else(|| format!("会话不存在: {}", session_id))?;
+    auth.user_for(Some(&session.user_id))?;
+    Ok(session)
+}
+
+/// Append messages to a session
+pub fn append_messages(session_id: &str, turn: &[AiMessage]) -> Result<(), String> {
+    Database::open_default()?.append_session_messages(session_id, turn)
+}
+
+/// Page context of the active tab for a conversation turn
+pub(crate) fn page_context(
+    browser: &BrowserState,
+    systems: &[BusinessSystem],
+    user: &User,
+    snapshot: Option<PageSnapshot>,
+) -> Result<Option<PageContext>, String> {
+    let user = ContextUser {
+        id: user.id.clone(),
+        role: Some(user.role.clone()),
+        ..Default::default()
+    };
+    PageContext::from_active_tab(browser, systems, Some(user), snapshot)
+}
+
+/// Client for a conversation turn: session-scoped redaction and usage accounting
+pub(crate) fn conversation_client(
+    state: &AiState,
+    config: &AiConfig,
+    session: &AssistantSession,
+    page: Option<&PageContext>,
+) -> AiClient {
+    let redactor = state.session_redactor(&session.id, &config.redaction);
+    // The page knows the patient even when the user only says "this patient"
+    if let (Some(redactor), Some(name)) = (&redactor, page.and_then(PageContext::patient_name)) {
+        redactor.add_known("patient_name", name);
+    }
+    let system = page.and_then(|p| p.page.system.clone());
+
+    AiClient::from_config(config)
+        .with_redactor(redactor)
+        .with_usage(UsageMeter::new(&session.user_id, system, &config.quota))
+}
+
+/// Messages to send for this turn, folding older history into the session summary
+pub(crate) async fn history_window(
+    client: &AiClient,
+    config: &AiConfig,
+    session: &mut AssistantSession,
+    messages: &[AiMessage],
+) -> Result<Vec<AiMessage>, String> {
+    let budget = config.history.budget_for(config.provider);
+    let summarized = session.summary_upto;
+    let window = client.window_history(session, messages, &budget).await;
+    if session.summary_upto != summarized {
+        Database::open_default()?.save_session(session)?;
+    }
+    Ok(window)
+}
+
+/// How a turn proceeds after slot filling
+pub(crate) enum TurnPlan {
+    /// Answer locally with a follow-up question or cancellation notice
+    Reply(String),
+    /// Call the model, with the confirmed parameters of a completed intent
+    Model { confirmed: Option<String> },
+}
+
+/// Run slot filling for a turn and persist the pending intent it leaves behind
+pub(crate) fn plan_turn(
+    session: &mut AssistantSession,
+    message: &str,
+    systems: &[BusinessSystem],
+    page: Option<&PageContext>,
+) -> Result<TurnPlan, String> {
+    let pending = PendingIntent::load(session);
+    let had_pending = session.pending_intent.is_some();
+    session.pending_intent = None;
+
+    let plan = match slots::advance(pending, message, &IntentMatcher::new(systems), page) {
+        SlotTurn::Ask { pending, question } => {
+            pending.store(session);
+            TurnPlan::Reply(question)
+        }
+        SlotTurn::Cancelled(notice) => TurnPlan::Reply(notice),
+        SlotTurn::Ready(intent) => TurnPlan::Model {
+            confirmed: Some(slots::confirmed_prompt(&intent)),
+        },
+        SlotTurn::Pass => TurnPlan::Model { confirmed: None },
+    };
+
+    if had_pending || session.pending_intent.is_some() {
+        Database::open_default()?.save_session(session)?;
+    }
+    Ok(plan)
+}
+
+/// Prompt context for a turn: the page, how to cite it and any confirmed intent parameters
+pub(crate) fn turn_context(page: Option<&PageContext>, confirmed: Option<String>) -> Option<String> {
+    let lines: Vec<String> = page
+        .map(|page| vec![page.to_prompt(), CITATION_PROMPT.to_string()])
+        .unwrap_or_default()
+        .into_iter()
+        .chain(confirmed)
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Deliver an answer produced without the model and store the turn
+fn local_answer(
+    app: &AppHandle,
+    session_id: &str,
+    user_message: AiMessage,
+    response: AiResponse,
+    stream_id: Option<&str>,
+) -> Result<AiResponse, String> {
+    if let Some(stream_id) = stream_id {
+        emit_delta(app, stream_id, &response.content);
+    }
+    append_messages(session_id, &[user_message, new_message("assistant", &response.content)])?;
+    Ok(response)
+}
+
+fn new_message(role: &str, content: &str) -> AiMessage {
+    AiMessage {
+        role: role.to_string(),
+        content: content.to_string(),
+    }
+}
+
+/// Tauri commands for assistant conversations
+
+#[tauri::command]
+pub fn ai_start_conversation(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+) -> Result<AssistantSession, String> {
+    let session = AssistantSession {
+        id: uuid::Uuid::new_v4().to_string(),
+        user_id: auth.user_for(user_id.as_deref())?.id,
+        messages: "[]".to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        summary: None,
+        summary_upto: 0,
+        pending_intent: None,
+    };
+
+    let db = Database::open_default()?;
+    db.save_session(&session)?;
+
+    info!("Started assistant conversation {} for {}", session.id, session.user_id);
+    Ok(session)
+}
+
+/// Send a message within a conversation
+///
+/// `page` is what the page bridge reports about the active tab. When
+/// `stream_id` is given the answer is streamed as `ai-delta` events and can
+/// be cancelled with `ai_cancel_stream`.
+#[tauri::command]
+pub async fn ai_send_message(
+    app: AppHandle,
+    state: tauri::State<'_, AiState>,
+    browser: tauri::State<'_, BrowserState>,
+    session_id: String,
+    message: String,
+    page: Option<PageSnapshot>,
+    stream_id: Option<String>,
+) -> Result<AiResponse, String> {
+    let auth = app.state::<AuthState>();
+    let user = auth.require_user()?;
+    let _turn = state.lock_turn(&session_id).await;
+    let mut session = load_conversation(&auth, &session_id)?;
+    let mut messages = session_messages(&session)?;
+    let user_message = new_message("user", &message);
+    messages.push(user_message.clone());
+
+    let config = AppConfig::load();
+    let page = page_context(&browser, &config.business_systems, &user, page)?;
+    let confirmed = match plan_turn(&mut session, &message, &config.business_systems, page.as_ref())? {
+        TurnPlan::Reply(reply) => {
+            return local_answer(&app, &session_id, user_message, AiResponse::text(reply), stream_id.as_deref());
+        }
+        TurnPlan::Model { confirmed } => confirmed,
+    };
+
+    let client = conversation_client(&state, &config.ai, &session, page.as_ref());
+    let prompt = prompts::system_prompt(&config.business_systems, page.as_ref());
+
+    // Informational questions may be answered from the local cache
+    let cache = AnswerCache::new(&config.ai.cache);
+    let phi_check = state.session_redactor(&session.id, &config.ai.redaction).unwrap_or_else(|| {
+        let rules = RedactionConfig {
+            enabled: true,
+            ..config.ai.redaction.clone()
+        };
+        Arc::new(Redactor::new(&rules))
+    });
+    let cache_slot = match confirmed {
+        Some(_) => None,
+        None => cache.slot(
+            &message,
+            &IntentMatcher::new(&config.business_systems).classify(&message),
+            &user.role,
+            page.as_ref().and_then(|p| p.page.system.as_deref()),
+            &prompt.version,
+            &phi_check,
+        ),
+    };
+    if let Some(response) = cache_slot.as_ref().and_then(|slot| cache.get(slot)) {
+        return local_answer(&app, &session_id, user_message, response, stream_id.as_deref());
+    }
+
+    let mut messages = history_window(&client, &config.ai, &mut session, &messages).await?;
+    messages.insert(0, prompt.message);
+    let request = AiRequest {
+        messages,
+        context: turn_context(page.as_ref(), confirmed),
+        tools: capabilities::tools(),
+    };
+
+    let mut response = match stream_id {
+        Some(stream_id) => {
+            let cancel = state.register(&stream_id)?;
+            let result = client
+                .chat_stream(request, &cancel, |delta| emit_delta(&app, &stream_id, delta))
+                .await;
+            state.unregister(&stream_id);
+            result?
+        }
+        None => client.chat(request).await?,
+    };
+    permissions::filter_actions(&auth, "ai_send_message", &mut response);
+
+    if let Some(slot) = &cache_slot {
+        cache.put(slot, &session.user_id, &response, &phi_check);
+    }
+    append_messages(&session_id, &[user_message, new_message("assistant", &response.content)])?;
+    Ok(response)
+}
+
+#[tauri::command]
+pub fn ai_get_history(auth: tauri::State<AuthState>, session_id: String) -> Result<Vec<AiMessage>, String> {
+    let session = load_conversation(&auth, &session_id)?;
+    session_messages(&session)
+}
@@ -0,0 +1,234 @@
+// Slot filling - asks for missing intent parameters before acting
+//
+// A request whose required parameters are unknown ("查一下那个患者的CT") is
+// kept on the session as a pending intent. Follow-up answers fill its slots
+// until the intent is complete, the user cancels or stops answering.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+
+use super::context::PageContext;
+use super::intent::{Intent, IntentKind, IntentMatcher, IntentSlots, RULE_CONFIDENCE_THRESHOLD};
+use crate::storage::AssistantSession;
+
+/// Unanswered follow-up questions before the pending intent is dropped
+const MAX_ATTEMPTS: u32 = 3;
+/// Rule matches below this confidence are answered by the model instead
+const MIN_CONFIDENCE: f32 = 0.6;
+
+const CANCEL_WORDS: &[&str] = &["取消", "算了", "不用了", "不要了", "不查了", "cancel"];
+
+/// Intent parameter the assistant can ask for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Slot {
+    System,
+    PatientName,
+    Tab,
+    FileOperation,
+    ReminderContent,
+}
+
+impl Slot {
+    fn value<'a>(&self, slots: &'a IntentSlots) -> Option<&'a str> {
+        match self {
+            Slot::System => slots.system.as_deref(),
+            Slot::PatientName => slots.patient_name.as_deref(),
+            Slot::Tab => slots.tab.as_deref(),
+            Slot::FileOperation => slots.file_operation.as_deref(),
+            Slot::ReminderContent => slots.reminder_content.as_deref(),
+        }
+    }
+
+    fn set(&self, slots: &mut IntentSlots, value: String) {
+        let slot = match self {
+            Slot::System => &mut slots.system,
+            Slot::PatientName => &mut slots.patient_name,
+            Slot::Tab => &mut slots.tab,
+            Slot::FileOperation => &mut slots.file_operation,
+            Slot::ReminderContent => &mut slots.reminder_content,
+        };
+        *slot = Some(value);
+    }
+
+    /// Follow-up question asking for this slot
+    fn question(&self, intent: &Intent) -> String {
+        match self {
+            Slot::PatientName => match &intent.slots.exam_type {
+                Some(exam) => format!("请问要查询哪位患者的{}？请告诉我患者姓名。", exam),
+                None => "请问要查询哪位患者？请告诉我患者姓名。".to_string(),
+            },
+            Slot::System if intent.kind == IntentKind::Navigate => "请问要打开哪个系统？".to_string(),
+            Slot::System => "请问要在哪个系统中查询？".to_string(),
+            Slot::Tab => "请问要切换到哪个标签页？可以说“下一个”、“上一个”或“第2个”。".to_string(),
+            Slot::FileOperation => "请问要对文件做什么操作？整理、移动、复制、删除还是重命名？".to_string(),
+            Slot::ReminderContent => "请问需要提醒您什么内容？".to_string(),
+        }
+    }
+}
+
+/// Parameters an intent needs before it can be acted on
+pub fn required_slots(kind: IntentKind) -> &'static [Slot] {
+    match kind {
+        IntentKind::Navigate => &[Slot::System],
+        IntentKind::SearchPatient => &[Slot::PatientName, Slot::System],
+        IntentKind::SwitchTab => &[Slot::Tab],
+        IntentKind::FileOperation => &[Slot::FileOperation],
+        IntentKind::CreateReminder => &[Slot::ReminderContent],
+        IntentKind::Chat => &[],
+    }
+}
+
+/// First required slot the intent is still missing
+pub fn missing_slot(intent: &Intent) -> Option<Slot> {
+    required_slots(intent.kind)
+        .iter()
+        .copied()
+        .find(|slot| slot.value(&intent.slots).is_none())
+}
+
+/// Intent waiting for the user to supply missing parameters
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingIntent {
+    pub intent: Intent,
+    /// Slot the last follow-up question asked for
+    pub asked: Slot,
+    /// Follow-up questions asked for `asked`
+    pub attempts: u32,
+}
+
+impl PendingIntent {
+    /// Pending intent stored with a session
+    pub fn load(session: &AssistantSession) -> Option<Self> {
+        let json = session.pending_intent.as_deref()?;
+        match serde_json::from_str(json) {
+            Ok(pending) => Some(pending),
+            Err(e) => {
+                warn!("Discarding unreadable pending intent of session {}: {}", session.id, e);
+                None
+            }
+        }
+    }
+
+    pub fn store(&self, session: &mut AssistantSession) {
+        session.pending_intent = serde_json::to_string(self).ok();
+    }
+}
+
+/// How a conversation turn continues
+#[derive(Debug, Clone)]
+pub enum SlotTurn {
+    /// Nothing to clarify, answer as usual
+    Pass,
+    /// Ask the user for a missing parameter
+    Ask { pending: PendingIntent, question: String },
+    /// A pending intent is now complete
+    Ready(Intent),
+    /// The user cancelled or never supplied the parameter
+    Cancelled(String),
+}
+
+/// Advance slot filling with the user's message
+pub fn advance(
+    pending: Option<PendingIntent>,
+    message: &str,
+    matcher: &IntentMatcher,
+    page: Option<&PageContext>,
+) -> SlotTurn {
+    let answer = matcher.classify(message);
+    let Some(mut pending) = pending else {
+        return start(answer, page);
+    };
+
+    let lower = message.trim().to_lowercase();
+    if CANCEL_WORDS.iter().any(|w| lower.contains(w)) {
+        info!("Pending {:?} intent cancelled by user", pending.intent.kind);
+        return SlotTurn::Cancelled("好的，已取消。".to_string());
+    }
+
+    // A different, clearly stated request replaces the one waiting for parameters
+    if answer.kind != IntentKind::Chat
+        && answer.kind != pending.intent.kind
+        && answer.confidence >= RULE_CONFIDENCE_THRESHOLD
+    {
+        info!("Pending {:?} intent replaced by {:?}", pending.intent.kind, answer.kind);
+        return start(answer, page);
+    }
+
+    let asked = pending.asked;
+    pending.intent.slots.fill_missing(answer.slots);
+    if asked.value(&pending.intent.slots).is_none() {
+        if let Some(value) = matcher.answer_slot(asked, message) {
+            asked.set(&mut pending.intent.slots, value);
+        }
+    }
+    fill_from_page(&mut pending.intent, page);
+
+    let Some(slot) = missing_slot(&pending.intent) else {
+        info!("Pending {:?} intent complete", pending.intent.kind);
+        return SlotTurn::Ready(pending.intent);
+    };
+    if slot == asked {
+        if pending.attempts >= MAX_ATTEMPTS {
+            info!("Giving up on {:?} intent after {} questions", pending.intent.kind, pending.attempts);
+            return SlotTurn::Cancelled("未能确认所需信息，已取消本次操作。您可以重新描述您的需求。".to_string());
+        }
+        pending.attempts += 1;
+    } else {
+        pending.asked = slot;
+        pending.attempts = 1;
+    }
+    SlotTurn::Ask {
+        question: slot.question(&pending.intent),
+        pending,
+    }
+}
+
+fn start(mut intent: Intent, page: Option<&PageContext>) -> SlotTurn {
+    if intent.kind == IntentKind::Chat || intent.confidence < MIN_CONFIDENCE {
+        return SlotTurn::Pass;
+    }
+    fill_from_page(&mut intent, page);
+    match missing_slot(&intent) {
+        Some(slot) => {
+            info!("{:?} intent is missing {:?}, asking the user", intent.kind, slot);
+            SlotTurn::Ask {
+                question: slot.question(&intent),
+                pending: PendingIntent {
+                    intent,
+                    asked: slot,
+                    attempts: 1,
+                },
+            }
+        }
+        None => SlotTurn::Pass,
+    }
+}
+
+/// A patient search refers to the patient and system on screen unless told otherwise
+fn fill_from_page(intent: &mut Intent, page: Option<&PageContext>) {
+    let Some(page) = page else { return };
+    if intent.kind != IntentKind::SearchPatient {
+        return;
+    }
+    if intent.slots.patient_name.is_none() {
+        intent.slots.patient_name = page.patient_name().map(str::to_string);
+    }
+    if intent.slots.system.is_none() {
+        intent.slots.system = page.page.system.clone();
+    }
+}
+
+/// Prompt line with the parameters collected for a completed intent
+pub fn confirmed_prompt(intent: &Intent) -> String {
+    let mut slots = match serde_json::to_value(&intent.slots) {
+        Ok(Value::Object(map)) => map,
+        _ => Default::default(),
+    };
+    slots.retain(|_, value| !value.is_null());
+    let kind = serde_json::to_value(intent.kind).unwrap_or_default();
+    format!(
+        "用户已确认的请求: {}",
+        serde_json::json!({ "intent": kind, "slots": slots })
+    )
+}
@@ -0,0 +1,176 @@
+// Streaming chat - consumes the SSE stream of the chat completion endpoint
+//
+// Streams are started by `ai_send_message` with a `stream_id`, so they go
+// through the same login, quota, redaction and permission checks as any turn.
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{Notify, OwnedMutexGuard};
+use tracing::{info, warn};
+
+use super::actions::MarkupFilter;
+use super::agent::PendingActions;
+use super::citations::CitationSources;
+use super::redact::{RedactionConfig, Redactor, RestoreFilter};
+use super::{AiClient, AiRequest, AiResponse};
+
+/// Event emitted for every streamed chunk of visible text
+pub const DELTA_EVENT: &str = "ai-delta";
+
+/// Payload of the `ai-delta` event
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AiDelta {
+    pub stream_id: String,
+    pub delta: String,
+}
+
+/// In-flight assistant work tracked for the frontend
+#[derive(Default)]
+pub struct AiState {
+    /// Streams that can be cancelled from the frontend
+    pub streams: Mutex<HashMap<String, Arc<Notify>>>,
+    /// Agent actions waiting for a result from the frontend bridge
+    pub pending_actions: Arc<PendingActions>,
+    /// PHI redaction vaults per conversation, kept in memory only
+    pub redactors: Mutex<HashMap<String, Arc<Redactor>>>,
+    /// Held for the duration of a turn, so turns of one conversation do not interleave
+    turns: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl AiState {
+    /// Redactor shared by all turns of a conversation
+    pub fn session_redactor(&self, session_id: &str, config: &RedactionConfig) -> Option<Arc<Redactor>> {
+        if !config.enabled {
+            return None;
+        }
+        let mut redactors = self.redactors.lock().ok()?;
+        let redactor = redactors
+            .entry(session_id.to_string())
+            .or_insert_with(|| Arc::new(Redactor::new(config)));
+        Some(redactor.clone())
+    }
+
+    /// Wait until no other turn of the conversation is running
+    pub(crate) async fn lock_turn(&self, session_id: &str) -> OwnedMutexGuard<()> {
+        let turn = {
+            let mut turns = self.turns.lock().unwrap_or_else(|e| e.into_inner());
+            // Forget conversations without a running or waiting turn
+            turns.retain(|_, turn| Arc::strong_count(turn) > 1);
+            turns.entry(session_id.to_string()).or_default().clone()
+        };
+        turn.lock_owned().await
+    }
+
+    pub(crate) fn register(&self, stream_id: &str) -> Result<Arc<Notify>, String> {
+        let cancel = Arc::new(Notify::new());
+        let mut streams = self.streams.lock().map_err(|e| e.to_string())?;
+        streams.insert(stream_id.to_string(), cancel.clone());
+        Ok(cancel)
+    }
+
+    pub(crate) fn unregister(&self, stream_id: &str) {
+        if let Ok(mut streams) = self.streams.lock() {
+            streams.remove(stream_id);
+        }
+    }
+
+    /// Cancel one stream, or all of them when no id is given
+    pub fn cancel(&self, stream_id: Option<&str>) -> Result<usize, String> {
+        let streams = self.streams.lock().map_err(|e| e.to_string())?;
+        let mut cancelled = 0;
+        for (id, cancel) in streams.iter() {
+            if stream_id.is_some_and(|s| s != id) {
+                continue;
+            }
+            cancel.notify_one();
+            cancelled += 1;
+        }
+        Ok(cancelled)
+    }
+}
+
+impl AiClient {
+    /// Send a chat request in streaming mode
+    ///
+    /// `on_delta` receives the visible text as it arrives, with action markup
+    /// held back. Resolves to the full response once the stream ends, or to an
+    /// error as soon as `cancel` is notified.
+    pub async fn chat_stream<F>(
+        &self,
+        request: AiRequest,
+        cancel: &Notify,
+        mut on_delta: F,
+    ) -> Result<AiResponse, String>
+    where
+        F: FnMut(&str) + Send,
+    {
+        let prompt_tokens = self.check_quota(&request)?;
+        let started = Instant::now();
+        let sources = CitationSources::from_request(&request);
+
+        let redacted;
+        let (request, mut restore) = match &self.redactor {
+            Some(redactor) => {
+                redacted = redactor.redact_request(&request);
+                (&redacted, Some(RestoreFilter::new(redactor)))
+            }
+            None => (&request, None),
+        };
+
+        let mut filter = MarkupFilter::new();
+        let mut forward = |delta: &str| {
+            let restored = match restore.as_mut() {
+                Some(restore) => restore.push(delta),
+                None => delta.to_string(),
+            };
+            let visible = filter.push(&restored);
+            if !visible.is_empty() {
+                on_delta(&visible);
+            }
+        };
+
+        let result = self.provider
+            .complete_stream(request, cancel, &mut forward)
+            .await
+            .inspect_err(|e| info!("AI stream ended early: {}", e));
+        self.record_usage(prompt_tokens, &result, started);
+        let content = result?;
+
+        let held_back = restore.as_mut().map(|r| r.finish()).unwrap_or_default();
+        let mut tail = filter.push(&held_back);
+        tail.push_str(&filter.finish());
+        if !tail.is_empty() {
+            on_delta(&tail);
+        }
+
+        let content = match &self.redactor {
+            Some(redactor) => redactor.restore(&content),
+            None => content,
+        };
+        Ok(Self::build_response(&content, &sources))
+    }
+}
+
+/// Emit one `ai-delta` event to the frontend
+pub(crate) fn emit_delta(app: &AppHandle, stream_id: &str, delta: &str) {
+    let payload = AiDelta {
+        stream_id: stream_id.to_string(),
+        delta: delta.to_string(),
+    };
+    if let Err(e) = app.emit(DELTA_EVENT, payload) {
+        warn!("Failed to emit {}: {}", DELTA_EVENT, e);
+    }
+}
+
+/// Tauri commands for streaming chat
+
+#[tauri::command]
+pub fn ai_cancel_stream(
+    state: tauri::State<AiState>,
+    stream_id: Option<String>,
+) -> Result<usize, String> {
+    state.cancel(stream_id.as_deref())
+}
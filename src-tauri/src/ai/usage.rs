@@ -0,0 +1,342 @@
+// Usage accounting - per-user request counts, prompt sizes and quotas
+//
+// Token counts are estimates; neither backend reports usage reliably, so the
+// same estimator is used for accounting and for enforcing limits.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use super::{AiClient, AiRequest};
+use crate::auth::AuthState;
+use crate::config::AppConfig;
+use crate::storage::{Database, SystemUsage, UsageRecord};
+
+/// Per-user limits, 0 disables a limit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    pub requests_per_hour: u32,
+    /// Maximum estimated size of the user's message in a request
+    ///
+    /// History, system prompt and page context are kept within their own
+    /// budgets and are not counted here.
+    pub max_tokens: u32,
+    pub tokens_per_day: u64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_hour: 100,
+            max_tokens: 4000,
+            tokens_per_day: 0,
+        }
+    }
+}
+
+/// Rough token estimate: one per CJK character, one per four other characters
+pub fn estimate_tokens(text: &str) -> u32 {
+    let mut cjk = 0u32;
+    let mut other = 0u32;
+    for c in text.chars() {
+        if is_cjk(c) {
+            cjk += 1;
+        } else {
+            other += 1;
+        }
+    }
+    cjk + other.div_ceil(4)
+}
+
+/// Estimated prompt size of a request, including per-message overhead
+pub fn estimate_request_tokens(request: &AiRequest) -> u32 {
+    let messages: u32 = request
+        .messages
+        .iter()
+        .map(|m| estimate_tokens(&m.content) + 4)
+        .sum();
+    messages + request.context.as_deref().map(estimate_tokens).unwrap_or(0)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{4E00}'..='\u{9FFF}'   // CJK unified ideographs
+        | '\u{3400}'..='\u{4DBF}' // extension A
+        | '\u{3000}'..='\u{303F}' // CJK punctuation
+        | '\u{FF00}'..='\u{FFEF}' // full-width forms
+    )
+}
+
+/// Who a request is accounted to
+#[derive(Debug, Clone)]
+pub struct UsageMeter {
+    pub user_id: String,
+    pub system: Option<String>,
+    pub quota: QuotaConfig,
+}
+
+impl UsageMeter {
+    pub fn new(user_id: &str, system: Option<String>, quota: &QuotaConfig) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            system,
+            quota: quota.clone(),
+        }
+    }
+
+    /// Reject the request if it would exceed a quota; returns the prompt estimate
+    ///
+    /// While a usage limit is set, the request is refused when usage cannot be read.
+    pub fn check(&self, request: &AiRequest) -> Result<u32, String> {
+        let message_tokens = request
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.role == "user")
+            .map(|m| estimate_tokens(&m.content))
+            .unwrap_or(0);
+        if self.quota.max_tokens > 0 && message_tokens > self.quota.max_tokens {
+            return Err(format!(
+                "消息过长（约 {} tokens），超过单次上限 {}",
+                message_tokens, self.quota.max_tokens
+            ));
+        }
+
+        let prompt_tokens = estimate_request_tokens(request);
+        if self.quota.requests_per_hour == 0 && self.quota.tokens_per_day == 0 {
+            return Ok(prompt_tokens);
+        }
+        let db = Database::open_default().map_err(unavailable)?;
+        self.check_usage(&db, prompt_tokens)?;
+        Ok(prompt_tokens)
+    }
+
+    fn check_usage(&self, db: &Database, prompt_tokens: u32) -> Result<(), String> {
+        if self.quota.requests_per_hour > 0 {
+            let (requests, _) = db
+                .usage_since(&self.user_id, &cutoff(Duration::from_secs(3600)))
+                .map_err(unavailable)?;
+            if requests >= self.quota.requests_per_hour {
+                return Err(format!(
+                    "已达到每小时 {} 次 AI 请求上限，请稍后再试",
+                    self.quota.requests_per_hour
+                ));
+            }
+        }
+
+        if self.quota.tokens_per_day > 0 {
+            let (_, tokens) = db
+                .usage_since(&self.user_id, &cutoff(Duration::from_secs(24 * 3600)))
+                .map_err(unavailable)?;
+            if tokens + u64::from(prompt_tokens) > self.quota.tokens_per_day {
+                return Err(format!(
+                    "已达到每日 {} tokens 的 AI 用量上限",
+                    self.quota.tokens_per_day
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a finished request
+    pub fn record(&self, prompt_tokens: u32, result: &Result<String, String>, started: Instant) {
+        if let Err(e) = Database::open_default().and_then(|db| self.record_in(&db, prompt_tokens, result, started)) {
+            warn!("Failed to record AI usage: {}", e);
+        }
+    }
+
+    fn record_in(
+        &self,
+        db: &Database,
+        prompt_tokens: u32,
+        result: &Result<String, String>,
+        started: Instant,
+    ) -> Result<(), String> {
+        db.record_usage(&UsageRecord {
+            user_id: self.user_id.clone(),
+            system: self.system.clone(),
+            prompt_tokens,
+            completion_tokens: result.as_deref().map(estimate_tokens).unwrap_or(0),
+            latency_ms: started.elapsed().as_millis() as u64,
+            success: result.is_ok(),
+        })
+    }
+}
+
+/// Quota error for usage that could not be read
+fn unavailable(e: String) -> String {
+    warn!("Usage check failed: {}", e);
+    format!("无法核对 AI 用量配额，请稍后重试: {}", e)
+}
+
+fn cutoff(window: Duration) -> String {
+    let window = chrono::Duration::from_std(window).unwrap_or_default();
+    (chrono::Utc::now() - window).to_rfc3339()
+}
+
+impl AiClient {
+    /// Account requests to a user and enforce their quotas
+    pub fn with_usage(mut self, meter: UsageMeter) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Quota check before a request; returns the prompt estimate for `record_usage`
+    pub(crate) fn check_quota(&self, request: &AiRequest) -> Result<u32, String> {
+        match &self.meter {
+            Some(meter) => meter.check(request),
+            None => Ok(estimate_request_tokens(request)),
+        }
+    }
+
+    pub(crate) fn record_usage(&self, prompt_tokens: u32, result: &Result<String, String>, started: Instant) {
+        if let Some(meter) = &self.meter {
+            meter.record(prompt_tokens, result, started);
+        }
+    }
+}
+
+/// Usage of one user over a period
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageSummary {
+    pub user_id: Option<String>,
+    pub since: String,
+    pub systems: Vec<SystemUsage>,
+    pub requests_last_hour: u32,
+    pub quota: QuotaConfig,
+}
+
+/// Tauri commands for usage accounting
+
+#[tauri::command]
+pub fn ai_get_usage_summary(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+    days: Option<u32>,
+) -> Result<UsageSummary, String> {
+    let user_id = auth.user_for(user_id.as_deref())?.id;
+    let db = Database::open_default()?;
+    let since = cutoff(Duration::from_secs(u64::from(days.unwrap_or(30)) * 24 * 3600));
+    let systems = db.usage_by_system(Some(&user_id), &since)?;
+    let requests_last_hour = db.usage_since(&user_id, &cutoff(Duration::from_secs(3600)))?.0;
+
+    Ok(UsageSummary {
+        user_id: Some(user_id),
+        since,
+        systems,
+        requests_last_hour,
+        quota: AppConfig::load().ai.quota,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AiMessage;
+
+    fn database() -> Database {
+        let db = Database::new(":memory:").unwrap();
+        db.init_schema().unwrap();
+        db
+    }
+
+    fn quota(requests_per_hour: u32, tokens_per_day: u64) -> QuotaConfig {
+        QuotaConfig {
+            requests_per_hour,
+            max_tokens: 100,
+            tokens_per_day,
+        }
+    }
+
+    fn request(messages: &[(&str, &str)]) -> AiRequest {
+        AiRequest {
+            messages: messages
+                .iter()
+                .map(|(role, content)| AiMessage {
+                    role: role.to_string(),
+                    content: content.to_string(),
+                })
+                .collect(),
+            context: None,
+            tools: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn estimates_count_cjk_per_character() {
+        assert_eq!(estimate_tokens("患者张三"), 4);
+        assert_eq!(estimate_tokens("abcdefgh"), 2);
+        assert_eq!(estimate_tokens("查询 report"), 2 + 2);
+        assert_eq!(estimate_request_tokens(&request(&[("user", "abcd"), ("assistant", "好")])), 1 + 4 + 1 + 4);
+    }
+
+    #[test]
+    fn usage_is_accounted_per_user_and_system() {
+        let db = database();
+        let ris = UsageMeter::new("u1", Some("ris".to_string()), &quota(0, 0));
+        let pis = UsageMeter::new("u1", Some("pis".to_string()), &quota(0, 0));
+        let other = UsageMeter::new("u2", Some("ris".to_string()), &quota(0, 0));
+        let started = Instant::now();
+        ris.record_in(&db, 10, &Ok("abcdefgh".to_string()), started).unwrap();
+        ris.record_in(&db, 20, &Err("timeout".to_string()), started).unwrap();
+        pis.record_in(&db, 5, &Ok("好".to_string()), started).unwrap();
+        other.record_in(&db, 40, &Ok(String::new()), started).unwrap();
+
+        let since = cutoff(Duration::from_secs(3600));
+        assert_eq!(db.usage_since("u1", &since).unwrap(), (3, 10 + 2 + 20 + 5 + 1));
+        assert_eq!(db.usage_since("u2", &since).unwrap(), (1, 40));
+
+        let systems = db.usage_by_system(Some("u1"), &since).unwrap();
+        assert_eq!(systems.len(), 2);
+        assert_eq!(systems[0].system.as_deref(), Some("ris"));
+        assert_eq!((systems[0].requests, systems[0].failures), (2, 1));
+        assert_eq!(systems[0].prompt_tokens, 30);
+        assert_eq!(systems[1].system.as_deref(), Some("pis"));
+
+        let everyone = db.usage_by_system(None, &since).unwrap();
+        assert_eq!(everyone[0].requests, 3);
+    }
+
+    #[test]
+    fn requests_over_the_hourly_limit_are_rejected() {
+        let db = database();
+        let meter = UsageMeter::new("u1", None, &quota(2, 0));
+        assert!(meter.check_usage(&db, 10).is_ok());
+        meter.record_in(&db, 10, &Ok(String::new()), Instant::now()).unwrap();
+        meter.record_in(&db, 10, &Ok(String::new()), Instant::now()).unwrap();
+
+        let err = meter.check_usage(&db, 10).unwrap_err();
+        assert!(err.contains("每小时 2 次"), "{}", err);
+        // Another user's quota is untouched
+        assert!(UsageMeter::new("u2", None, &quota(2, 0)).check_usage(&db, 10).is_ok());
+    }
+
+    #[test]
+    fn tokens_over_the_daily_limit_are_rejected() {
+        let db = database();
+        let meter = UsageMeter::new("u1", None, &quota(0, 100));
+        meter.record_in(&db, 90, &Ok(String::new()), Instant::now()).unwrap();
+
+        assert!(meter.check_usage(&db, 10).is_ok());
+        assert!(meter.check_usage(&db, 11).unwrap_err().contains("每日 100 tokens"));
+    }
+
+    #[test]
+    fn unreadable_usage_rejects_the_request() {
+        // No schema, so every usage query fails
+        let db = Database::new(":memory:").unwrap();
+        let meter = UsageMeter::new("u1", None, &quota(10, 0));
+        assert!(meter.check_usage(&db, 10).unwrap_err().contains("无法核对"));
+    }
+
+    #[test]
+    fn message_limit_ignores_history_and_system_prompt() {
+        let meter = UsageMeter::new("u1", None, &quota(0, 0));
+        let history = "病历".repeat(200);
+        let long_turn = request(&[("system", &history), ("assistant", &history), ("user", "今天几个检查？")]);
+        assert!(meter.check(&long_turn).unwrap() > 800);
+
+        let long_message = "查".repeat(101);
+        let err = meter.check(&request(&[("user", &long_message), ("assistant", "好")])).unwrap_err();
+        assert!(err.contains("超过单次上限 100"), "{}", err);
+    }
+}
@@ -1,7 +1,43 @@
 // Authentication module
+//
+// Credentials are checked by the configured authentication service
+// (POST {endpoint}/api/auth/login from the API contract), which may be the
+// business systems' SSO or a local stand-in. The mock login that accepts any
+// credentials is only available in debug builds with `dev_mock` set.
+//
+// Tokens are refreshed by a background task ahead of their expiry; commands
+// that need a token wait for a refresh in progress instead of failing.
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::fs;
 use std::sync::Mutex;
-use tracing::info;
+use std::time::Duration;
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use tracing::{error, info, warn};
+
+use crate::ai::gateway::GatewayClient;
+use crate::config::AppConfig;
+use crate::lock::{LockConfig, SessionLock};
+use crate::permissions::{self, Permission, PermissionConfig, Permissions, APP_SCOPE};
+use crate::storage::Database;
+
+/// Environment variable enabling the mock login, same as `dev_mock`
+pub const DEV_MOCK_ENV: &str = "EW_AUTH_DEV_MOCK";
+
+/// Event telling the frontend that the session ended because the token could not be refreshed
+pub const EXPIRED_EVENT: &str = "auth-expired";
+
+/// Longest wait between refresh attempts after a failure
+const MAX_RETRY_SECS: u64 = 60;
+/// Shortest wait between refreshes, however short-lived the token
+const MIN_REFRESH_SECS: u64 = 5;
+/// Cap of `refresh_margin_secs`, so a misconfigured margin stays in range
+const MAX_REFRESH_MARGIN_SECS: u64 = 24 * 3600;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -10,21 +46,275 @@ pub struct User {
     pub display_name: String,
     pub role: String,
     pub institution_id: Option<String>,
+    #[serde(default)]
+    pub department: Option<String>,
+    /// Granted per business system, see `permissions`
+    #[serde(default)]
+    pub permissions: Permissions,
 }
 
-/// Authentication state
-pub struct AuthState {
-    pub current_user: Mutex<Option<User>>,
+/// Authentication service settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub endpoint: String,
+    pub timeout_secs: u64,
+    /// Tokens are refreshed this long before they expire
+    pub refresh_margin_secs: u64,
+    /// Accept any credentials and return a mock user, honoured in debug builds only
+    pub dev_mock: bool,
+    /// Role defaults for users the service sends no permissions for
+    pub permissions: PermissionConfig,
+    /// Idle auto-lock
+    pub lock: LockConfig,
 }
 
-impl Default for AuthState {
+impl Default for AuthConfig {
     fn default() -> Self {
         Self {
-            current_user: Mutex::new(None),
+            endpoint: "http://localhost:5000".to_string(),
+            timeout_secs: 15,
+            refresh_margin_secs: 300,
+            dev_mock: false,
+            permissions: PermissionConfig::default(),
+            lock: LockConfig::default(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Whether the mock login is enabled by config or `EW_AUTH_DEV_MOCK`
+    pub(crate) fn mock_enabled(&self) -> bool {
+        let requested = self.dev_mock
+            || std::env::var(DEV_MOCK_ENV).is_ok_and(|v| matches!(v.trim(), "1" | "true"));
+        if requested && !cfg!(debug_assertions) {
+            warn!("Mock login requested in a release build, ignoring");
+            return false;
+        }
+        requested
+    }
+
+    /// `refresh_margin_secs`, capped to a day
+    pub(crate) fn refresh_margin(&self) -> u64 {
+        self.refresh_margin_secs.min(MAX_REFRESH_MARGIN_SECS)
+    }
+}
+
+/// Authentication state, the signed-in user and their token
+#[derive(Default)]
+pub struct AuthState {
+    pub session: Mutex<Option<Session>>,
+    /// Held while the token is refreshed, so token users wait for the new one
+    refreshing: tokio::sync::Mutex<()>,
+    /// Background refresh task of the current session
+    refresher: Mutex<Option<JoinHandle<()>>>,
+    /// Idle lock of the current session
+    pub lock: SessionLock,
+}
+
+impl AuthState {
+    pub fn sign_in(&self, session: Session) {
+        if let Ok(mut current) = self.session.lock() {
+            *current = Some(session);
+        }
+        self.lock.reset();
+    }
+
+    /// Forget the session and stop refreshing it, returning the user who was signed in
+    pub fn sign_out(&self) -> Option<User> {
+        if let Some(task) = self.refresher.lock().ok().and_then(|mut r| r.take()) {
+            task.abort();
+        }
+        self.lock.stop();
+        self.session.lock().ok()?.take().map(|s| s.user)
+    }
+
+    pub fn current_user(&self) -> Option<User> {
+        self.session.lock().ok()?.as_ref().map(|s| s.user.clone())
+    }
+
+    fn current_session(&self) -> Option<Session> {
+        self.session.lock().ok()?.clone()
+    }
+
+    /// Signed-in user, or an error asking to log in or to unlock the session
+    pub fn require_user(&self) -> Result<User, String> {
+        let user = self.current_user().ok_or_else(|| "请先登录".to_string())?;
+        if self.lock.is_locked() {
+            return Err("会话已锁定，请先解锁".to_string());
+        }
+        Ok(user)
+    }
+
+    /// Signed-in user for a command that writes or reads user-scoped data
+    ///
+    /// `claimed` is a user id the frontend passed along; it must match the
+    /// signed-in user and a mismatch is audited as a forgery attempt.
+    pub fn user_for(&self, claimed: Option<&str>) -> Result<User, String> {
+        let user = self.require_user()?;
+        match claimed {
+            Some(claimed) if claimed != user.id => {
+                warn!("User {} attempted to act as {}", user.id, claimed);
+                let details = json!({ "claimed_user_id": claimed }).to_string();
+                if let Err(e) = Database::open_default()
+                    .and_then(|db| db.log_audit(Some(&user.id), "forged_user_id", &details, "High"))
+                {
+                    warn!("Failed to write audit log: {}", e);
+                }
+                Err("无权访问其他用户的数据".to_string())
+            }
+            _ => Ok(user),
+        }
+    }
+
+    /// Signed-in user, if they hold `permission` in `system` (client-wide for `None`)
+    ///
+    /// Denied calls are audited and fail with a 403-style error.
+    pub fn authorize(&self, command: &str, system: Option<&str>, permission: Permission) -> Result<User, String> {
+        let user = self.require_user()?;
+        let scope = system.unwrap_or(APP_SCOPE);
+        if user.permissions.allows(scope, permission) {
+            return Ok(user);
+        }
+        permissions::audit_denied(&user, command, scope, permission);
+        Err(permissions::denied_message(scope, permission))
+    }
+
+    /// Token that is valid now, refreshed first if it is about to expire
+    ///
+    /// Waits for a refresh already in progress.
+    pub async fn valid_token(&self, config: &AuthConfig) -> Result<String, String> {
+        let _guard = self.refreshing.lock().await;
+        let session = self.current_session().ok_or("请先登录")?;
+        if !session.expires_within(config.refresh_margin()) {
+            return Ok(session.token);
+        }
+        match self.refresh_locked(config).await {
+            Ok(token) => Ok(token),
+            // A refresh failure is only fatal once the old token has run out
+            Err(e) if !session.expires_within(0) => {
+                warn!("Token refresh failed, using current token: {:?}", e);
+                Ok(session.token)
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Refresh the token now
+    pub async fn refresh(&self, config: &AuthConfig) -> Result<String, AuthError> {
+        let _guard = self.refreshing.lock().await;
+        self.refresh_locked(config).await
+    }
+
+    async fn refresh_locked(&self, config: &AuthConfig) -> Result<String, AuthError> {
+        let session = self.current_session().ok_or(AuthError::SessionExpired)?;
+        let grant = AuthClient::new(config).refresh(&session).await?;
+        let mut current = self.session.lock().map_err(|e| AuthError::Network(e.to_string()))?;
+        // The user may have logged out while the request was in flight
+        let current = current
+            .as_mut()
+            .filter(|s| s.user.id == session.user.id)
+            .ok_or(AuthError::SessionExpired)?;
+        current.token = grant.token.clone();
+        current.expires_at = grant.expires_at;
+        if grant.refresh_token.is_some() {
+            current.refresh_token = grant.refresh_token;
+        }
+        info!("Refreshed token for user {}, expires at {:?}", current.user.id, current.expires_at);
+        Ok(grant.token)
+    }
+
+    /// Keep the session's token fresh in the background until logout
+    pub fn start_refresh(&self, app: AppHandle) {
+        let task = tauri::async_runtime::spawn(refresh_loop(app));
+        if let Ok(mut refresher) = self.refresher.lock() {
+            if let Some(previous) = refresher.replace(task) {
+                previous.abort();
+            }
+        }
+    }
+
+    /// End a session whose token could not be refreshed, without stopping the calling task
+    fn expire(&self) -> Option<User> {
+        self.refresher.lock().ok().and_then(|mut r| r.take());
+        self.session.lock().ok()?.take().map(|s| s.user)
+    }
+}
+
+/// Refresh ahead of expiry; after a failure retry with backoff until the token runs out
+async fn refresh_loop(app: AppHandle) {
+    let mut failures: u32 = 0;
+    loop {
+        let config = AppConfig::load().auth;
+        let auth = app.state::<AuthState>();
+        let Some(expires_at) = auth.current_session().and_then(|s| s.expires_at) else {
+            // Signed out, or a token without expiry
+            return;
+        };
+
+        let until_expiry = (expires_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(refresh_wait(until_expiry, config.refresh_margin(), failures)).await;
+
+        match auth.refresh(&config).await {
+            Ok(_) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                warn!("Token refresh attempt {} failed: {:?}", failures, e);
+                let rejected = matches!(e, AuthError::SessionExpired | AuthError::Forbidden(_));
+                if rejected || Utc::now() >= expires_at {
+                    end_expired_session(&app, &auth, &e);
+                    return;
+                }
+            }
         }
     }
 }
 
+/// Pause before the next refresh, `failures` being the failed attempts in a row
+fn refresh_wait(until_expiry: Duration, margin_secs: u64, failures: u32) -> Duration {
+    if failures == 0 {
+        // A token living no longer than the margin is refreshed halfway
+        // through its lifetime, not again right after every refresh
+        until_expiry
+            .saturating_sub(Duration::from_secs(margin_secs))
+            .max(until_expiry / 2)
+            .max(Duration::from_secs(MIN_REFRESH_SECS))
+    } else {
+        let backoff = Duration::from_secs((5u64 << failures.min(4)).min(MAX_RETRY_SECS));
+        backoff.min(until_expiry)
+    }
+}
+
+fn end_expired_session(app: &AppHandle, auth: &AuthState, error: &AuthError) {
+    let Some(user) = auth.expire() else {
+        return;
+    };
+    error!("Session of user {} expired: {:?}", user.id, error);
+    if let Some(gateway) = app.try_state::<GatewayClient>() {
+        gateway.disconnect();
+    }
+    if let Err(e) = Database::open_default()
+        .and_then(|db| db.log_audit(Some(&user.id), "session_expired", &json!({ "error": format!("{:?}", error) }).to_string(), "Low"))
+    {
+        warn!("Failed to write audit log: {}", e);
+    }
+    let payload = json!({ "userId": user.id, "message": AuthError::SessionExpired.to_string() });
+    if let Err(e) = app.emit(EXPIRED_EVENT, payload) {
+        warn!("Failed to emit {}: {}", EXPIRED_EVENT, e);
+    }
+}
+
+/// Sign out the current user, if any, before another one signs in or on logout
+fn end_session(app: &AppHandle, auth: &AuthState) -> Option<User> {
+    // Tokens mapped for the previous user must not outlive the session
+    if let Some(gateway) = app.try_state::<GatewayClient>() {
+        gateway.disconnect();
+    }
+    // Signing out resets the lock, which would leave its hidden windows hidden
+    auth.lock.restore_windows(app);
+    auth.sign_out()
+}
+
 /// Login request
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -38,52 +328,579 @@ pub struct LoginResponse {
     pub success: bool,
     pub user: Option<User>,
     pub token: Option<String>,
+    /// Token expiry, RFC 3339
+    pub expires_at: Option<String>,
     pub message: String,
 }
 
+impl LoginResponse {
+    fn failed(message: String) -> Self {
+        Self {
+            success: false,
+            user: None,
+            token: None,
+            expires_at: None,
+            message,
+        }
+    }
+}
+
+/// Errors reported while logging in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError {
+    /// 401 - wrong username or password
+    InvalidCredentials,
+    /// 403 - account disabled or not allowed to use the client
+    Forbidden(String),
+    /// 423 - account locked after failed attempts
+    Locked,
+    /// 429 - too many attempts
+    RateLimited,
+    /// 5xx - authentication service failure
+    Server(String),
+    /// Any other rejected request
+    BadRequest(String),
+    /// The token could no longer be refreshed
+    SessionExpired,
+    Timeout,
+    Network(String),
+    InvalidResponse(String),
+}
+
+impl AuthError {
+    fn from_status(status: StatusCode, message: String) -> Self {
+        match status.as_u16() {
+            401 => AuthError::InvalidCredentials,
+            403 => AuthError::Forbidden(message),
+            423 => AuthError::Locked,
+            429 => AuthError::RateLimited,
+            500..=599 => AuthError::Server(message),
+            _ => AuthError::BadRequest(format!("{}: {}", status, message)),
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::InvalidCredentials => write!(f, "用户名或密码错误"),
+            AuthError::Forbidden(m) if m.is_empty() => write!(f, "该账号无权登录"),
+            AuthError::Forbidden(m) => write!(f, "该账号无权登录: {}", m),
+            AuthError::Locked => write!(f, "账号已被锁定，请联系管理员"),
+            AuthError::RateLimited => write!(f, "登录尝试过于频繁，请稍后重试"),
+            AuthError::Server(_) => write!(f, "认证服务暂时不可用，请稍后重试"),
+            AuthError::BadRequest(m) => write!(f, "登录请求无效: {}", m),
+            AuthError::SessionExpired => write!(f, "登录已过期，请重新登录"),
+            AuthError::Timeout => write!(f, "连接认证服务超时，请检查网络"),
+            AuthError::Network(_) => write!(f, "无法连接认证服务，请检查网络或服务地址"),
+            AuthError::InvalidResponse(_) => write!(f, "认证服务响应格式错误"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+#[derive(Debug, Serialize)]
+struct AuthLoginRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+    device_id: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthLoginResponse {
+    user: AuthUser,
+    /// Sent next to the user in the architecture doc, accepted inside it too
+    #[serde(default)]
+    permissions: Option<Permissions>,
+    #[serde(flatten)]
+    grant: AuthGrant,
+}
+
+/// Token issued on login and refresh
+#[derive(Debug, Deserialize)]
+struct AuthGrant {
+    token: String,
+    /// RFC 3339 expiry time
+    #[serde(default)]
+    expires_at: Option<String>,
+    /// Lifetime in seconds, used when `expires_at` is missing
+    #[serde(default)]
+    expires_in: Option<u64>,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+impl AuthGrant {
+    fn expiry(&self) -> Option<DateTime<Utc>> {
+        let parsed = self.expires_at.as_deref().and_then(|t| {
+            DateTime::parse_from_rfc3339(t)
+                .map(|t| t.with_timezone(&Utc))
+                .map_err(|e| warn!("Ignoring unparsable token expiry {:?}: {}", t, e))
+                .ok()
+        });
+        parsed.or_else(|| {
+            let lifetime = TimeDelta::try_seconds(i64::try_from(self.expires_in?).ok()?)?;
+            Utc::now().checked_add_signed(lifetime)
+        })
+    }
+}
+
+/// Refreshed token
+#[derive(Debug)]
+struct Refreshed {
+    token: String,
+    expires_at: Option<DateTime<Utc>>,
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AuthRefreshRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<&'a str>,
+    device_id: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthUser {
+    id: String,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default, alias = "display_name")]
+    name: Option<String>,
+    role: String,
+    #[serde(default, alias = "institution")]
+    institution_id: Option<String>,
+    #[serde(default)]
+    department: Option<String>,
+    #[serde(default)]
+    permissions: Option<Permissions>,
+}
+
+impl AuthUser {
+    fn into_user(self, username: &str) -> User {
+        let username = self.username.unwrap_or_else(|| username.to_string());
+        User {
+            id: self.id,
+            display_name: self.name.filter(|n| !n.is_empty()).unwrap_or_else(|| username.clone()),
+            username,
+            role: self.role,
+            institution_id: self.institution_id,
+            department: self.department,
+            permissions: self.permissions.unwrap_or_default(),
+        }
+    }
+}
+
+/// Successful login
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user: User,
+    pub token: String,
+    /// `None` when the service did not report an expiry; such tokens are never refreshed
+    pub expires_at: Option<DateTime<Utc>>,
+    pub refresh_token: Option<String>,
+}
+
+impl Session {
+    /// Whether the token expires within `secs` seconds
+    fn expires_within(&self, secs: u64) -> bool {
+        let Some(expires_at) = self.expires_at else {
+            return false;
+        };
+        let margin = i64::try_from(secs).ok().and_then(TimeDelta::try_seconds);
+        match margin.and_then(|m| expires_at.checked_sub_signed(m)) {
+            Some(due) => due <= Utc::now(),
+            // A margin beyond the range of dates covers any expiry
+            None => true,
+        }
+    }
+}
+
+/// Client for the authentication service
+pub struct AuthClient {
+    http: Client,
+    endpoint: String,
+}
+
+impl AuthClient {
+    pub fn new(config: &AuthConfig) -> Self {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap_or_else(|e| {
+                warn!("Failed to build HTTP client, using defaults: {}", e);
+                Client::new()
+            });
+        Self {
+            http,
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Check credentials with the authentication service
+    pub async fn login(&self, username: &str, password: &str) -> Result<Session, AuthError> {
+        let url = format!("{}/api/auth/login", self.endpoint);
+        let body = AuthLoginRequest {
+            username,
+            password,
+            device_id: &device_id(),
+        };
+
+        let login: AuthLoginResponse = self.send(self.http.post(&url).json(&body)).await?;
+        if login.grant.token.is_empty() {
+            return Err(AuthError::InvalidResponse("empty token".to_string()));
+        }
+
+        let mut user = login.user.into_user(username);
+        if let Some(permissions) = login.permissions {
+            user.permissions = permissions;
+        }
+        Ok(Session {
+            user,
+            expires_at: login.grant.expiry(),
+            token: login.grant.token,
+            refresh_token: login.grant.refresh_token,
+        })
+    }
+
+    /// Exchange the session's token for a new one
+    async fn refresh(&self, session: &Session) -> Result<Refreshed, AuthError> {
+        let url = format!("{}/api/auth/refresh", self.endpoint);
+        let body = AuthRefreshRequest {
+            refresh_token: session.refresh_token.as_deref(),
+            device_id: &device_id(),
+        };
+
+        let request = self.http.post(&url).bearer_auth(&session.token).json(&body);
+        let grant: AuthGrant = self.send(request).await.map_err(|e| match e {
+            AuthError::InvalidCredentials => AuthError::SessionExpired,
+            e => e,
+        })?;
+        if grant.token.is_empty() {
+            return Err(AuthError::InvalidResponse("empty token".to_string()));
+        }
+
+        Ok(Refreshed {
+            expires_at: grant.expiry(),
+            token: grant.token,
+            refresh_token: grant.refresh_token,
+        })
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, AuthError> {
+        let response = request.send().await.map_err(|e| {
+            if e.is_timeout() {
+                AuthError::Timeout
+            } else {
+                AuthError::Network(e.to_string())
+            }
+        })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            return Err(AuthError::from_status(status, error_message(&text)));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AuthError::InvalidResponse(e.to_string()))
+    }
+}
+
+/// `message` or `error` field of an error body, the raw body otherwise
+fn error_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| {
+            ["message", "error"]
+                .iter()
+                .find_map(|key| v.get(key).and_then(Value::as_str).map(str::to_string))
+        })
+        .unwrap_or_else(|| body.trim().chars().take(200).collect())
+}
+
+/// Stable id of this installation, created on first use
+fn device_id() -> String {
+    let path = AppConfig::config_dir().join("device_id");
+    if let Ok(id) = fs::read_to_string(&path) {
+        if !id.trim().is_empty() {
+            return id.trim().to_string();
+        }
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    if let Err(e) = fs::create_dir_all(AppConfig::config_dir()).and_then(|_| fs::write(&path, &id)) {
+        warn!("Failed to store device id: {}", e);
+    }
+    id
+}
+
+fn mock_session(username: &str) -> Session {
+    Session {
+        user: User {
+            id: uuid::Uuid::new_v4().to_string(),
+            username: username.to_string(),
+            display_name: username.to_string(),
+            role: "user".to_string(),
+            institution_id: None,
+            department: None,
+            permissions: Permissions::default(),
+        },
+        token: "mock-jwt-token".to_string(),
+        expires_at: None,
+        refresh_token: None,
+    }
+}
+
 /// Tauri commands for authentication
 
 #[tauri::command]
-pub fn login(request: LoginRequest) -> LoginResponse {
+pub async fn login(
+    app: AppHandle,
+    auth: tauri::State<'_, AuthState>,
+    request: LoginRequest,
+) -> Result<LoginResponse, String> {
     info!("Login attempt for user: {}", request.username);
 
-    // TODO: Validate credentials against backend
-    // For now, simulate successful login
-    if request.username.is_empty() || request.password.is_empty() {
-        return LoginResponse {
-            success: false,
-            user: None,
-            token: None,
-            message: "用户名和密码不能为空".to_string(),
-        };
+    if request.username.trim().is_empty() || request.password.is_empty() {
+        return Ok(LoginResponse::failed("用户名和密码不能为空".to_string()));
+    }
+    // Signing in over a locked session would get around the unlock
+    if auth.current_user().is_some() && auth.lock.is_locked() {
+        warn!("Login of {} rejected while the session is locked", request.username);
+        return Ok(LoginResponse::failed("会话已锁定，请先解锁或退出登录".to_string()));
     }
 
-    let user = User {
-        id: uuid::Uuid::new_v4().to_string(),
-        username: request.username.clone(),
-        display_name: request.username.clone(),
-        role: "user".to_string(),
-        institution_id: None,
+    let config = AppConfig::load().auth;
+    let result = if config.mock_enabled() {
+        warn!("Using mock login for user: {}", request.username);
+        Ok(mock_session(request.username.trim()))
+    } else {
+        AuthClient::new(&config).login(request.username.trim(), &request.password).await
     };
 
-    info!("Login successful for user: {}", request.username);
+    let mut session = match result {
+        Ok(session) => session,
+        Err(e) => {
+            error!("Login failed for user {}: {:?}", request.username, e);
+            return Ok(LoginResponse::failed(e.to_string()));
+        }
+    };
 
-    LoginResponse {
+    if session.user.permissions.is_empty() {
+        session.user.permissions = config.permissions.for_role(&session.user.role);
+    }
+
+    info!("Login successful for user: {} ({})", session.user.username, session.user.role);
+    let db = Database::open_default()?;
+    if let Some(previous) = end_session(&app, &auth) {
+        info!("User {} signed out by the login of {}", previous.username, session.user.username);
+        db.log_audit(Some(&previous.id), "logout", &json!({ "reason": "new_login" }).to_string(), "Low")?;
+    }
+    db.log_audit(Some(&session.user.id), "login", &json!({ "username": session.user.username }).to_string(), "Low")?;
+    auth.sign_in(session.clone());
+    auth.start_refresh(app.clone());
+    auth.lock.start_monitor(app);
+
+    Ok(LoginResponse {
         success: true,
-        user: Some(user),
-        token: Some("mock-jwt-token".to_string()),
+        user: Some(session.user),
+        token: Some(session.token),
+        expires_at: session.expires_at.map(|t| t.to_rfc3339()),
         message: "登录成功".to_string(),
-    }
+    })
 }
 
 #[tauri::command]
-pub fn logout() -> Result<(), String> {
-    info!("User logged out");
+pub fn logout(app: AppHandle, auth: tauri::State<AuthState>) -> Result<(), String> {
+    if let Some(user) = end_session(&app, &auth) {
+        Database::open_default()?.log_audit(Some(&user.id), "logout", "{}", "Low")?;
+        info!("User {} logged out", user.username);
+    }
     Ok(())
 }
 
 #[tauri::command]
-pub fn get_current_user() -> Option<User> {
-    // TODO: Get current user from auth state
-    None
+pub fn get_current_user(auth: tauri::State<AuthState>) -> Option<User> {
+    auth.current_user()
+}
+
+/// Current token for calls to the business systems, waiting for a refresh in progress
+#[tauri::command]
+pub async fn get_auth_token(auth: tauri::State<'_, AuthState>) -> Result<String, String> {
+    auth.require_user()?;
+    auth.valid_token(&AppConfig::load().auth).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::provider::mock_server::{MockServer, Reply};
+
+    fn client(url: &str) -> AuthClient {
+        AuthClient::new(&AuthConfig {
+            endpoint: url.to_string(),
+            ..AuthConfig::default()
+        })
+    }
+
+    fn session(expires_at: Option<DateTime<Utc>>) -> Session {
+        let mut session = mock_session("zhang");
+        session.token = "old-token".to_string();
+        session.refresh_token = Some("refresh-1".to_string());
+        session.expires_at = expires_at;
+        session
+    }
+
+    #[test]
+    fn expires_within_handles_any_margin() {
+        let in_an_hour = session(Some(Utc::now() + TimeDelta::hours(1)));
+        assert!(!in_an_hour.expires_within(0));
+        assert!(!in_an_hour.expires_within(300));
+        assert!(in_an_hour.expires_within(7200));
+        assert!(in_an_hour.expires_within(u64::MAX));
+
+        let expired = session(Some(Utc::now() - TimeDelta::seconds(1)));
+        assert!(expired.expires_within(0));
+        assert!(!session(None).expires_within(u64::MAX));
+
+        let config = AuthConfig {
+            refresh_margin_secs: u64::MAX,
+            ..AuthConfig::default()
+        };
+        assert_eq!(config.refresh_margin(), MAX_REFRESH_MARGIN_SECS);
+    }
+
+    #[test]
+    fn refresh_waits_for_the_margin() {
+        let secs = Duration::from_secs;
+        assert_eq!(refresh_wait(secs(3600), 300, 0), secs(3300));
+        // Backoff after failures, but never past the expiry
+        assert_eq!(refresh_wait(secs(3600), 300, 1), secs(10));
+        assert_eq!(refresh_wait(secs(3600), 300, 9), secs(MAX_RETRY_SECS));
+        assert_eq!(refresh_wait(secs(8), 300, 2), secs(8));
+    }
+
+    #[test]
+    fn short_lived_tokens_are_not_refreshed_in_a_loop() {
+        let secs = Duration::from_secs;
+        // Lifetime below the margin: halfway through instead of immediately
+        assert_eq!(refresh_wait(secs(120), 300, 0), secs(60));
+        assert_eq!(refresh_wait(secs(300), 300, 0), secs(150));
+        assert_eq!(refresh_wait(secs(4), 300, 0), secs(MIN_REFRESH_SECS));
+        assert_eq!(refresh_wait(Duration::ZERO, 300, 0), secs(MIN_REFRESH_SECS));
+    }
+
+    #[tokio::test]
+    async fn login_statuses_map_to_auth_errors() {
+        let cases = [
+            (Reply::Json(401, json!({"message": "bad password"})), AuthError::InvalidCredentials),
+            (Reply::Json(403, json!({"message": "账号已停用"})), AuthError::Forbidden("账号已停用".to_string())),
+            (Reply::Json(423, json!({})), AuthError::Locked),
+            (Reply::Json(429, json!({})), AuthError::RateLimited),
+            (Reply::Json(502, json!({"error": "upstream"})), AuthError::Server("upstream".to_string())),
+            (Reply::Json(400, json!({"message": "x"})), AuthError::BadRequest("400 Bad Request: x".to_string())),
+            (Reply::Json(200, json!({"token": "t"})), AuthError::InvalidResponse(String::new())),
+            (
+                Reply::Json(200, json!({"user": {"id": "u1", "role": "doctor"}, "token": ""})),
+                AuthError::InvalidResponse("empty token".to_string()),
+            ),
+        ];
+        for (reply, expected) in cases {
+            let server = MockServer::start(vec![reply]).await;
+            let err = client(&server.url).login("zhang", "secret").await.unwrap_err();
+            match (&err, &expected) {
+                // serde's message is not worth pinning down
+                (AuthError::InvalidResponse(_), AuthError::InvalidResponse(m)) if m.is_empty() => {}
+                _ => assert_eq!(err, expected),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn login_reads_user_permissions_and_expiry() {
+        let server = MockServer::start(vec![Reply::Json(
+            200,
+            json!({
+                "user": {"id": "u1", "display_name": "张医生", "role": "doctor", "institution": "h1"},
+                "permissions": {"ris": ["read", "approve"]},
+                "token": "jwt",
+                "expires_in": 3600,
+                "refresh_token": "r1"
+            }),
+        )])
+        .await;
+
+        let before = Utc::now();
+        let session = client(&server.url).login("zhang", "secret").await.unwrap();
+
+        let sent = &server.requests()[0];
+        assert_eq!(sent.path, "/api/auth/login");
+        assert_eq!(sent.body["username"], "zhang");
+        assert_eq!(sent.body["password"], "secret");
+        assert_eq!(session.user.username, "zhang");
+        assert_eq!(session.user.display_name, "张医生");
+        assert_eq!(session.user.institution_id.as_deref(), Some("h1"));
+        assert!(session.user.permissions.allows("ris", Permission::Approve));
+        assert!(!session.user.permissions.allows("pis", Permission::Read));
+        assert_eq!(session.token, "jwt");
+        assert_eq!(session.refresh_token.as_deref(), Some("r1"));
+        let expires_at = session.expires_at.unwrap();
+        assert!(expires_at >= before + TimeDelta::seconds(3600));
+        assert!(expires_at <= Utc::now() + TimeDelta::seconds(3600));
+    }
+
+    #[test]
+    fn grant_expiry_prefers_expires_at() {
+        let grant = |expires_at: Option<&str>, expires_in: Option<u64>| AuthGrant {
+            token: "t".to_string(),
+            expires_at: expires_at.map(str::to_string),
+            expires_in,
+            refresh_token: None,
+        };
+
+        let at = grant(Some("2030-01-02T03:04:05+08:00"), Some(60)).expiry().unwrap();
+        assert_eq!(at.to_rfc3339(), "2030-01-01T19:04:05+00:00");
+
+        // An unparsable time falls back to the lifetime
+        let at = grant(Some("tomorrow"), Some(60)).expiry().unwrap();
+        assert!(at > Utc::now() && at <= Utc::now() + TimeDelta::seconds(60));
+
+        assert_eq!(grant(None, None).expiry(), None);
+        assert_eq!(grant(None, Some(u64::MAX)).expiry(), None);
+        assert_eq!(grant(None, Some(i64::MAX as u64)).expiry(), None);
+    }
+
+    #[tokio::test]
+    async fn refresh_exchanges_the_token() {
+        let server = MockServer::start(vec![Reply::Json(
+            200,
+            json!({"token": "new-token", "expires_at": "2030-01-01T00:00:00Z"}),
+        )])
+        .await;
+
+        let refreshed = client(&server.url).refresh(&session(None)).await.unwrap();
+
+        let sent = &server.requests()[0];
+        assert_eq!(sent.path, "/api/auth/refresh");
+        assert_eq!(sent.header("authorization"), Some("Bearer old-token"));
+        assert_eq!(sent.body["refresh_token"], "refresh-1");
+        assert_eq!(refreshed.token, "new-token");
+        let expires_at = refreshed.expires_at.map(|t| t.to_rfc3339());
+        assert_eq!(expires_at.as_deref(), Some("2030-01-01T00:00:00+00:00"));
+        assert_eq!(refreshed.refresh_token, None);
+    }
+
+    #[tokio::test]
+    async fn rejected_refresh_expires_the_session() {
+        let server = MockServer::start(vec![Reply::Json(401, json!({"message": "expired"}))]).await;
+        let err = client(&server.url).refresh(&session(None)).await.unwrap_err();
+        assert_eq!(err, AuthError::SessionExpired);
+
+        let server = MockServer::start(vec![Reply::Json(200, json!({"token": ""}))]).await;
+        let err = client(&server.url).refresh(&session(None)).await.unwrap_err();
+        assert_eq!(err, AuthError::InvalidResponse("empty token".to_string()));
+    }
 }
@@ -4,6 +4,19 @@ use std::fs;
 use std::path::PathBuf;
 use tracing::{error, info};
 
+use crate::ai::agent::AgentConfig;
+use crate::ai::cache::CacheConfig;
+use crate::ai::gateway::GatewayConfig;
+use crate::ai::history::HistoryConfig;
+use crate::ai::provider::{FixtureConfig, ProviderKind};
+use crate::ai::redact::RedactionConfig;
+use crate::ai::resilience::ResilienceConfig;
+use crate::ai::usage::QuotaConfig;
+use crate::auth::{AuthConfig, AuthState};
+use crate::permissions::Permission;
+use crate::secrets::{self, SecretStore};
+use crate::voice::VoiceConfig;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BusinessSystem {
     pub id: String,
@@ -17,6 +30,60 @@ pub struct BusinessSystem {
 pub struct AppConfig {
     pub business_systems: Vec<BusinessSystem>,
     pub user_preferences: UserPreferences,
+    #[serde(default)]
+    pub ai: AiConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub voice: VoiceConfig,
+}
+
+/// AI service configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiConfig {
+    #[serde(default)]
+    pub provider: ProviderKind,
+    pub endpoint: String,
+    pub api_key: String,
+    /// Model name, required by OpenAI-compatible servers
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub agent: AgentConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    #[serde(default)]
+    pub resilience: ResilienceConfig,
+    #[serde(default)]
+    pub gateway: GatewayConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    /// Record/replay of AI traffic, see `ai::provider::fixture`
+    #[serde(default)]
+    pub fixtures: FixtureConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            provider: ProviderKind::FastGpt,
+            endpoint: "http://localhost:3000".to_string(),
+            api_key: String::new(),
+            model: None,
+            agent: AgentConfig::default(),
+            redaction: RedactionConfig::default(),
+            resilience: ResilienceConfig::default(),
+            gateway: GatewayConfig::default(),
+            quota: QuotaConfig::default(),
+            history: HistoryConfig::default(),
+            fixtures: FixtureConfig::default(),
+            cache: CacheConfig::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +104,9 @@ impl Default for AppConfig {
                 voice_enabled: true,
                 memory_limit_mb: 500,
             },
+            ai: AiConfig::default(),
+            auth: AuthConfig::default(),
+            voice: VoiceConfig::default(),
         }
     }
 }
@@ -68,21 +138,41 @@ impl AppConfig {
         ]
     }
 
-    fn config_path() -> PathBuf {
+    /// Directory holding config.json and other user-editable settings
+    pub fn config_dir() -> PathBuf {
         dirs::config_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join("EWDesktopAgent")
-            .join("config.json")
     }
 
+    fn config_path() -> PathBuf {
+        Self::config_dir().join("config.json")
+    }
+
+    /// Load config.json with secrets filled in from the secret store
+    ///
+    /// Plaintext secrets still present in the file are moved to the store.
     pub fn load() -> Self {
+        let mut config = Self::load_file();
+        secrets::unseal_config(&mut config, &SecretStore::open_default());
+        config
+    }
+
+    fn load_file() -> Self {
         let path = Self::config_path();
         if path.exists() {
             match fs::read_to_string(&path) {
                 Ok(content) => {
-                    match serde_json::from_str(&content) {
-                        Ok(config) => {
+                    match serde_json::from_str::<Self>(&content) {
+                        Ok(mut config) => {
                             info!("Loaded config from {:?}", path);
+                            let moved = secrets::seal_config(&mut config, &SecretStore::open_default());
+                            if moved > 0 {
+                                match config.write() {
+                                    Ok(()) => info!("Moved {} plaintext secrets from config to the secret store", moved),
+                                    Err(e) => error!("Failed to remove migrated secrets from config: {}", e),
+                                }
+                            }
                             return config;
                         }
                         Err(e) => {
@@ -99,7 +189,14 @@ impl AppConfig {
         Self::default()
     }
 
+    /// Save config.json, keeping secrets in the secret store
     pub fn save(&self) -> Result<(), std::io::Error> {
+        let mut config = self.clone();
+        secrets::seal_config(&mut config, &SecretStore::open_default());
+        config.write()
+    }
+
+    fn write(&self) -> Result<(), std::io::Error> {
         let path = Self::config_path();
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
@@ -120,7 +217,8 @@ pub fn load_business_systems() -> Result<Vec<BusinessSystem>, String> {
 }
 
 #[tauri::command]
-pub fn save_business_system(system: BusinessSystem) -> Result<(), String> {
+pub fn save_business_system(auth: tauri::State<AuthState>, system: BusinessSystem) -> Result<(), String> {
+    auth.authorize("save_business_system", None, Permission::Admin)?;
     let mut config = AppConfig::load();
 
     // Update or add the business system
@@ -135,5 +233,5 @@ pub fn save_business_system(system: BusinessSystem) -> Result<(), String> {
 
 #[tauri::command]
 pub fn get_app_config() -> Result<AppConfig, String> {
-    Ok(AppConfig::load())
+    Ok(secrets::redacted_config(&AppConfig::load()))
 }
@@ -1,22 +1,55 @@
 // Security module - Risk assessment and operation control
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
 use tracing::{error, info};
 
-/// Risk level enum
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+use crate::config::AppConfig;
+
+/// Override of the built-in rules in the config directory
+const RULES_FILE: &str = "risk_rules.json";
+const BUILTIN_RULES: &str = include_str!("risk_rules.json");
+
+/// Risk level enum, ordered from lowest to highest
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum RiskLevel {
     Low,
     Medium,
     High,
 }
 
+/// How an assessed operation may be executed
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Execute without asking
+    Auto,
+    /// Show what will happen before executing
+    Preview,
+    /// Require explicit user confirmation
+    Confirm,
+}
+
+impl ExecutionMode {
+    fn for_rule(level: &RiskLevel, requires_confirmation: bool, show_preview: bool) -> Self {
+        match level {
+            _ if requires_confirmation => ExecutionMode::Confirm,
+            RiskLevel::High => ExecutionMode::Confirm,
+            RiskLevel::Medium => ExecutionMode::Preview,
+            RiskLevel::Low if show_preview => ExecutionMode::Preview,
+            RiskLevel::Low => ExecutionMode::Auto,
+        }
+    }
+}
+
 /// Risk assessment result
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RiskAssessment {
     pub level: RiskLevel,
     pub reason: String,
     pub requires_confirmation: bool,
+    pub execution_mode: ExecutionMode,
 }
 
 /// Risk rule pattern
@@ -36,6 +69,8 @@ pub struct RiskRule {
     pub risk_level: String,
     pub patterns: Vec<RiskPattern>,
     pub requires_confirmation: bool,
+    #[serde(default)]
+    pub show_preview: bool,
     pub allowed_on_whitelist: bool,
 }
 
@@ -60,75 +95,92 @@ pub struct RiskEngine {
 }
 
 impl RiskEngine {
-    /// Load risk rules from JSON file
+    /// Rules from `risk_rules.json` in the config directory, or the built-in ones
     pub fn load() -> Self {
-        let config_path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-            .join("src")
-            .join("core")
-            .join("security")
-            .join("risk_rules.json");
-
-        if config_path.exists() {
-            match fs::read_to_string(&config_path) {
-                Ok(content) => {
-                    match serde_json::from_str(&content) {
-                        Ok(config) => {
-                            info!("Loaded risk rules from {:?}", config_path);
-                            return Self { config };
-                        }
-                        Err(e) => {
-                            error!("Failed to parse risk rules: {}", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to read risk rules: {}", e);
-                }
+        let path = AppConfig::config_dir().join(RULES_FILE);
+        if path.exists() {
+            if let Some(engine) = Self::from_file(&path) {
+                return engine;
             }
         }
+        info!("Using built-in risk rules");
+        Self::builtin()
+    }
 
-        // Return default config if file not found or parse error
-        info!("Using default risk rules");
-        Self::default()
+    /// Engine shared by all assessments, loaded on first use
+    pub fn shared() -> &'static Self {
+        static ENGINE: OnceLock<RiskEngine> = OnceLock::new();
+        ENGINE.get_or_init(Self::load)
+    }
+
+    fn from_file(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| error!("Failed to read risk rules {:?}: {}", path, e))
+            .ok()?;
+        let config = serde_json::from_str(&content)
+            .map_err(|e| error!("Failed to parse risk rules {:?}: {}", path, e))
+            .ok()?;
+        info!("Loaded risk rules from {:?}", path);
+        Some(Self { config })
+    }
+
+    /// Rules compiled into the binary from `risk_rules.json`
+    fn builtin() -> Self {
+        Self {
+            config: serde_json::from_str(BUILTIN_RULES).expect("built-in risk rules are valid"),
+        }
     }
 
     /// Assess risk for an action
     pub fn assess(&self, action_type: &str, target: &str) -> RiskAssessment {
-        // Check whitelist first
-        if self.config.whitelist.enabled {
-            if self.is_whitelisted(target) {
-                return RiskAssessment {
-                    level: RiskLevel::Low,
-                    reason: "Target is on whitelist".to_string(),
-                    requires_confirmation: false,
-                };
-            }
-        }
+        let whitelisted = self.config.whitelist.enabled && self.is_whitelisted(target);
 
-        // Check rules in order (High -> Medium -> Low)
+        // The first matching rule wins
         for rule in &self.config.rules {
             for pattern in &rule.patterns {
                 if self.matches_pattern(action_type, target, pattern) {
+                    // Whitelisted targets only downgrade rules that allow it
+                    if whitelisted && rule.allowed_on_whitelist {
+                        return Self::whitelisted();
+                    }
+
                     let level = match rule.risk_level.as_str() {
                         "High" => RiskLevel::High,
                         "Medium" => RiskLevel::Medium,
                         _ => RiskLevel::Low,
                     };
+                    let execution_mode =
+                        ExecutionMode::for_rule(&level, rule.requires_confirmation, rule.show_preview);
 
                     return RiskAssessment {
                         level,
                         reason: pattern.reason.clone(),
                         requires_confirmation: rule.requires_confirmation,
+                        execution_mode,
                     };
                 }
             }
         }
 
+        if whitelisted {
+            return Self::whitelisted();
+        }
+
         // Default to medium risk if no match
         RiskAssessment {
             level: RiskLevel::Medium,
             reason: "No specific rule matched, assuming medium risk".to_string(),
             requires_confirmation: false,
+            execution_mode: ExecutionMode::Preview,
+        }
+    }
+
+    fn whitelisted() -> RiskAssessment {
+        RiskAssessment {
+            level: RiskLevel::Low,
+            reason: "Target is on whitelist".to_string(),
+            requires_confirmation: false,
+            execution_mode: ExecutionMode::Auto,
         }
     }
 
@@ -187,46 +239,75 @@ impl RiskEngine {
 
         true
     }
-
-    /// Get default configuration
-    fn default() -> Self {
-        Self {
-            config: RiskRulesConfig {
-                version: "1.0".to_string(),
-                rules: vec![
-                    RiskRule {
-                        id: "high_risk".to_string(),
-                        name: "High Risk".to_string(),
-                        description: "High risk operations".to_string(),
-                        risk_level: "High".to_string(),
-                        patterns: vec![
-                            RiskPattern {
-                                action_type: "submit".to_string(),
-                                target_match: "*".to_string(),
-                                reason: "Form submission may change data".to_string(),
-                            },
-                            RiskPattern {
-                                action_type: "delete".to_string(),
-                                target_match: "*".to_string(),
-                                reason: "Delete is irreversible".to_string(),
-                            },
-                        ],
-                        requires_confirmation: true,
-                        allowed_on_whitelist: false,
-                    },
-                ],
-                whitelist: Whitelist {
-                    enabled: true,
-                    domains: vec!["localhost".to_string()],
-                },
-            },
-        }
-    }
 }
 
 /// Tauri command for risk assessment
 #[tauri::command]
 pub fn assess_risk(action_type: String, target: String) -> RiskAssessment {
-    let engine = RiskEngine::load();
-    engine.assess(&action_type, &target)
+    RiskEngine::shared().assess(&action_type, &target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assess(action_type: &str, target: &str) -> (RiskLevel, ExecutionMode) {
+        let assessment = RiskEngine::builtin().assess(action_type, target);
+        (assessment.level, assessment.execution_mode)
+    }
+
+    #[test]
+    fn state_changing_actions_need_confirmation() {
+        let confirm = (RiskLevel::High, ExecutionMode::Confirm);
+        assert_eq!(assess("submit", "#btn-save"), confirm);
+        assert_eq!(assess("submit", "#report-form"), confirm);
+        assert_eq!(assess("execute", "ris.report.approve"), confirm);
+        assert_eq!(assess("execute", "pis.report.sign"), confirm);
+        assert_eq!(assess("delete", "#row-3"), confirm);
+    }
+
+    #[test]
+    fn rules_match_by_type_and_target() {
+        assert_eq!(assess("execute", "ris.query.patient"), (RiskLevel::Low, ExecutionMode::Auto));
+        assert_eq!(assess("scroll", "#list"), (RiskLevel::Low, ExecutionMode::Auto));
+        assert_eq!(assess("click", "https://ris.example/preview.png"), (RiskLevel::Low, ExecutionMode::Auto));
+        assert_eq!(assess("download", "https://files.example/setup.EXE"), (RiskLevel::Medium, ExecutionMode::Preview));
+        // No rule: medium risk with a preview
+        assert_eq!(assess("download", "https://files.example/report.pdf"), (RiskLevel::Medium, ExecutionMode::Preview));
+        assert_eq!(assess("input", "#patient-name"), (RiskLevel::Medium, ExecutionMode::Preview));
+    }
+
+    #[test]
+    fn whitelist_downgrades_only_rules_that_allow_it() {
+        let low = (RiskLevel::Low, ExecutionMode::Auto);
+        assert_eq!(assess("download", "https://pacs.hospital.com/setup.exe"), low);
+        assert_eq!(assess("input", "http://localhost:8080/#name"), low);
+        assert_eq!(assess("delete", "https://pacs.hospital.com/#row-3"), (RiskLevel::High, ExecutionMode::Confirm));
+        assert_eq!(assess("submit", "http://localhost/form"), (RiskLevel::High, ExecutionMode::Confirm));
+    }
+
+    #[test]
+    fn execution_mode_follows_level_and_flags() {
+        use ExecutionMode::*;
+        assert_eq!(ExecutionMode::for_rule(&RiskLevel::High, false, false), Confirm);
+        assert_eq!(ExecutionMode::for_rule(&RiskLevel::Medium, false, false), Preview);
+        assert_eq!(ExecutionMode::for_rule(&RiskLevel::Medium, true, false), Confirm);
+        assert_eq!(ExecutionMode::for_rule(&RiskLevel::Low, false, false), Auto);
+        assert_eq!(ExecutionMode::for_rule(&RiskLevel::Low, false, true), Preview);
+        assert_eq!(ExecutionMode::for_rule(&RiskLevel::Low, true, false), Confirm);
+    }
+
+    #[test]
+    fn rules_file_overrides_the_builtin_rules() {
+        let path = std::env::temp_dir().join(format!("risk-{}.json", uuid::Uuid::new_v4()));
+        let mut config = RiskEngine::builtin().config;
+        config.rules.retain(|r| r.id != "high_risk_operations");
+        fs::write(&path, serde_json::to_string(&config).unwrap()).unwrap();
+        let engine = RiskEngine::from_file(&path).unwrap();
+        assert_eq!(engine.assess("delete", "#row-3").level, RiskLevel::Medium);
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(RiskEngine::from_file(&path).is_none());
+        fs::remove_file(path).ok();
+    }
 }
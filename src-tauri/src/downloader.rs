@@ -3,6 +3,9 @@ use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use tracing::info;
 
+use crate::auth::AuthState;
+use crate::permissions::Permission;
+
 /// Download request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DownloadRequest {
@@ -99,6 +102,10 @@ impl DownloadService {
 /// Tauri command for downloading
 
 #[tauri::command]
-pub async fn download_file(request: DownloadRequest) -> Result<DownloadResult, String> {
+pub async fn download_file(
+    auth: tauri::State<'_, AuthState>,
+    request: DownloadRequest,
+) -> Result<DownloadResult, String> {
+    auth.authorize("download_file", None, Permission::Write)?;
     DownloadService::download(request).await
 }
@@ -6,6 +6,9 @@ use std::path::PathBuf;
 use std::sync::Mutex;
 use tracing::info;
 
+use crate::auth::AuthState;
+use crate::permissions::Permission;
+
 /// File operation permission
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FilePermission {
@@ -230,12 +233,21 @@ impl FileService {
 /// Tauri commands for file operations
 
 #[tauri::command]
-pub fn execute_file_operation(operation: FileOperation) -> Result<OperationResult, String> {
+pub fn execute_file_operation(
+    auth: tauri::State<AuthState>,
+    operation: FileOperation,
+) -> Result<OperationResult, String> {
+    auth.authorize("execute_file_operation", None, Permission::Write)?;
     FileService::execute_operation(operation)
 }
 
 #[tauri::command]
-pub fn preview_organization(source_dir: String, rule: String) -> Result<Vec<String>, String> {
+pub fn preview_organization(
+    auth: tauri::State<AuthState>,
+    source_dir: String,
+    rule: String,
+) -> Result<Vec<String>, String> {
+    auth.authorize("preview_organization", None, Permission::Read)?;
     FileService::preview_organization(&source_dir, &rule)
 }
 
@@ -246,14 +258,16 @@ pub fn check_file_permission(path: String, operation: String) -> bool {
 }
 
 #[tauri::command]
-pub fn grant_file_permission(path: String) -> Result<(), String> {
+pub fn grant_file_permission(auth: tauri::State<AuthState>, path: String) -> Result<(), String> {
+    auth.authorize("grant_file_permission", None, Permission::Admin)?;
     let manager = PermissionManager::default();
     manager.grant_permission(&path);
     Ok(())
 }
 
 #[tauri::command]
-pub fn revoke_file_permission(path: String) -> Result<(), String> {
+pub fn revoke_file_permission(auth: tauri::State<AuthState>, path: String) -> Result<(), String> {
+    auth.authorize("revoke_file_permission", None, Permission::Admin)?;
     let manager = PermissionManager::default();
     manager.revoke_permission(&path);
     Ok(())
@@ -13,6 +13,9 @@ pub mod personalization;
 pub mod file_ops;
 pub mod downloader;
 pub mod notification;
+pub mod secrets;
+pub mod permissions;
+pub mod lock;
 
 use browser::{init_browser_state};
 use tauri::Manager;
@@ -57,6 +60,9 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .manage(init_browser_state())
+        .manage(ai::AiState::default())
+        .manage(auth::AuthState::default())
+        .manage(ai::gateway::GatewayClient::from_config(&config::AppConfig::load().ai))
         .invoke_handler(tauri::generate_handler![
             browser::create_browser_tab,
             browser::close_browser_tab,
@@ -74,8 +80,32 @@ pub fn run() {
             storage::log_audit,
             storage::query_audit_logs,
             core::security::assess_risk,
+            ai::stream::ai_cancel_stream,
+            ai::session::ai_start_conversation,
+            ai::session::ai_send_message,
+            ai::session::ai_get_history,
+            ai::agent::ai_run_agent,
+            ai::agent::ai_report_action_result,
+            ai::intent::ai_parse_intent,
+            ai::resilience::ai_connection_status,
+            ai::usage::ai_get_usage_summary,
+            ai::prompts::ai_reload_prompts,
+            ai::cache::ai_clear_answer_cache,
+            ai::capabilities::ai_get_capabilities,
+            ai::capabilities::ai_reload_capabilities,
+            ai::gateway::gateway_connect,
+            ai::gateway::gateway_disconnect,
+            ai::gateway::gateway_get_capabilities,
+            ai::gateway::gateway_ai_request,
             auth::login,
             auth::logout,
+            auth::get_current_user,
+            auth::get_auth_token,
+            lock::report_activity,
+            lock::lock_session,
+            lock::unlock_session,
+            lock::get_lock_status,
+            lock::set_lock_pin,
             reminder::create_reminder_rule,
             reminder::get_reminder_rules,
             reminder::update_reminder_rule,
@@ -94,6 +124,9 @@ pub fn run() {
             downloader::download_file,
             notification::show_notification,
             notification::request_notification_permission,
+            secrets::set_secret,
+            secrets::delete_secret,
+            secrets::has_secret,
         ])
         .setup(|app| {
             info!("Application setup complete");
@@ -0,0 +1,571 @@
+// Session lock - locks an unattended workstation until the user re-authenticates
+//
+// The frontend reports user activity (input events, throttled on its side)
+// with `report_activity`. A monitor started at login locks the session after
+// `idle_timeout_secs` without activity, and `lock_session` locks it at once.
+// While locked, commands that need the signed-in user fail, webview windows
+// other than the main one are hidden and the frontend shows its lock screen.
+// Tabs and the login session are kept, so unlocking with the user's PIN or
+// password continues where they left off. Lock and unlock are audited.
+//
+// PINs are stored as Argon2 hashes in the secret store; hashes of the older
+// iterated SHA-256 scheme still verify and are replaced on the next unlock.
+use aes_gcm::aead::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Emitter, Manager};
+use tracing::{info, warn};
+
+use crate::auth::{AuthClient, AuthState, User};
+use crate::config::AppConfig;
+use crate::secrets::SecretStore;
+use crate::storage::Database;
+
+/// Event telling the frontend to show the lock screen
+pub const LOCKED_EVENT: &str = "session-locked";
+/// Event telling the frontend to hide the lock screen
+pub const UNLOCKED_EVENT: &str = "session-unlocked";
+
+/// Window that shows the lock screen and stays visible
+const MAIN_WINDOW: &str = "main";
+/// Secret holding the PIN hash of a user, followed by the user id
+const PIN_SECRET_PREFIX: &str = "lock.pin.";
+/// Rounds of the iterated SHA-256 hashes stored before Argon2
+const LEGACY_PIN_ROUNDS: u32 = 100_000;
+/// Longest sleep of the idle monitor, so config changes are picked up
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Idle lock settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockConfig {
+    pub enabled: bool,
+    pub idle_timeout_secs: u64,
+    /// Wrong PINs before only the password unlocks
+    pub max_pin_attempts: u32,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            idle_timeout_secs: 300,
+            max_pin_attempts: 5,
+        }
+    }
+}
+
+/// Why the session was locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    Idle,
+    Manual,
+}
+
+/// Lock state as shown by the lock screen
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockStatus {
+    pub locked: bool,
+    pub reason: Option<LockReason>,
+    pub locked_at: Option<String>,
+    pub pin_set: bool,
+    /// `false` after too many wrong PINs
+    pub pin_allowed: bool,
+    pub idle_timeout_secs: u64,
+}
+
+struct Locked {
+    reason: LockReason,
+    at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct LockInner {
+    last_activity: Option<Instant>,
+    locked: Option<Locked>,
+    failed_pins: u32,
+    /// Windows hidden by the lock, shown again on unlock
+    hidden_windows: Vec<String>,
+}
+
+/// Lock of the signed-in session, part of `AuthState`
+#[derive(Default)]
+pub struct SessionLock {
+    inner: Mutex<LockInner>,
+    monitor: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl SessionLock {
+    pub fn is_locked(&self) -> bool {
+        self.inner.lock().map(|i| i.locked.is_some()).unwrap_or(true)
+    }
+
+    /// Record user activity; ignored while locked
+    pub fn touch(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if inner.locked.is_none() {
+                inner.last_activity = Some(Instant::now());
+            }
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.inner
+            .lock()
+            .ok()
+            .and_then(|i| i.last_activity)
+            .map(|t| t.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Unlocked state for a new session
+    pub fn reset(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            *inner = LockInner {
+                last_activity: Some(Instant::now()),
+                ..LockInner::default()
+            };
+        }
+    }
+
+    /// Show the windows hidden by the lock, for a session that ends without unlocking
+    pub fn restore_windows(&self, app: &AppHandle) {
+        let hidden = self
+            .inner
+            .lock()
+            .map(|mut i| std::mem::take(&mut i.hidden_windows))
+            .unwrap_or_default();
+        show_windows(app, hidden);
+    }
+
+    /// Lock idle sessions in the background until logout
+    pub fn start_monitor(&self, app: AppHandle) {
+        let task = tauri::async_runtime::spawn(idle_loop(app));
+        if let Ok(mut monitor) = self.monitor.lock() {
+            if let Some(previous) = monitor.replace(task) {
+                previous.abort();
+            }
+        }
+    }
+
+    /// Stop the monitor and forget the lock, on logout
+    pub fn stop(&self) {
+        if let Some(task) = self.monitor.lock().ok().and_then(|mut m| m.take()) {
+            task.abort();
+        }
+        self.reset();
+    }
+
+    /// Lock unless already locked; `false` if it was
+    fn engage(&self, reason: LockReason) -> bool {
+        let Ok(mut inner) = self.inner.lock() else {
+            return false;
+        };
+        if inner.locked.is_some() {
+            return false;
+        }
+        inner.locked = Some(Locked { reason, at: Utc::now() });
+        true
+    }
+
+    /// Unlock, returning how long the session was locked and the windows to show again
+    fn release(&self) -> (Duration, Vec<String>) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return (Duration::ZERO, Vec::new());
+        };
+        let locked_for = inner
+            .locked
+            .take()
+            .and_then(|l| (Utc::now() - l.at).to_std().ok())
+            .unwrap_or_default();
+        inner.failed_pins = 0;
+        inner.last_activity = Some(Instant::now());
+        (locked_for, std::mem::take(&mut inner.hidden_windows))
+    }
+
+    /// Whether an idle session is due to lock, and how long to sleep until the next check
+    fn idle_check(&self, config: &LockConfig) -> (bool, Duration) {
+        let timeout = Duration::from_secs(config.idle_timeout_secs.max(1));
+        let idle = self.idle_for();
+        let due = config.enabled && idle >= timeout && !self.is_locked();
+        let wait = timeout.checked_sub(idle).unwrap_or(timeout);
+        (due, wait.clamp(Duration::from_secs(1), MAX_CHECK_INTERVAL))
+    }
+
+    fn pin_failed(&self) -> u32 {
+        self.inner
+            .lock()
+            .map(|mut i| {
+                i.failed_pins += 1;
+                i.failed_pins
+            })
+            .unwrap_or(u32::MAX)
+    }
+
+    /// Check a PIN against its stored hash, unless too many wrong ones were entered
+    ///
+    /// `Ok(None)` when it matches, otherwise the number of wrong PINs so far.
+    fn attempt_pin(&self, stored: &str, pin: &str, max_attempts: u32) -> Result<Option<u32>, String> {
+        let exhausted = self.inner.lock().map(|i| i.failed_pins >= max_attempts).unwrap_or(true);
+        if exhausted {
+            return Err("PIN 错误次数过多，请使用密码解锁".to_string());
+        }
+        if check_pin(stored, pin)? {
+            Ok(None)
+        } else {
+            Ok(Some(self.pin_failed()))
+        }
+    }
+
+    fn status(&self, user: Option<&User>, config: &LockConfig) -> LockStatus {
+        self.status_with_pin(user.is_some_and(|u| has_pin(&u.id)), config)
+    }
+
+    fn status_with_pin(&self, pin_set: bool, config: &LockConfig) -> LockStatus {
+        let Ok(inner) = self.inner.lock() else {
+            return LockStatus {
+                locked: true,
+                reason: None,
+                locked_at: None,
+                pin_set,
+                pin_allowed: false,
+                idle_timeout_secs: config.idle_timeout_secs,
+            };
+        };
+        LockStatus {
+            locked: inner.locked.is_some(),
+            reason: inner.locked.as_ref().map(|l| l.reason),
+            locked_at: inner.locked.as_ref().map(|l| l.at.to_rfc3339()),
+            pin_set,
+            pin_allowed: pin_set && inner.failed_pins < config.max_pin_attempts,
+            idle_timeout_secs: config.idle_timeout_secs,
+        }
+    }
+}
+
+/// Lock once the user has been idle for the configured timeout
+async fn idle_loop(app: AppHandle) {
+    loop {
+        let config = AppConfig::load().auth.lock;
+        let auth = app.state::<AuthState>();
+        if auth.current_user().is_none() {
+            return;
+        }
+
+        let (due, wait) = auth.lock.idle_check(&config);
+        if due {
+            lock(&app, LockReason::Idle);
+        }
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Lock the session, hide secondary windows and tell the frontend
+pub fn lock(app: &AppHandle, reason: LockReason) -> Option<LockStatus> {
+    let auth = app.state::<AuthState>();
+    let user = auth.current_user()?;
+    let config = AppConfig::load().auth.lock;
+    if !auth.lock.engage(reason) {
+        return Some(auth.lock.status(Some(&user), &config));
+    }
+
+    let mut hidden = Vec::new();
+    for (label, window) in app.webview_windows() {
+        if label != MAIN_WINDOW && window.is_visible().unwrap_or(false) {
+            match window.hide() {
+                Ok(()) => hidden.push(label),
+                Err(e) => warn!("Failed to hide window {}: {}", label, e),
+            }
+        }
+    }
+    if let Ok(mut inner) = auth.lock.inner.lock() {
+        inner.hidden_windows = hidden;
+    }
+
+    info!("Session of user {} locked ({:?})", user.id, reason);
+    audit(&user, "session_locked", json!({ "reason": reason }), "Low");
+    let status = auth.lock.status(Some(&user), &config);
+    if let Err(e) = app.emit(LOCKED_EVENT, &status) {
+        warn!("Failed to emit {}: {}", LOCKED_EVENT, e);
+    }
+    Some(status)
+}
+
+fn show_windows(app: &AppHandle, labels: Vec<String>) {
+    for label in labels {
+        if let Some(window) = app.get_webview_window(&label) {
+            if let Err(e) = window.show() {
+                warn!("Failed to show window {}: {}", label, e);
+            }
+        }
+    }
+}
+
+fn audit(user: &User, action: &str, details: serde_json::Value, risk: &str) {
+    if let Err(e) = Database::open_default()
+        .and_then(|db| db.log_audit(Some(&user.id), action, &details.to_string(), risk))
+    {
+        warn!("Failed to write audit log: {}", e);
+    }
+}
+
+fn pin_secret(user_id: &str) -> String {
+    format!("{}{}", PIN_SECRET_PREFIX, user_id)
+}
+
+/// Argon2 hash of a PIN in PHC string format
+fn hash_pin(pin: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| format!("无法保存 PIN: {}", e))
+}
+
+/// Whether `pin` matches a stored hash, Argon2 or legacy "salt$hash"
+fn check_pin(stored: &str, pin: &str) -> Result<bool, String> {
+    const CORRUPT: &str = "PIN 数据已损坏，请使用密码解锁";
+    if is_legacy_pin(stored) {
+        let (salt, hash) = stored.split_once('$').ok_or(CORRUPT)?;
+        let salt = BASE64.decode(salt).map_err(|_| CORRUPT)?;
+        let hash = BASE64.decode(hash).map_err(|_| CORRUPT)?;
+        return Ok(legacy_hash_pin(&salt, pin) == hash);
+    }
+    let hash = PasswordHash::new(stored).map_err(|_| CORRUPT)?;
+    Ok(Argon2::default().verify_password(pin.as_bytes(), &hash).is_ok())
+}
+
+fn is_legacy_pin(stored: &str) -> bool {
+    !stored.starts_with('$')
+}
+
+fn legacy_hash_pin(salt: &[u8], pin: &str) -> Vec<u8> {
+    let mut digest = Sha256::new().chain_update(salt).chain_update(pin.as_bytes()).finalize();
+    for _ in 1..LEGACY_PIN_ROUNDS {
+        digest = Sha256::new().chain_update(digest).chain_update(salt).finalize();
+    }
+    digest.to_vec()
+}
+
+fn has_pin(user_id: &str) -> bool {
+    SecretStore::open_default().get(&pin_secret(user_id)).is_ok_and(|p| p.is_some())
+}
+
+fn stored_pin(user_id: &str) -> Result<String, String> {
+    SecretStore::open_default()
+        .get(&pin_secret(user_id))?
+        .ok_or_else(|| "尚未设置解锁 PIN，请使用密码解锁".to_string())
+}
+
+/// Check the password of the signed-in user with the authentication service
+async fn verify_password(user: &User, password: &str) -> Result<(), String> {
+    let config = AppConfig::load().auth;
+    if config.mock_enabled() {
+        return if password.is_empty() { Err("请输入密码".to_string()) } else { Ok(()) };
+    }
+    let session = AuthClient::new(&config)
+        .login(&user.username, password)
+        .await
+        .map_err(|e| e.to_string())?;
+    if session.user.id != user.id {
+        return Err("账号与当前登录用户不一致".to_string());
+    }
+    Ok(())
+}
+
+/// Tauri commands for the session lock
+
+#[tauri::command]
+pub fn report_activity(auth: tauri::State<AuthState>) {
+    auth.lock.touch();
+}
+
+#[tauri::command]
+pub fn lock_session(app: AppHandle) -> Result<LockStatus, String> {
+    lock(&app, LockReason::Manual).ok_or_else(|| "请先登录".to_string())
+}
+
+#[tauri::command]
+pub fn get_lock_status(auth: tauri::State<AuthState>) -> LockStatus {
+    auth.lock.status(auth.current_user().as_ref(), &AppConfig::load().auth.lock)
+}
+
+#[tauri::command]
+pub async fn unlock_session(
+    app: AppHandle,
+    auth: tauri::State<'_, AuthState>,
+    pin: Option<String>,
+    password: Option<String>,
+) -> Result<LockStatus, String> {
+    let user = auth.current_user().ok_or("请先登录")?;
+    let config = AppConfig::load().auth.lock;
+    if !auth.lock.is_locked() {
+        return Ok(auth.lock.status(Some(&user), &config));
+    }
+
+    let method = match (pin, password) {
+        (_, Some(password)) => {
+            if let Err(e) = verify_password(&user, &password).await {
+                audit(&user, "unlock_failed", json!({ "method": "password" }), "Medium");
+                return Err(e);
+            }
+            "password"
+        }
+        (Some(pin), None) => {
+            let stored = stored_pin(&user.id)?;
+            if let Some(failures) = auth.lock.attempt_pin(&stored, pin.trim(), config.max_pin_attempts)? {
+                audit(&user, "unlock_failed", json!({ "method": "pin", "failures": failures }), "Medium");
+                return Err("PIN 错误".to_string());
+            }
+            if is_legacy_pin(&stored) {
+                match hash_pin(pin.trim()) {
+                    Ok(hash) => SecretStore::open_default().set(&pin_secret(&user.id), &hash)?,
+                    Err(e) => warn!("Keeping legacy PIN hash of user {}: {}", user.id, e),
+                }
+            }
+            "pin"
+        }
+        (None, None) => return Err("请输入 PIN 或密码".to_string()),
+    };
+
+    let (locked_for, hidden) = auth.lock.release();
+    show_windows(&app, hidden);
+
+    info!("Session of user {} unlocked by {}", user.id, method);
+    audit(&user, "session_unlocked", json!({ "method": method, "locked_secs": locked_for.as_secs() }), "Low");
+    let status = auth.lock.status(Some(&user), &config);
+    if let Err(e) = app.emit(UNLOCKED_EVENT, &status) {
+        warn!("Failed to emit {}: {}", UNLOCKED_EVENT, e);
+    }
+    Ok(status)
+}
+
+/// Set the PIN of the signed-in user, confirmed with their password
+#[tauri::command]
+pub async fn set_lock_pin(auth: tauri::State<'_, AuthState>, pin: String, password: String) -> Result<(), String> {
+    let user = auth.require_user()?;
+    let pin = pin.trim();
+    if !(4..=8).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err("PIN 必须为 4 到 8 位数字".to_string());
+    }
+    verify_password(&user, &password).await?;
+
+    SecretStore::open_default().set(&pin_secret(&user.id), &hash_pin(pin)?)?;
+    audit(&user, "lock_pin_set", json!({}), "Low");
+    info!("Unlock PIN set for user {}", user.id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn idle_lock(idle_secs: u64) -> SessionLock {
+        let lock = SessionLock::default();
+        lock.reset();
+        lock.inner.lock().unwrap().last_activity = Some(Instant::now() - Duration::from_secs(idle_secs));
+        lock
+    }
+
+    fn config(idle_timeout_secs: u64) -> LockConfig {
+        LockConfig {
+            idle_timeout_secs,
+            ..LockConfig::default()
+        }
+    }
+
+    #[test]
+    fn idle_session_locks_after_the_timeout() {
+        let lock = idle_lock(10);
+        assert_eq!(lock.idle_check(&config(5)), (true, Duration::from_secs(5)));
+        assert!(!lock.idle_check(&LockConfig { enabled: false, ..config(5) }).0);
+
+        // Not yet due: sleep until the timeout, at most the check interval
+        let (due, wait) = lock.idle_check(&config(60));
+        assert!(!due);
+        assert_eq!(wait, MAX_CHECK_INTERVAL);
+        let (due, wait) = lock.idle_check(&config(25));
+        assert!(!due);
+        assert!(wait <= Duration::from_secs(15) && wait >= Duration::from_secs(14));
+
+        lock.touch();
+        assert!(!lock.idle_check(&config(5)).0);
+    }
+
+    #[test]
+    fn locked_session_ignores_activity_until_released() {
+        let lock = idle_lock(10);
+        assert!(!lock.is_locked());
+        assert!(lock.engage(LockReason::Idle));
+        assert!(!lock.engage(LockReason::Manual));
+        assert!(lock.is_locked());
+        // Already locked, so the monitor does not lock again
+        assert!(!lock.idle_check(&config(5)).0);
+
+        lock.touch();
+        assert!(lock.idle_for() >= Duration::from_secs(10));
+        let status = lock.status_with_pin(true, &config(5));
+        assert!(status.locked);
+        assert_eq!(status.reason, Some(LockReason::Idle));
+
+        lock.inner.lock().unwrap().hidden_windows = vec!["tab-1".to_string()];
+        let (_, hidden) = lock.release();
+        assert_eq!(hidden, ["tab-1"]);
+        assert!(!lock.is_locked());
+        assert!(lock.idle_for() < Duration::from_secs(1));
+        assert!(!lock.status_with_pin(true, &config(5)).locked);
+    }
+
+    #[test]
+    fn pin_hash_verifies_only_the_same_pin() {
+        let stored = hash_pin("2468").unwrap();
+        assert!(stored.starts_with("$argon2"));
+        assert!(!stored.contains("2468"));
+        assert!(check_pin(&stored, "2468").unwrap());
+        assert!(!check_pin(&stored, "2469").unwrap());
+        assert_ne!(hash_pin("2468").unwrap(), stored);
+        assert!(check_pin("$argon2id$v=19$m=x$", "2468").is_err());
+    }
+
+    #[test]
+    fn legacy_pin_hash_still_verifies() {
+        let salt = [7u8; 16];
+        let stored = format!("{}${}", BASE64.encode(salt), BASE64.encode(legacy_hash_pin(&salt, "1357")));
+        assert!(is_legacy_pin(&stored));
+        assert!(check_pin(&stored, "1357").unwrap());
+        assert!(!check_pin(&stored, "1358").unwrap());
+        assert!(check_pin("not-a-hash", "1357").is_err());
+    }
+
+    #[test]
+    fn wrong_pins_are_limited() {
+        let lock = SessionLock::default();
+        lock.engage(LockReason::Manual);
+        let stored = hash_pin("2468").unwrap();
+        let config = LockConfig {
+            max_pin_attempts: 2,
+            ..LockConfig::default()
+        };
+
+        assert_eq!(lock.attempt_pin(&stored, "0000", config.max_pin_attempts), Ok(Some(1)));
+        assert!(lock.status_with_pin(true, &config).pin_allowed);
+        assert_eq!(lock.attempt_pin(&stored, "1111", config.max_pin_attempts), Ok(Some(2)));
+        assert!(!lock.status_with_pin(true, &config).pin_allowed);
+
+        // Out of attempts: even the right PIN is refused until a password unlock
+        assert!(lock.attempt_pin(&stored, "2468", config.max_pin_attempts).is_err());
+        lock.release();
+        assert_eq!(lock.attempt_pin(&stored, "2468", config.max_pin_attempts), Ok(None));
+    }
+}
@@ -0,0 +1,279 @@
+// Permissions - what a signed-in user may do
+//
+// Permissions are granted per business system, in the shape of the
+// architecture doc: {"ris": ["read", "write", "approve"], "pis": ["read"]}.
+// The "app" scope holds client-wide permissions such as changing the
+// configuration or reading other users' audit logs, and "*" stands for every
+// system and permission. The authentication service may send permissions with
+// the login; otherwise they come from the defaults for the user's role.
+//
+// Commands declare what they need with `AuthState::authorize`. Actions the
+// model proposes are checked against the capability they call.
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use tracing::warn;
+
+use crate::ai::{capabilities, ActionDiagnostic, ActionType, AiAction, AiResponse};
+use crate::auth::{AuthState, User};
+use crate::storage::Database;
+
+/// Scope of client-wide permissions
+pub const APP_SCOPE: &str = "app";
+
+/// Matches every system or every permission
+const ANY: &str = "*";
+
+/// Role used when a role has no entry of its own
+const DEFAULT_ROLE: &str = "default";
+
+/// A permission commands and capabilities can require
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+    Approve,
+    /// Client administration: configuration, secrets, all users' audit logs
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Approve => "approve",
+            Permission::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "read" => Some(Permission::Read),
+            "write" => Some(Permission::Write),
+            "approve" => Some(Permission::Approve),
+            "admin" => Some(Permission::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Permissions granted per scope, a business system id or `APP_SCOPE`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Permissions(BTreeMap<String, BTreeSet<String>>);
+
+impl Permissions {
+    pub fn is_empty(&self) -> bool {
+        self.0.values().all(BTreeSet::is_empty)
+    }
+
+    /// Whether `permission` is granted in `scope`, directly or through "*"
+    pub fn allows(&self, scope: &str, permission: Permission) -> bool {
+        [scope, ANY].iter().filter_map(|s| self.0.get(*s)).any(|granted| {
+            granted.iter().any(|p| p == ANY || p.eq_ignore_ascii_case(permission.as_str()))
+        })
+    }
+}
+
+/// `permissions` in every scope
+fn everywhere(permissions: &[&str]) -> Permissions {
+    let granted = permissions.iter().map(|p| p.to_string()).collect();
+    Permissions(BTreeMap::from([(ANY.to_string(), granted)]))
+}
+
+/// Permissions of users the authentication service sends none for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionConfig {
+    /// By role; roles without an entry get the "default" one
+    pub roles: BTreeMap<String, Permissions>,
+}
+
+impl Default for PermissionConfig {
+    fn default() -> Self {
+        let roles = [
+            ("admin", everywhere(&[ANY])),
+            ("doctor", everywhere(&["read", "write", "approve"])),
+            ("technician", everywhere(&["read", "write"])),
+            ("nurse", everywhere(&["read", "write"])),
+            (DEFAULT_ROLE, everywhere(&["read"])),
+        ];
+        Self {
+            roles: roles.into_iter().map(|(role, p)| (role.to_string(), p)).collect(),
+        }
+    }
+}
+
+impl PermissionConfig {
+    pub fn for_role(&self, role: &str) -> Permissions {
+        self.roles
+            .get(role)
+            .or_else(|| self.roles.get(DEFAULT_ROLE))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Error returned for a denied call, in the style of an HTTP 403
+pub fn denied_message(scope: &str, permission: Permission) -> String {
+    if scope == APP_SCOPE {
+        format!("403 权限不足：需要 {} 权限", permission)
+    } else {
+        format!("403 权限不足：需要 {} 系统的 {} 权限", scope, permission)
+    }
+}
+
+/// Record a denied call in the audit log
+pub fn audit_denied(user: &User, command: &str, scope: &str, permission: Permission) {
+    warn!("Denied {} to user {} ({}): needs {}:{}", command, user.id, user.role, scope, permission);
+    let details = json!({
+        "command": command,
+        "scope": scope,
+        "permission": permission.as_str(),
+        "role": user.role,
+    });
+    if let Err(e) = Database::open_default()
+        .and_then(|db| db.log_audit(Some(&user.id), "permission_denied", &details.to_string(), "Medium"))
+    {
+        warn!("Failed to write audit log: {}", e);
+    }
+}
+
+/// Scope and permission an action needs, `None` for actions inside the open page
+///
+/// Page actions run in the business system's own session, which enforces its
+/// own rules. `execute` actions need what their capability declares in
+/// `auth_required` ("ris:approve"), and read access to the system otherwise.
+pub fn required_for(action: &AiAction) -> Result<Option<(String, Permission)>, String> {
+    if action.action_type != ActionType::Execute {
+        return Ok(None);
+    }
+    required_by(action, capabilities::auth_required(&action.target))
+}
+
+/// Scope and permission of an `execute` action whose capability declares `declared`
+fn required_by(action: &AiAction, declared: Option<String>) -> Result<Option<(String, Permission)>, String> {
+    let system = action
+        .system
+        .clone()
+        .or_else(|| action.target.split('.').next().map(str::to_string))
+        .unwrap_or_default();
+
+    let Some(declared) = declared else {
+        return Ok(Some((system, Permission::Read)));
+    };
+    let (scope, permission) = declared.split_once(':').unwrap_or((system.as_str(), declared.as_str()));
+    match Permission::parse(permission) {
+        Some(permission) => Ok(Some((scope.trim().to_string(), permission))),
+        None => Err(format!("能力 {} 声明了未知权限: {}", action.target, declared)),
+    }
+}
+
+/// Check one action for the signed-in user
+pub fn authorize_action(auth: &AuthState, command: &str, action: &AiAction) -> Result<(), String> {
+    match required_for(action)? {
+        Some((scope, permission)) => auth.authorize(command, Some(&scope), permission).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Move actions the signed-in user may not run into the diagnostics
+pub fn filter_actions(auth: &AuthState, command: &str, response: &mut AiResponse) {
+    for action in std::mem::take(&mut response.actions) {
+        match authorize_action(auth, command, &action) {
+            Ok(()) => response.actions.push(action),
+            Err(message) => {
+                let snippet = serde_json::to_string(&action).unwrap_or_default().chars().take(200).collect();
+                response.diagnostics.push(ActionDiagnostic { snippet, message });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permissions(value: serde_json::Value) -> Permissions {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn execute(target: &str, system: Option<&str>) -> AiAction {
+        AiAction {
+            action_type: ActionType::Execute,
+            target: target.to_string(),
+            value: None,
+            system: system.map(str::to_string),
+            params: Default::default(),
+            risk: None,
+        }
+    }
+
+    #[test]
+    fn allows_matches_scope_and_wildcards() {
+        let granted = permissions(json!({"ris": ["read", "APPROVE"], "pis": ["*"], "*": ["read"]}));
+        assert!(granted.allows("ris", Permission::Approve));
+        assert!(!granted.allows("ris", Permission::Write));
+        assert!(granted.allows("pis", Permission::Write));
+        assert!(granted.allows("lis", Permission::Read));
+        assert!(!granted.allows("lis", Permission::Write));
+        assert!(!granted.allows(APP_SCOPE, Permission::Admin));
+
+        assert!(Permissions::default().is_empty());
+        assert!(!Permissions::default().allows("ris", Permission::Read));
+        assert!(permissions(json!({"ris": []})).is_empty());
+    }
+
+    #[test]
+    fn roles_fall_back_to_the_default_role() {
+        let config = PermissionConfig::default();
+        assert!(config.for_role("admin").allows(APP_SCOPE, Permission::Admin));
+        assert!(config.for_role("doctor").allows("ris", Permission::Approve));
+        assert!(!config.for_role("doctor").allows(APP_SCOPE, Permission::Admin));
+        assert!(config.for_role("nurse").allows("pis", Permission::Write));
+        assert!(!config.for_role("nurse").allows("pis", Permission::Approve));
+
+        let unknown = config.for_role("visitor");
+        assert_eq!(unknown, config.for_role(DEFAULT_ROLE));
+        assert!(unknown.allows("ris", Permission::Read));
+        assert!(!unknown.allows("ris", Permission::Write));
+
+        let empty = PermissionConfig { roles: BTreeMap::new() };
+        assert!(empty.for_role("doctor").is_empty());
+    }
+
+    #[test]
+    fn page_actions_need_no_permission() {
+        let mut action = execute("#save", None);
+        action.action_type = ActionType::Click;
+        assert_eq!(required_for(&action), Ok(None));
+    }
+
+    #[test]
+    fn execute_actions_need_what_their_capability_declares() {
+        let read = |scope: &str| Ok(Some((scope.to_string(), Permission::Read)));
+        // Nothing declared: read access to the system of the action or the id
+        assert_eq!(required_by(&execute("ris.query.patient", None), None), read("ris"));
+        assert_eq!(required_by(&execute("query.patient", Some("pis")), None), read("pis"));
+
+        let declared = |value: &str| Some(value.to_string());
+        assert_eq!(
+            required_by(&execute("ris.report.approve", None), declared("ris:approve")),
+            Ok(Some(("ris".to_string(), Permission::Approve)))
+        );
+        assert_eq!(
+            required_by(&execute("ris.report.save", None), declared("Write")),
+            Ok(Some(("ris".to_string(), Permission::Write)))
+        );
+        assert!(required_by(&execute("ris.report.delete", None), declared("ris:delete")).is_err());
+    }
+}
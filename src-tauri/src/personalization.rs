@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tracing::info;
 
+use crate::auth::AuthState;
+
 /// User behavior log entry
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BehaviorLog {
@@ -77,10 +79,15 @@ impl PersonalizationEngine {
 /// Tauri commands for personalization
 
 #[tauri::command]
-pub fn log_behavior(action_type: String, target: Option<String>, metadata: Option<String>) -> Result<(), String> {
+pub fn log_behavior(
+    auth: tauri::State<AuthState>,
+    action_type: String,
+    target: Option<String>,
+    metadata: Option<String>,
+) -> Result<(), String> {
     let log = BehaviorLog {
         id: uuid::Uuid::new_v4().to_string(),
-        user_id: "default".to_string(), // TODO: Get from auth
+        user_id: auth.require_user()?.id,
         action_type,
         target,
         metadata,
@@ -90,16 +97,27 @@ pub fn log_behavior(action_type: String, target: Option<String>, metadata: Optio
 }
 
 #[tauri::command]
-pub fn analyze_user_patterns(user_id: String) -> Result<BehaviorPattern, String> {
-    PersonalizationEngine::analyze_patterns(&user_id)
+pub fn analyze_user_patterns(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+) -> Result<BehaviorPattern, String> {
+    PersonalizationEngine::analyze_patterns(&auth.user_for(user_id.as_deref())?.id)
 }
 
 #[tauri::command]
-pub fn get_recommendations(user_id: String) -> Result<Vec<Recommendation>, String> {
-    PersonalizationEngine::generate_recommendations(&user_id)
+pub fn get_recommendations(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+) -> Result<Vec<Recommendation>, String> {
+    PersonalizationEngine::generate_recommendations(&auth.user_for(user_id.as_deref())?.id)
 }
 
 #[tauri::command]
-pub fn submit_feedback(user_id: String, item_id: String, feedback: String) -> Result<(), String> {
-    PersonalizationEngine::learn_feedback(&user_id, &item_id, &feedback)
+pub fn submit_feedback(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+    item_id: String,
+    feedback: String,
+) -> Result<(), String> {
+    PersonalizationEngine::learn_feedback(&auth.user_for(user_id.as_deref())?.id, &item_id, &feedback)
 }
@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use std::sync::Mutex;
 use tracing::info;
 
+use crate::auth::AuthState;
+
 /// Reminder rule
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReminderRule {
@@ -53,8 +55,9 @@ impl Default for ReminderState {
 /// Tauri commands for reminders
 
 #[tauri::command]
-pub fn create_reminder_rule(rule: ReminderRule) -> Result<ReminderRule, String> {
-    info!("Creating reminder rule: {}", rule.name);
+pub fn create_reminder_rule(auth: tauri::State<AuthState>, rule: ReminderRule) -> Result<ReminderRule, String> {
+    let user = auth.require_user()?;
+    info!("Creating reminder rule {} for user {}", rule.name, user.id);
     let mut new_rule = rule;
     if new_rule.id.is_empty() {
         new_rule.id = uuid::Uuid::new_v4().to_string();
@@ -64,34 +67,42 @@ pub fn create_reminder_rule(rule: ReminderRule) -> Result<ReminderRule, String>
 }
 
 #[tauri::command]
-pub fn get_reminder_rules() -> Result<Vec<ReminderRule>, String> {
+pub fn get_reminder_rules(auth: tauri::State<AuthState>) -> Result<Vec<ReminderRule>, String> {
+    auth.require_user()?;
     // TODO: Load from database
     Ok(Vec::new())
 }
 
 #[tauri::command]
-pub fn update_reminder_rule(rule: ReminderRule) -> Result<(), String> {
-    info!("Updating reminder rule: {}", rule.id);
+pub fn update_reminder_rule(auth: tauri::State<AuthState>, rule: ReminderRule) -> Result<(), String> {
+    let user = auth.require_user()?;
+    info!("Updating reminder rule {} for user {}", rule.id, user.id);
     // TODO: Update in database
     Ok(())
 }
 
 #[tauri::command]
-pub fn delete_reminder_rule(rule_id: String) -> Result<(), String> {
-    info!("Deleting reminder rule: {}", rule_id);
+pub fn delete_reminder_rule(auth: tauri::State<AuthState>, rule_id: String) -> Result<(), String> {
+    let user = auth.require_user()?;
+    info!("Deleting reminder rule {} for user {}", rule_id, user.id);
     // TODO: Delete from database
     Ok(())
 }
 
 #[tauri::command]
-pub fn get_reminder_records(_user_id: String) -> Result<Vec<ReminderRecord>, String> {
+pub fn get_reminder_records(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+) -> Result<Vec<ReminderRecord>, String> {
+    auth.user_for(user_id.as_deref())?;
     // TODO: Load from database
     Ok(Vec::new())
 }
 
 #[tauri::command]
-pub fn mark_reminder_read(record_id: String) -> Result<(), String> {
-    info!("Marking reminder as read: {}", record_id);
+pub fn mark_reminder_read(auth: tauri::State<AuthState>, record_id: String) -> Result<(), String> {
+    let user = auth.require_user()?;
+    info!("Marking reminder {} as read for user {}", record_id, user.id);
     // TODO: Update in database
     Ok(())
 }
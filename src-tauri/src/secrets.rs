@@ -0,0 +1,601 @@
+// Secret store - credentials encrypted at rest
+//
+// Secrets live in secrets.json in the app data directory, each value
+// encrypted with AES-256-GCM and bound to its name. The key comes from a
+// `KeyProvider`: on Windows and macOS a random key kept in the OS credential
+// store (DPAPI / Keychain), so only the OS user can unlock the file; elsewhere
+// one derived from the machine id and the OS user.
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tracing::{error, info, warn};
+
+use crate::auth::AuthState;
+use crate::config::AppConfig;
+use crate::permissions::Permission;
+
+/// API key of the AI provider (`ai.api_key` in config.json)
+pub const AI_API_KEY: &str = "ai.api_key";
+/// API key of the speech recognition service
+pub const ASR_API_KEY: &str = "voice.asr_api_key";
+/// API key of the cloud TTS fallback
+pub const TTS_API_KEY: &str = "voice.tts_api_key";
+/// Business-system token exchanged at the gateway
+pub const SYSTEM_TOKEN: &str = "gateway.system_token";
+
+/// Secrets that may be set from the frontend
+pub const SECRET_NAMES: &[&str] = &[AI_API_KEY, ASR_API_KEY, TTS_API_KEY, SYSTEM_TOKEN];
+
+const FILE_VERSION: u32 = 1;
+const KEY_CONTEXT: &[u8] = b"EWDesktopAgent secret store v1";
+
+/// Source of the encryption key
+pub trait KeyProvider: Send + Sync {
+    /// Short name stored with the file, so a changed provider is noticed
+    fn name(&self) -> &'static str;
+
+    /// 256-bit key for a store; `salt` is random per store file
+    fn key(&self, salt: &str) -> Result<[u8; 32], String>;
+}
+
+/// Key bound to this machine and OS user
+pub struct MachineKeyProvider;
+
+impl KeyProvider for MachineKeyProvider {
+    fn name(&self) -> &'static str {
+        "machine"
+    }
+
+    fn key(&self, salt: &str) -> Result<[u8; 32], String> {
+        let machine = machine_id().ok_or("无法读取本机标识，密钥存储不可用")?;
+        let user = std::env::var("USERNAME")
+            .or_else(|_| std::env::var("USER"))
+            .unwrap_or_default();
+        let home = dirs::home_dir().map(|h| h.to_string_lossy().into_owned()).unwrap_or_default();
+
+        Ok(derive_key(&[machine.as_bytes(), user.as_bytes(), home.as_bytes(), salt.as_bytes()]))
+    }
+}
+
+/// Random master key in the OS credential store
+///
+/// Windows keeps it in the Credential Manager, encrypted with DPAPI for the
+/// OS user; macOS in the login Keychain. Other local users cannot read it,
+/// even when they can read secrets.json.
+#[cfg(any(windows, target_os = "macos"))]
+pub struct KeychainKeyProvider;
+
+#[cfg(any(windows, target_os = "macos"))]
+impl KeychainKeyProvider {
+    const SERVICE: &'static str = "EWDesktopAgent";
+    const ACCOUNT: &'static str = "secret-store";
+
+    fn master_key(&self) -> Result<Vec<u8>, String> {
+        let entry = keyring::Entry::new(Self::SERVICE, Self::ACCOUNT)
+            .map_err(|e| format!("无法访问系统凭据存储: {}", e))?;
+        match entry.get_password() {
+            Ok(encoded) => BASE64
+                .decode(encoded.trim())
+                .map_err(|_| "系统凭据存储中的主密钥已损坏".to_string()),
+            Err(keyring::Error::NoEntry) => {
+                let key = Aes256Gcm::generate_key(&mut OsRng).to_vec();
+                entry
+                    .set_password(&BASE64.encode(&key))
+                    .map_err(|e| format!("无法写入系统凭据存储: {}", e))?;
+                info!("Created secret store master key in the OS credential store");
+                Ok(key)
+            }
+            Err(e) => Err(format!("无法读取系统凭据存储: {}", e)),
+        }
+    }
+}
+
+#[cfg(any(windows, target_os = "macos"))]
+impl KeyProvider for KeychainKeyProvider {
+    fn name(&self) -> &'static str {
+        "keychain"
+    }
+
+    fn key(&self, salt: &str) -> Result<[u8; 32], String> {
+        let master = self.master_key()?;
+        Ok(derive_key(&[&master, salt.as_bytes()]))
+    }
+}
+
+/// SHA-256 over length-prefixed parts, so part boundaries cannot shift
+fn derive_key(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in std::iter::once(KEY_CONTEXT).chain(parts.iter().copied()) {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Remembers the keys of another provider, so each is derived once per process
+pub struct CachedKeyProvider<P> {
+    inner: P,
+    keys: Mutex<HashMap<String, [u8; 32]>>,
+}
+
+impl<P: KeyProvider> CachedKeyProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self { inner, keys: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl<P: KeyProvider> KeyProvider for CachedKeyProvider<P> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn key(&self, salt: &str) -> Result<[u8; 32], String> {
+        let mut keys = self.keys.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(key) = keys.get(salt) {
+            return Ok(*key);
+        }
+        let key = self.inner.key(salt)?;
+        keys.insert(salt.to_string(), key);
+        Ok(key)
+    }
+}
+
+/// Stable id of the machine from the operating system, read once per process
+fn machine_id() -> Option<String> {
+    static ID: OnceLock<Option<String>> = OnceLock::new();
+    ID.get_or_init(|| read_machine_id().filter(|id| !id.is_empty())).clone()
+}
+
+#[cfg(windows)]
+fn read_machine_id() -> Option<String> {
+    use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_WOW64_64KEY};
+    use winreg::RegKey;
+    // The 64-bit view, so a 32-bit build reads the same value
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(r"SOFTWARE\Microsoft\Cryptography", KEY_READ | KEY_WOW64_64KEY)
+        .and_then(|key| key.get_value::<String, _>("MachineGuid"))
+        .map_err(|e| warn!("Failed to read MachineGuid: {}", e))
+        .ok()
+}
+
+#[cfg(target_os = "macos")]
+fn read_machine_id() -> Option<String> {
+    let output = std::process::Command::new("ioreg")
+        .args(["-rd1", "-c", "IOPlatformExpertDevice"])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|l| l.contains("IOPlatformUUID"))
+        .and_then(|l| l.split('"').nth(3))
+        .map(str::to_string)
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn read_machine_id() -> Option<String> {
+    ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(|p| fs::read_to_string(p).ok())
+        .map(|id| id.trim().to_string())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SealedSecret {
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Contents of secrets.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SecretFile {
+    version: u32,
+    provider: String,
+    salt: String,
+    secrets: BTreeMap<String, SealedSecret>,
+}
+
+/// Encrypted secret file
+pub struct SecretStore {
+    path: PathBuf,
+    provider: Arc<dyn KeyProvider>,
+    /// Provider of older files, which are re-encrypted on first read
+    previous: Option<Arc<dyn KeyProvider>>,
+}
+
+impl SecretStore {
+    pub fn new(path: PathBuf, provider: Arc<dyn KeyProvider>) -> Self {
+        Self { path, provider, previous: None }
+    }
+
+    pub fn with_previous(mut self, provider: Arc<dyn KeyProvider>) -> Self {
+        self.previous = Some(provider);
+        self
+    }
+
+    /// Store in the app data directory with the platform's key provider
+    ///
+    /// The provider is shared, so the key is derived once rather than on
+    /// every config load.
+    pub fn open_default() -> Self {
+        static PROVIDER: OnceLock<Arc<dyn KeyProvider>> = OnceLock::new();
+        let path = dirs::data_local_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("EWDesktopAgent")
+            .join("secrets.json");
+
+        #[cfg(any(windows, target_os = "macos"))]
+        {
+            let provider = PROVIDER.get_or_init(|| Arc::new(CachedKeyProvider::new(KeychainKeyProvider)));
+            Self::new(path, provider.clone()).with_previous(Arc::new(MachineKeyProvider))
+        }
+        #[cfg(not(any(windows, target_os = "macos")))]
+        {
+            let provider = PROVIDER.get_or_init(|| Arc::new(CachedKeyProvider::new(MachineKeyProvider)));
+            Self::new(path, provider.clone())
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>, String> {
+        let _guard = file_lock();
+        let Some(file) = self.read()? else {
+            return Ok(None);
+        };
+        let Some(sealed) = file.secrets.get(name) else {
+            return Ok(None);
+        };
+        let cipher = self.cipher(&file)?;
+        open_secret(&cipher, name, sealed).map(Some)
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        let _guard = file_lock();
+        let mut file = match self.read()? {
+            Some(file) => file,
+            None => self.empty_file(),
+        };
+        let cipher = self.cipher(&file)?;
+        file.secrets.insert(name.to_string(), seal_secret(&cipher, name, value)?);
+        self.write(&file)?;
+        info!("Stored secret {}", name);
+        Ok(())
+    }
+
+    /// Remove a secret, returning whether it existed
+    pub fn delete(&self, name: &str) -> Result<bool, String> {
+        let _guard = file_lock();
+        let Some(mut file) = self.read()? else {
+            return Ok(false);
+        };
+        let existed = file.secrets.remove(name).is_some();
+        if existed {
+            self.write(&file)?;
+            info!("Deleted secret {}", name);
+        }
+        Ok(existed)
+    }
+
+    fn empty_file(&self) -> SecretFile {
+        SecretFile {
+            version: FILE_VERSION,
+            provider: self.provider.name().to_string(),
+            salt: uuid::Uuid::new_v4().to_string(),
+            secrets: BTreeMap::new(),
+        }
+    }
+
+    fn cipher(&self, file: &SecretFile) -> Result<Aes256Gcm, String> {
+        if file.provider != self.provider.name() {
+            return Err(format!("密钥文件使用的密钥来源 {} 与当前配置不一致", file.provider));
+        }
+        cipher_for(self.provider.as_ref(), &file.salt)
+    }
+
+    /// Re-encrypt a file of the previous provider with the current one
+    fn rekey(&self, old: SecretFile) -> Result<SecretFile, String> {
+        let Some(previous) = self.previous.as_ref().filter(|p| p.name() == old.provider) else {
+            return Ok(old);
+        };
+        let old_cipher = cipher_for(previous.as_ref(), &old.salt)?;
+        let mut file = self.empty_file();
+        let cipher = self.cipher(&file)?;
+        for (name, sealed) in &old.secrets {
+            let value = open_secret(&old_cipher, name, sealed)?;
+            file.secrets.insert(name.clone(), seal_secret(&cipher, name, &value)?);
+        }
+        self.write(&file)?;
+        info!("Moved {} secrets from the {} key to the {} key", file.secrets.len(), old.provider, file.provider);
+        Ok(file)
+    }
+
+    fn read(&self) -> Result<Option<SecretFile>, String> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let content = fs::read_to_string(&self.path)
+            .map_err(|e| format!("无法读取密钥文件 {}: {}", self.path.display(), e))?;
+        let file: SecretFile = serde_json::from_str(&content)
+            .map_err(|e| format!("密钥文件格式错误 {}: {}", self.path.display(), e))?;
+        if file.version != FILE_VERSION {
+            return Err(format!("不支持的密钥文件版本: {}", file.version));
+        }
+        if file.provider != self.provider.name() {
+            return self.rekey(file).map(Some);
+        }
+        Ok(Some(file))
+    }
+
+    fn write(&self, file: &SecretFile) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(file).map_err(|e| e.to_string())?;
+        // Write then rename so a crash never leaves a truncated file behind
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, content).map_err(|e| e.to_string())?;
+        restrict_permissions(&tmp);
+        fs::rename(&tmp, &self.path).map_err(|e| e.to_string())
+    }
+}
+
+fn cipher_for(provider: &dyn KeyProvider, salt: &str) -> Result<Aes256Gcm, String> {
+    let key = provider.key(salt)?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+}
+
+/// Encrypt a value, binding it to its name
+fn seal_secret(cipher: &Aes256Gcm, name: &str, value: &str) -> Result<SealedSecret, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: value.as_bytes(), aad: name.as_bytes() })
+        .map_err(|e| format!("加密密钥 {} 失败: {}", name, e))?;
+    Ok(SealedSecret {
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+fn open_secret(cipher: &Aes256Gcm, name: &str, sealed: &SealedSecret) -> Result<String, String> {
+    let nonce = BASE64.decode(&sealed.nonce).map_err(|e| e.to_string())?;
+    let ciphertext = BASE64.decode(&sealed.ciphertext).map_err(|e| e.to_string())?;
+    if nonce.len() != 12 {
+        return Err(format!("密钥 {} 已损坏", name));
+    }
+    let plain = cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: name.as_bytes() })
+        .map_err(|_| format!("无法解密密钥 {}，密钥文件可能来自其他电脑或用户", name))?;
+    String::from_utf8(plain).map_err(|e| e.to_string())
+}
+
+/// Serializes read-modify-write cycles on the secret file
+fn file_lock() -> std::sync::MutexGuard<'static, ()> {
+    static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    LOCK.get_or_init(|| Mutex::new(())).lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        warn!("Failed to restrict permissions of {}: {}", path.display(), e);
+    }
+}
+
+// Windows files inherit the ACL of the user's AppData; the keychain key is
+// what keeps other users out there
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) {}
+
+/// Move a plaintext value into the store, leaving it in place if that fails
+fn seal(store: &SecretStore, name: &str, value: &mut Option<String>) -> bool {
+    let Some(secret) = value.take().filter(|v| !v.is_empty()) else {
+        return false;
+    };
+    match store.set(name, &secret) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Keeping {} in plaintext, secret store unavailable: {}", name, e);
+            *value = Some(secret);
+            false
+        }
+    }
+}
+
+fn unseal(store: &SecretStore, name: &str, value: &mut Option<String>) {
+    if value.as_deref().is_some_and(|v| !v.is_empty()) {
+        return;
+    }
+    match store.get(name) {
+        Ok(Some(secret)) => *value = Some(secret),
+        Ok(None) => {}
+        Err(e) => error!("Failed to read secret {}: {}", name, e),
+    }
+}
+
+/// Move plaintext secrets of a config into the store, returning how many were moved
+pub fn seal_config(config: &mut AppConfig, store: &SecretStore) -> usize {
+    let mut api_key = Some(std::mem::take(&mut config.ai.api_key));
+    let mut moved = usize::from(seal(store, AI_API_KEY, &mut api_key));
+    config.ai.api_key = api_key.unwrap_or_default();
+    moved += usize::from(seal(store, ASR_API_KEY, &mut config.voice.asr_api_key));
+    moved += usize::from(seal(store, TTS_API_KEY, &mut config.voice.tts_api_key));
+    moved
+}
+
+/// Fill the secret fields of a config from the store
+pub fn unseal_config(config: &mut AppConfig, store: &SecretStore) {
+    let mut api_key = Some(std::mem::take(&mut config.ai.api_key));
+    unseal(store, AI_API_KEY, &mut api_key);
+    config.ai.api_key = api_key.unwrap_or_default();
+    unseal(store, ASR_API_KEY, &mut config.voice.asr_api_key);
+    unseal(store, TTS_API_KEY, &mut config.voice.tts_api_key);
+}
+
+/// Config with secret fields blanked, for the frontend
+pub fn redacted_config(config: &AppConfig) -> AppConfig {
+    let mut config = config.clone();
+    config.ai.api_key.clear();
+    config.voice.asr_api_key = None;
+    config.voice.tts_api_key = None;
+    config
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if SECRET_NAMES.contains(&name) {
+        Ok(())
+    } else {
+        Err(format!("未知的密钥名称: {}", name))
+    }
+}
+
+/// Tauri commands for the secret store
+
+#[tauri::command]
+pub fn set_secret(auth: tauri::State<AuthState>, name: String, value: String) -> Result<(), String> {
+    auth.authorize("set_secret", None, Permission::Admin)?;
+    check_name(&name)?;
+    SecretStore::open_default().set(&name, &value)
+}
+
+#[tauri::command]
+pub fn delete_secret(auth: tauri::State<AuthState>, name: String) -> Result<bool, String> {
+    auth.authorize("delete_secret", None, Permission::Admin)?;
+    check_name(&name)?;
+    SecretStore::open_default().delete(&name)
+}
+
+#[tauri::command]
+pub fn has_secret(name: String) -> Result<bool, String> {
+    check_name(&name)?;
+    Ok(SecretStore::open_default().get(&name)?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixed key, standing in for the OS credential store
+    struct TestKeys {
+        name: &'static str,
+        key: [u8; 32],
+    }
+
+    fn keys(name: &'static str, byte: u8) -> Arc<dyn KeyProvider> {
+        Arc::new(TestKeys { name, key: [byte; 32] })
+    }
+
+    impl KeyProvider for TestKeys {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn key(&self, _salt: &str) -> Result<[u8; 32], String> {
+            Ok(self.key)
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("secrets-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn secrets_round_trip_encrypted() {
+        let path = temp_path();
+        let store = SecretStore::new(path.clone(), keys("test", 1));
+        store.set(AI_API_KEY, "sk-secret").unwrap();
+        store.set(ASR_API_KEY, "asr-secret").unwrap();
+
+        assert_eq!(store.get(AI_API_KEY).unwrap().as_deref(), Some("sk-secret"));
+        assert_eq!(store.get(TTS_API_KEY).unwrap(), None);
+        assert!(!fs::read_to_string(&path).unwrap().contains("sk-secret"));
+
+        assert!(store.delete(AI_API_KEY).unwrap());
+        assert!(!store.delete(AI_API_KEY).unwrap());
+        assert_eq!(store.get(AI_API_KEY).unwrap(), None);
+        assert_eq!(store.get(ASR_API_KEY).unwrap().as_deref(), Some("asr-secret"));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn wrong_key_cannot_decrypt() {
+        let path = temp_path();
+        SecretStore::new(path.clone(), keys("test", 1)).set(AI_API_KEY, "sk-secret").unwrap();
+
+        let err = SecretStore::new(path.clone(), keys("test", 2)).get(AI_API_KEY).unwrap_err();
+        assert!(err.contains("无法解密"), "{}", err);
+        let err = SecretStore::new(path.clone(), keys("other", 1)).get(AI_API_KEY).unwrap_err();
+        assert!(err.contains("不一致"), "{}", err);
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn secret_moved_to_another_name_is_rejected() {
+        let path = temp_path();
+        let store = SecretStore::new(path.clone(), keys("test", 1));
+        store.set(AI_API_KEY, "sk-secret").unwrap();
+        store.set(TTS_API_KEY, "tts-secret").unwrap();
+
+        // Same key, but the ciphertext is bound to the name it was stored under
+        let mut file: SecretFile = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let sealed = file.secrets[AI_API_KEY].clone();
+        file.secrets.insert(TTS_API_KEY.to_string(), sealed);
+        fs::write(&path, serde_json::to_string(&file).unwrap()).unwrap();
+
+        assert!(store.get(TTS_API_KEY).unwrap_err().contains("无法解密"));
+        assert_eq!(store.get(AI_API_KEY).unwrap().as_deref(), Some("sk-secret"));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn previous_provider_file_is_rekeyed() {
+        let path = temp_path();
+        SecretStore::new(path.clone(), keys("machine", 1)).set(AI_API_KEY, "sk-secret").unwrap();
+
+        let store = SecretStore::new(path.clone(), keys("keychain", 2)).with_previous(keys("machine", 1));
+        assert_eq!(store.get(AI_API_KEY).unwrap().as_deref(), Some("sk-secret"));
+
+        // The file now opens with the new key alone
+        let store = SecretStore::new(path.clone(), keys("keychain", 2));
+        assert_eq!(store.get(AI_API_KEY).unwrap().as_deref(), Some("sk-secret"));
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn seal_config_moves_plaintext_keys_into_the_store() {
+        let path = temp_path();
+        let store = SecretStore::new(path.clone(), keys("test", 1));
+        let mut config = AppConfig::default();
+        config.ai.api_key = "sk-plain".to_string();
+        config.voice.asr_api_key = Some("asr-plain".to_string());
+        config.voice.tts_api_key = Some(String::new());
+
+        assert_eq!(seal_config(&mut config, &store), 2);
+        assert!(config.ai.api_key.is_empty());
+        assert_eq!(config.voice.asr_api_key, None);
+        assert_eq!(seal_config(&mut config, &store), 0);
+
+        unseal_config(&mut config, &store);
+        assert_eq!(config.ai.api_key, "sk-plain");
+        assert_eq!(config.voice.asr_api_key.as_deref(), Some("asr-plain"));
+        assert_eq!(store.get(TTS_API_KEY).unwrap(), None);
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn unavailable_store_keeps_plaintext() {
+        let path = temp_path();
+        SecretStore::new(path.clone(), keys("other", 1)).set(AI_API_KEY, "old").unwrap();
+
+        let mut config = AppConfig::default();
+        config.ai.api_key = "sk-plain".to_string();
+        assert_eq!(seal_config(&mut config, &SecretStore::new(path.clone(), keys("test", 1))), 0);
+        assert_eq!(config.ai.api_key, "sk-plain");
+        fs::remove_file(path).ok();
+    }
+}
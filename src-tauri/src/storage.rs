@@ -1,10 +1,13 @@
 // Storage module - SQLite database management
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, Result, Transaction, TransactionBehavior};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::sync::Mutex;
 use tracing::info;
 
+use crate::auth::AuthState;
+use crate::permissions::Permission;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
@@ -19,6 +22,15 @@ pub struct AssistantSession {
     pub user_id: String,
     pub messages: String,
     pub created_at: String,
+    /// Rolling summary of older messages
+    #[serde(default)]
+    pub summary: Option<String>,
+    /// Number of leading messages covered by `summary`
+    #[serde(default)]
+    pub summary_upto: usize,
+    /// Intent waiting for missing parameters, as JSON
+    #[serde(default)]
+    pub pending_intent: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +51,17 @@ impl Database {
         Ok(Self { conn })
     }
 
+    /// Open the application database, creating the schema if needed
+    pub fn open_default() -> Result<Self, String> {
+        let db_path = get_db_path();
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let db = Self::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
+        db.init_schema().map_err(|e| e.to_string())?;
+        Ok(db)
+    }
+
     pub fn init_schema(&self) -> Result<()> {
         self.conn.execute_batch(
             "
@@ -101,16 +124,136 @@ impl Database {
                 created_at TEXT NOT NULL
             );
 
+            CREATE TABLE IF NOT EXISTS ai_usage (
+                id TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                system TEXT,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                latency_ms INTEGER NOT NULL,
+                success INTEGER NOT NULL,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS ai_answer_cache (
+                key TEXT PRIMARY KEY,
+                user_id TEXT NOT NULL,
+                system TEXT,
+                prompt_version TEXT NOT NULL,
+                answer TEXT NOT NULL,
+                hits INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                last_used_at TEXT NOT NULL
+            );
+
             CREATE INDEX IF NOT EXISTS idx_audit_logs_user ON audit_logs(user_id);
             CREATE INDEX IF NOT EXISTS idx_audit_logs_created ON audit_logs(created_at);
             CREATE INDEX IF NOT EXISTS idx_behavior_logs_user ON behavior_logs(user_id);
             CREATE INDEX IF NOT EXISTS idx_behavior_logs_created ON behavior_logs(created_at);
+            CREATE INDEX IF NOT EXISTS idx_ai_usage_user_created ON ai_usage(user_id, created_at);
+            CREATE INDEX IF NOT EXISTS idx_ai_answer_cache_user ON ai_answer_cache(user_id);
             ",
         )?;
+        self.ensure_column("assistant_sessions", "summary", "TEXT")?;
+        self.ensure_column("assistant_sessions", "summary_upto", "INTEGER NOT NULL DEFAULT 0")?;
+        self.ensure_column("assistant_sessions", "pending_intent", "TEXT")?;
         info!("Database schema initialized");
         Ok(())
     }
 
+    /// Add a column to an existing table if an older schema lacks it
+    fn ensure_column(&self, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(1))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+        if !exists {
+            self.conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+            info!("Added column {}.{}", table, column);
+        }
+        Ok(())
+    }
+
+    /// Insert or update an assistant session
+    pub fn save_session(&self, session: &AssistantSession) -> Result<(), String> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO assistant_sessions (id, user_id, messages, created_at, updated_at, summary, summary_upto, pending_intent)
+             VALUES (?1, ?2, ?3, ?4, datetime('now'), ?5, ?6, ?7)",
+            rusqlite::params![
+                session.id,
+                session.user_id,
+                session.messages,
+                session.created_at,
+                session.summary,
+                session.summary_upto as i64,
+                session.pending_intent,
+            ],
+        ).map_err(|e| e.to_string())?;
+
+        info!("Saved session: {}", session.id);
+        Ok(())
+    }
+
+    /// Append entries to the message list of a session in one transaction
+    ///
+    /// Only the messages column is written, so a concurrent update of the
+    /// summary or pending intent is not undone.
+    pub fn append_session_messages<T: Serialize>(&self, session_id: &str, entries: &[T]) -> Result<(), String> {
+        let tx = Transaction::new_unchecked(&self.conn, TransactionBehavior::Immediate).map_err(|e| e.to_string())?;
+        let stored = tx.query_row(
+            "SELECT messages FROM assistant_sessions WHERE id = ?1",
+            [session_id],
+            |row| row.get::<_, Option<String>>(0),
+        );
+        let stored = match stored {
+            Ok(stored) => stored.unwrap_or_default(),
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(format!("会话不存在: {}", session_id)),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let mut messages: Vec<serde_json::Value> = if stored.trim().is_empty() {
+            Vec::new()
+        } else {
+            serde_json::from_str(&stored).map_err(|e| e.to_string())?
+        };
+        for entry in entries {
+            messages.push(serde_json::to_value(entry).map_err(|e| e.to_string())?);
+        }
+        let messages = serde_json::to_string(&messages).map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "UPDATE assistant_sessions SET messages = ?1, updated_at = datetime('now') WHERE id = ?2",
+            (&messages, session_id),
+        ).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    /// Load an assistant session by id
+    pub fn load_session(&self, session_id: &str) -> Result<Option<AssistantSession>, String> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, user_id, messages, created_at, summary, summary_upto, pending_intent FROM assistant_sessions WHERE id = ?1")
+            .map_err(|e| e.to_string())?;
+
+        let result = stmt.query_row([session_id], |row| {
+            Ok(AssistantSession {
+                id: row.get(0)?,
+                user_id: row.get(1)?,
+                messages: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                created_at: row.get(3)?,
+                summary: row.get(4)?,
+                summary_upto: row.get::<_, i64>(5)? as usize,
+                pending_intent: row.get(6)?,
+            })
+        });
+
+        match result {
+            Ok(session) => Ok(Some(session)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
     /// Log an audit entry
     pub fn log_audit(&self, user_id: Option<&str>, action: &str, details: &str, risk_level: &str) -> Result<(), String> {
         let id = uuid::Uuid::new_v4().to_string();
@@ -168,6 +311,147 @@ impl Database {
 
         Ok(logs)
     }
+
+    /// Record an AI request for usage accounting
+    pub fn record_usage(&self, record: &UsageRecord) -> Result<(), String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let created_at = chrono::Utc::now().to_rfc3339();
+
+        self.conn.execute(
+            "INSERT INTO ai_usage (id, user_id, system, prompt_tokens, completion_tokens, latency_ms, success, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            rusqlite::params![
+                id,
+                record.user_id,
+                record.system,
+                record.prompt_tokens,
+                record.completion_tokens,
+                record.latency_ms as i64,
+                record.success,
+                created_at,
+            ],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Request count and total tokens of a user since an RFC 3339 timestamp
+    pub fn usage_since(&self, user_id: &str, since: &str) -> Result<(u32, u64), String> {
+        self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(prompt_tokens + completion_tokens), 0)
+             FROM ai_usage WHERE user_id = ?1 AND created_at >= ?2",
+            [user_id, since],
+            |row| Ok((row.get::<_, u32>(0)?, row.get::<_, i64>(1)? as u64)),
+        ).map_err(|e| e.to_string())
+    }
+
+    /// Usage per business system since an RFC 3339 timestamp, optionally for one user
+    pub fn usage_by_system(&self, user_id: Option<&str>, since: &str) -> Result<Vec<SystemUsage>, String> {
+        let mut stmt = self.conn.prepare(
+            "SELECT system, COUNT(*), SUM(CASE WHEN success THEN 0 ELSE 1 END),
+                    SUM(prompt_tokens), SUM(completion_tokens), CAST(AVG(latency_ms) AS INTEGER)
+             FROM ai_usage
+             WHERE created_at >= ?1 AND (?2 IS NULL OR user_id = ?2)
+             GROUP BY system ORDER BY COUNT(*) DESC"
+        ).map_err(|e| e.to_string())?;
+
+        let rows = stmt.query_map(rusqlite::params![since, user_id], |row| {
+            Ok(SystemUsage {
+                system: row.get(0)?,
+                requests: row.get(1)?,
+                failures: row.get(2)?,
+                prompt_tokens: row.get::<_, i64>(3)? as u64,
+                completion_tokens: row.get::<_, i64>(4)? as u64,
+                avg_latency_ms: row.get::<_, i64>(5)? as u64,
+            })
+        }).map_err(|e| e.to_string())?;
+
+        rows.collect::<Result<Vec<_>>>().map_err(|e| e.to_string())
+    }
+
+    /// Cached answer stored at or after an RFC 3339 timestamp, counting the hit
+    pub fn cached_answer(&self, key: &str, since: &str) -> Result<Option<String>, String> {
+        let answer = self.conn.query_row(
+            "SELECT answer FROM ai_answer_cache WHERE key = ?1 AND created_at >= ?2",
+            [key, since],
+            |row| row.get::<_, String>(0),
+        );
+        match answer {
+            Ok(answer) => {
+                self.conn.execute(
+                    "UPDATE ai_answer_cache SET hits = hits + 1, last_used_at = ?2 WHERE key = ?1",
+                    [key, &chrono::Utc::now().to_rfc3339()],
+                ).map_err(|e| e.to_string())?;
+                Ok(Some(answer))
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Insert or replace a cached answer
+    pub fn store_answer(&self, entry: &CachedAnswer) -> Result<(), String> {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO ai_answer_cache (key, user_id, system, prompt_version, answer, hits, created_at, last_used_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, ?6)",
+            rusqlite::params![entry.key, entry.user_id, entry.system, entry.prompt_version, entry.answer, now],
+        ).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// Drop answers stored before `before` and all but the `max_entries` most recently used
+    pub fn trim_answer_cache(&self, before: &str, max_entries: u32) -> Result<usize, String> {
+        let expired = self.conn.execute("DELETE FROM ai_answer_cache WHERE created_at < ?1", [before])
+            .map_err(|e| e.to_string())?;
+        let evicted = self.conn.execute(
+            "DELETE FROM ai_answer_cache WHERE key NOT IN (
+                 SELECT key FROM ai_answer_cache ORDER BY last_used_at DESC LIMIT ?1
+             )",
+            [max_entries],
+        ).map_err(|e| e.to_string())?;
+        Ok(expired + evicted)
+    }
+
+    /// Remove cached answers stored by one user, or all of them
+    pub fn clear_answer_cache(&self, user_id: Option<&str>) -> Result<usize, String> {
+        self.conn.execute(
+            "DELETE FROM ai_answer_cache WHERE ?1 IS NULL OR user_id = ?1",
+            [user_id],
+        ).map_err(|e| e.to_string())
+    }
+}
+
+/// Answer kept in the local answer cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedAnswer {
+    pub key: String,
+    /// User whose question first produced the answer
+    pub user_id: String,
+    pub system: Option<String>,
+    pub prompt_version: String,
+    pub answer: String,
+}
+
+/// One AI request as recorded for usage accounting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub user_id: String,
+    pub system: Option<String>,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub latency_ms: u64,
+    pub success: bool,
+}
+
+/// Aggregated AI usage for one business system
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemUsage {
+    pub system: Option<String>,
+    pub requests: u32,
+    pub failures: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub avg_latency_ms: u64,
 }
 
 /// Audit log entry
@@ -220,47 +504,36 @@ pub fn init_database() -> Result<String, String> {
 }
 
 #[tauri::command]
-pub fn save_session(session: AssistantSession) -> Result<(), String> {
+pub fn save_session(auth: tauri::State<AuthState>, session: AssistantSession) -> Result<(), String> {
+    auth.user_for(Some(&session.user_id))?;
     let db_path = get_db_path();
     let db = Database::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
-
-    db.conn.execute(
-        "INSERT OR REPLACE INTO assistant_sessions (id, user_id, messages, created_at, updated_at)
-         VALUES (?1, ?2, ?3, ?4, datetime('now'))",
-        (&session.id, &session.user_id, &session.messages, &session.created_at),
-    ).map_err(|e| e.to_string())?;
-
-    info!("Saved session: {}", session.id);
-    Ok(())
+    // An existing session keeps its owner
+    if let Some(existing) = db.load_session(&session.id)? {
+        auth.user_for(Some(&existing.user_id))?;
+    }
+    db.save_session(&session)
 }
 
 #[tauri::command]
-pub fn load_session(session_id: String) -> Result<Option<AssistantSession>, String> {
+pub fn load_session(auth: tauri::State<AuthState>, session_id: String) -> Result<Option<AssistantSession>, String> {
+    auth.require_user()?;
     let db_path = get_db_path();
     let db = Database::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
-
-    let mut stmt = db.conn
-        .prepare("SELECT id, user_id, messages, created_at FROM assistant_sessions WHERE id = ?1")
-        .map_err(|e| e.to_string())?;
-
-    let result = stmt.query_row([&session_id], |row| {
-        Ok(AssistantSession {
-            id: row.get(0)?,
-            user_id: row.get(1)?,
-            messages: row.get(2)?,
-            created_at: row.get(3)?,
-        })
-    });
-
-    match result {
-        Ok(session) => Ok(Some(session)),
-        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-        Err(e) => Err(e.to_string()),
+    let session = db.load_session(&session_id)?;
+    if let Some(session) = &session {
+        auth.user_for(Some(&session.user_id))?;
     }
+    Ok(session)
 }
 
 #[tauri::command]
-pub fn save_user_preferences(user_id: String, preferences: UserPreferences) -> Result<(), String> {
+pub fn save_user_preferences(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+    preferences: UserPreferences,
+) -> Result<(), String> {
+    let user_id = auth.user_for(user_id.as_deref())?.id;
     let db_path = get_db_path();
     let db = Database::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
 
@@ -277,7 +550,11 @@ pub fn save_user_preferences(user_id: String, preferences: UserPreferences) -> R
 }
 
 #[tauri::command]
-pub fn load_user_preferences(user_id: String) -> Result<Option<UserPreferences>, String> {
+pub fn load_user_preferences(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+) -> Result<Option<UserPreferences>, String> {
+    let user_id = auth.user_for(user_id.as_deref())?.id;
     let db_path = get_db_path();
     let db = Database::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
 
@@ -303,14 +580,43 @@ pub fn load_user_preferences(user_id: String) -> Result<Option<UserPreferences>,
 /// Tauri commands for audit logging
 
 #[tauri::command]
-pub fn log_audit(user_id: Option<String>, action: String, details: String, risk_level: String) -> Result<(), String> {
+pub fn log_audit(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+    action: String,
+    details: String,
+    risk_level: String,
+) -> Result<(), String> {
+    // Events before login, e.g. failed attempts, are logged without a user
+    let user_id = match auth.current_user() {
+        Some(_) => Some(auth.user_for(user_id.as_deref())?.id),
+        None => None,
+    };
     let db_path = get_db_path();
     let db = Database::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
     db.log_audit(user_id.as_deref(), &action, &details, &risk_level)
 }
 
 #[tauri::command]
-pub fn query_audit_logs(user_id: Option<String>, limit: i32) -> Result<Vec<AuditLogEntry>, String> {
+pub fn query_audit_logs(
+    auth: tauri::State<AuthState>,
+    user_id: Option<String>,
+    all_users: Option<bool>,
+    limit: i32,
+) -> Result<Vec<AuditLogEntry>, String> {
+    let user = auth.require_user()?;
+    // Other users' entries are for administrators only
+    let user_id = match user_id {
+        _ if all_users.unwrap_or(false) => {
+            auth.authorize("query_audit_logs", None, Permission::Admin)?;
+            None
+        }
+        Some(id) if id != user.id => {
+            auth.authorize("query_audit_logs", None, Permission::Admin)?;
+            Some(id)
+        }
+        _ => Some(user.id),
+    };
     let db_path = get_db_path();
     let db = Database::new(db_path.to_str().unwrap()).map_err(|e| e.to_string())?;
     db.query_audit_logs(user_id.as_deref(), limit)
@@ -5,9 +5,11 @@ use serde::{Deserialize, Serialize};
 use tracing::info;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VoiceConfig {
     pub wakeword_enabled: bool,
     pub asr_provider: String,
+    /// Kept in the secret store, see `secrets::ASR_API_KEY`
     pub asr_api_key: Option<String>,
     pub tts_provider: String,
     pub tts_api_key: Option<String>,
@@ -36,7 +38,10 @@ pub struct VoiceService {
 
 impl VoiceService {
     pub fn new(config: VoiceConfig) -> Self {
-        info!("Initializing voice service with config: {:?}", config);
+        info!(
+            "Initializing voice service (ASR: {}, TTS: {}, wake word: {})",
+            config.asr_provider, config.tts_provider, config.wakeword_enabled
+        );
         Self { config }
     }
 